@@ -0,0 +1,86 @@
+use crate::copy::stats::CopyStats;
+use std::task::{Context, Poll};
+use tokio::task::coop::{self, RestoreOnPending};
+
+/// How much work a zero-copy loop does in a single poll before it yields to other tasks.
+///
+/// When both sides are fast, splice and sendfile loops could move gigabytes without ever
+/// returning `Pending` and monopolise a worker thread. Every iteration of the loop also consumes
+/// tokio's cooperative budget, so the copy yields when either budget is spent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CopyBudget {
+    bytes: usize,
+    syscalls: usize,
+}
+
+impl Default for CopyBudget {
+    fn default() -> Self {
+        Self {
+            bytes: 2 * 1024 * 1024,
+            syscalls: 128,
+        }
+    }
+}
+
+impl CopyBudget {
+    /// Yield after `bytes` bytes were moved or `syscalls` syscalls were made in a single poll.
+    pub fn new(bytes: usize, syscalls: usize) -> Self {
+        Self {
+            bytes: bytes.max(1),
+            syscalls: syscalls.max(1),
+        }
+    }
+
+    /// Only yield when tokio's cooperative budget is spent.
+    pub fn unlimited() -> Self {
+        Self {
+            bytes: usize::MAX,
+            syscalls: usize::MAX,
+        }
+    }
+}
+
+/// The work done by a copy since the start of the current poll.
+pub(crate) struct PollBudget {
+    budget: CopyBudget,
+    bytes: usize,
+    syscalls: usize,
+}
+
+impl PollBudget {
+    /// Starts a poll of a copy with the given progress so far.
+    pub(crate) fn start(budget: CopyBudget, stats: &CopyStats) -> Self {
+        Self {
+            budget,
+            bytes: stats.bytes,
+            syscalls: stats.syscalls(),
+        }
+    }
+
+    /// Whether the copy has used up either budget since the start of the poll.
+    pub(crate) fn is_spent(&self, stats: &CopyStats) -> bool {
+        stats.bytes - self.bytes >= self.budget.bytes
+            || stats.syscalls() - self.syscalls >= self.budget.syscalls
+    }
+
+    /// Returns `Pending` and schedules the task again once the copy has used up either budget.
+    /// Call [`RestoreOnPending::made_progress`] on the result once the iteration made progress.
+    pub(crate) fn poll_proceed(
+        &self,
+        cx: &mut Context<'_>,
+        stats: &mut CopyStats,
+    ) -> Poll<RestoreOnPending> {
+        if self.is_spent(stats) {
+            stats.yields += 1;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        match coop::poll_proceed(cx) {
+            Poll::Ready(coop) => Poll::Ready(coop),
+            Poll::Pending => {
+                stats.yields += 1;
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::copy::capture::CaptureTap;
+use crate::copy::error::{CopyError, CopyPhase, CopyResult, IoResultExt};
+use crate::copy::stats::{CopyMechanism, CopyStats};
+use std::io::{Error, ErrorKind};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const BUF_SIZE: usize = 8 * 1024;
+
+/// Copy data from a reader to a writer through a userspace buffer.
+/// Copies `length` bytes if given, otherwise until EOF. The writer is flushed afterwards.
+/// Every chunk read is recorded by the capture tap, if any.
+/// This function is only available on non-linux platforms.
+pub async fn copy<R, W>(
+    r: &mut R,
+    w: &mut W,
+    length: Option<usize>,
+    mut capture: Option<CaptureTap>,
+) -> CopyResult<CopyStats>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = vec![0; BUF_SIZE];
+    let mut stats = CopyStats::new(CopyMechanism::Buffered);
+    loop {
+        let limit = length.map_or(BUF_SIZE, |length| BUF_SIZE.min(length - stats.bytes));
+        if limit == 0 {
+            break;
+        }
+        stats.read_calls += 1;
+        let n = r
+            .read(&mut buf[..limit])
+            .await
+            .in_phase(CopyPhase::Read, stats.bytes)?;
+        if n == 0 {
+            break;
+        }
+        if let Some(capture) = &mut capture {
+            capture
+                .record(&buf[..n])
+                .in_phase(CopyPhase::Capture, stats.bytes)?;
+        }
+        let mut pos = 0;
+        while pos < n {
+            stats.write_calls += 1;
+            let written = w
+                .write(&buf[pos..n])
+                .await
+                .in_phase(CopyPhase::Write, stats.bytes)?;
+            if written == 0 {
+                return Err(CopyError::new(
+                    CopyPhase::Write,
+                    stats.bytes,
+                    Error::new(ErrorKind::WriteZero, "write zero byte into writer"),
+                ));
+            }
+            pos += written;
+            stats.bytes += written;
+        }
+    }
+    w.flush().await.in_phase(CopyPhase::Flush, stats.bytes)?;
+    Ok(stats.finish())
+}
@@ -0,0 +1,394 @@
+use crate::copy::capture::{CaptureDirection, CaptureTap};
+use crate::copy::endpoint::ZeroCopySink;
+use crate::copy::error::{CopyError, CopyPhase, CopyResult, IoResultExt};
+use crate::copy::file;
+use crate::copy::options::CopyOptions;
+use crate::copy::pipe::{Pipe, SpliceConfig, SplicePipe};
+use crate::copy::stats::{CopyMechanism, CopyStats};
+use essentials::debug;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::poll_fn;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+/// A cache of small responses, e.g. a health page or a popular static asset, held in pipes.
+///
+/// Every entry is a pipe filled with the content once. Sending an entry duplicates the cached
+/// pipe into another pipe with tee and splices that one into the sink, so the content never
+/// re-enters userspace. The cached pipe itself is never drained. The other pipe is leased from
+/// the [`PipePool`](crate::PipePool) of the copy options if there is one, otherwise it is created per send.
+///
+/// Content is copied into the pipes, files are read and written into them rather than spliced,
+/// so an entry keeps its content even if the file is rewritten in place afterwards.
+/// Files are read on tokio's blocking thread pool, so cold pages do not block the runtime.
+///
+/// An entry holds at most one pipe of the configured size, content which does not fit
+/// is not cached and [`send_file`](PipeCache::send_file) sends it with sendfile instead.
+/// The cache owns at most `max_entries` pipes and at most `max_memory` bytes of pipe buffers,
+/// every pipe uses two file descriptors.
+///
+/// The cache is cheap to clone and can be shared across tasks.
+/// Entries can be replaced or invalidated at any time, sends in progress finish with the old content.
+#[derive(Clone)]
+pub struct PipeCache {
+    inner: Arc<Mutex<CacheInner>>,
+}
+
+struct CacheInner {
+    entries: HashMap<String, Arc<CachedPipe>>,
+    pipe_size: usize,
+    max_entries: usize,
+    hits: u64,
+    misses: u64,
+}
+
+/// A pipe holding the content of an entry.
+struct CachedPipe {
+    pipe: Pipe,
+    /// The size of the pipe granted by the kernel.
+    size: usize,
+    /// The number of bytes in the pipe.
+    len: usize,
+}
+
+impl fmt::Debug for PipeCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.lock();
+        f.debug_struct("PipeCache")
+            .field("entries", &inner.entries.len())
+            .field("pipe_size", &inner.pipe_size)
+            .field("max_entries", &inner.max_entries)
+            .finish()
+    }
+}
+
+impl PipeCache {
+    /// Create an empty cache of 64 KiB pipes owning at most `max_entries` pipes and `max_memory` bytes of pipe buffers.
+    pub fn new(max_entries: usize, max_memory: usize) -> Self {
+        Self::with_config(max_entries, max_memory, SpliceConfig::default())
+    }
+
+    /// Create an empty cache of pipes sized according to the config,
+    /// the pipe size is the largest content an entry can hold.
+    pub fn with_config(max_entries: usize, max_memory: usize, config: SpliceConfig) -> Self {
+        // the kernel rounds pipe sizes up to a power of two pages
+        let pipe_size = config.get_pipe_size().next_power_of_two();
+        Self {
+            inner: Arc::new(Mutex::new(CacheInner {
+                entries: HashMap::new(),
+                pipe_size,
+                max_entries: max_entries.min(max_memory / pipe_size),
+                hits: 0,
+                misses: 0,
+            })),
+        }
+    }
+
+    /// The largest content an entry can hold.
+    pub fn capacity(&self) -> usize {
+        self.lock().pipe_size
+    }
+
+    /// The maximum number of entries.
+    pub fn max_entries(&self) -> usize {
+        self.lock().max_entries
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Whether the cache has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.lock().entries.is_empty()
+    }
+
+    /// Whether there is an entry for the key.
+    pub fn contains(&self, key: &str) -> bool {
+        self.lock().entries.contains_key(key)
+    }
+
+    /// How many times a send found its entry.
+    pub fn hits(&self) -> u64 {
+        self.lock().hits
+    }
+
+    /// How many times a send did not find its entry.
+    pub fn misses(&self) -> u64 {
+        self.lock().misses
+    }
+
+    /// Cache the content under the key, replacing the entry for the key if there is one.
+    /// Returns `false` if the content is bigger than the pipe size or the cache is full.
+    pub fn insert(&self, key: impl Into<String>, content: &[u8]) -> Result<bool> {
+        let Some(mut pipe) = self.new_pipe(content.len())? else {
+            return Ok(false);
+        };
+        pipe.write(content)?;
+        Ok(self.store(key.into(), pipe).is_some())
+    }
+
+    /// Cache the content of a file under the key, replacing the entry for the key if there is one.
+    /// The file is read from the start, its file position is left untouched.
+    /// Returns `false` if the file is bigger than the pipe size or the cache is full.
+    pub async fn insert_file(&self, key: impl Into<String>, file: &mut File) -> Result<bool> {
+        Ok(self.fill(key.into(), file).await?.is_some())
+    }
+
+    /// Remove the entry for the key, returns whether there was one.
+    pub fn invalidate(&self, key: &str) -> bool {
+        self.lock().entries.remove(key).is_some()
+    }
+
+    /// Remove all entries.
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    /// Send the entry for the key to a sink, the sink is flushed but not shut down.
+    /// Returns `None` if there is no entry for the key.
+    pub async fn send<W: ZeroCopySink>(
+        &self,
+        key: &str,
+        w: &mut W,
+    ) -> CopyResult<Option<CopyStats>> {
+        self.send_with(key, w, &CopyOptions::default()).await
+    }
+
+    /// Same as [`send`](PipeCache::send), but uses the given options,
+    /// the content is recorded if [`CopyOptions::capture`] is set.
+    pub async fn send_with<W: ZeroCopySink>(
+        &self,
+        key: &str,
+        w: &mut W,
+        options: &CopyOptions,
+    ) -> CopyResult<Option<CopyStats>> {
+        match self.get(key) {
+            Some(cached) => Ok(Some(cached.send(w, options).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Send the entry for the key to a sink, the sink is flushed but not shut down.
+    /// If there is no entry, the whole file is cached under the key first.
+    /// Files which do not fit into the cache are sent with sendfile, like [`copy_file`](crate::copy_file)
+    /// but from the start of the file. The file position is left untouched.
+    pub async fn send_file<W: ZeroCopySink>(
+        &self,
+        key: &str,
+        file: &mut File,
+        w: &mut W,
+    ) -> CopyResult<CopyStats> {
+        self.send_file_with(key, file, w, &CopyOptions::default())
+            .await
+    }
+
+    /// Same as [`send_file`](PipeCache::send_file), but uses the given options,
+    /// for the sendfile copy of files which do not fit as well as for cached content.
+    pub async fn send_file_with<W: ZeroCopySink>(
+        &self,
+        key: &str,
+        file: &mut File,
+        w: &mut W,
+        options: &CopyOptions,
+    ) -> CopyResult<CopyStats> {
+        if let Some(cached) = self.get(key) {
+            return cached.send(w, options).await;
+        }
+        match self
+            .fill(key.to_string(), file)
+            .await
+            .in_phase(CopyPhase::File, 0)?
+        {
+            Some(cached) => cached.send(w, options).await,
+            None => {
+                debug!("file does not fit into the pipe cache, sending it with sendfile");
+                let len = file.metadata().await.in_phase(CopyPhase::File, 0)?.len();
+                file::copy_range(file, w, 0, len as usize, options).await
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Arc<CachedPipe>> {
+        let mut inner = self.lock();
+        match inner.entries.get(key).cloned() {
+            Some(cached) => {
+                inner.hits += 1;
+                Some(cached)
+            }
+            None => {
+                inner.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Fills a pipe with the whole file and caches it.
+    async fn fill(&self, key: String, file: &File) -> Result<Option<Arc<CachedPipe>>> {
+        let len = file.metadata().await?.len() as usize;
+        let Some(mut pipe) = self.new_pipe(len)? else {
+            return Ok(None);
+        };
+        // reading cold pages blocks, so the file is read on the blocking thread pool with its own fd.
+        // Splicing it would leave page cache pages in the pipe, which change with the file.
+        let file = file.try_clone().await?.into_std().await;
+        let pipe = tokio::task::spawn_blocking(move || {
+            let mut content = vec![0; len];
+            file.read_exact_at(&mut content, 0)?;
+            pipe.write(&content)?;
+            Ok::<_, Error>(pipe)
+        })
+        .await
+        .map_err(Error::other)??;
+        Ok(self.store(key, pipe))
+    }
+
+    /// Creates an empty pipe for `len` bytes,
+    /// `None` if they do not fit into a pipe or the cache is full.
+    fn new_pipe(&self, len: usize) -> Result<Option<CachedPipe>> {
+        let pipe_size = {
+            let inner = self.lock();
+            if len > inner.pipe_size || inner.max_entries == 0 {
+                return Ok(None);
+            }
+            inner.pipe_size
+        };
+        let (pipe, size) = Pipe::with_size(pipe_size)?;
+        if len > size {
+            // the user's pipe buffer limit is reached
+            return Ok(None);
+        }
+        Ok(Some(CachedPipe { pipe, size, len: 0 }))
+    }
+
+    /// Caches a filled pipe, `None` if the cache is full.
+    fn store(&self, key: String, pipe: CachedPipe) -> Option<Arc<CachedPipe>> {
+        let mut inner = self.lock();
+        if !inner.entries.contains_key(&key) && inner.entries.len() >= inner.max_entries {
+            return None;
+        }
+        let pipe = Arc::new(pipe);
+        inner.entries.insert(key, pipe.clone());
+        Some(pipe)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheInner> {
+        // the cache is never left in an inconsistent state, so a poisoned lock is still usable
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl CachedPipe {
+    /// Copies the content into the empty pipe, which has room for it.
+    fn write(&mut self, content: &[u8]) -> Result<()> {
+        while self.len < content.len() {
+            match unsafe {
+                libc::write(
+                    self.pipe.write_fd(),
+                    content[self.len..].as_ptr().cast(),
+                    content.len() - self.len,
+                )
+            } {
+                -1 => return Err(Error::last_os_error()),
+                n => self.len += n as usize,
+            }
+        }
+        Ok(())
+    }
+
+    /// Duplicates the content into another pipe and splices it into the sink.
+    async fn send<W: ZeroCopySink>(
+        &self,
+        w: &mut W,
+        options: &CopyOptions,
+    ) -> CopyResult<CopyStats> {
+        debug!("sending cached pipe using tee and splice");
+        let mut stats = CopyStats::new(CopyMechanism::Splice);
+        if self.len == 0 {
+            return Ok(stats.finish());
+        }
+        if let Some(mut capture) = CaptureTap::new(options, CaptureDirection::Forward) {
+            capture
+                .record_pipe(self.pipe.read_fd(), self.len)
+                .in_phase(CopyPhase::Capture, 0)?;
+        }
+        let mut pipe = SplicePipe::new(options.get_pipe_pool(), options.get_splice_config())
+            .await
+            .in_phase(CopyPhase::Pipe, 0)?;
+        if pipe.size() < self.len {
+            // a pooled pipe is restored to its size when it is returned
+            pipe.set_size(self.size).in_phase(CopyPhase::Pipe, 0)?;
+        }
+        stats.pipe_size = Some(pipe.size());
+        // the pipe is empty and has room for the content, so it takes the whole content at once
+        match unsafe {
+            libc::tee(
+                self.pipe.read_fd(),
+                pipe.write_fd(),
+                self.len,
+                libc::SPLICE_F_NONBLOCK,
+            )
+        } {
+            -1 => {
+                return Err(CopyError::new(
+                    CopyPhase::SpliceIn,
+                    0,
+                    Error::last_os_error(),
+                ))
+            }
+            n if n as usize != self.len => {
+                return Err(CopyError::new(
+                    CopyPhase::SpliceIn,
+                    0,
+                    Error::other("cached content does not fit into the pipe"),
+                ))
+            }
+            _ => {}
+        }
+        while stats.bytes < self.len {
+            poll_fn(|cx| w.poll_write_ready_n(cx))
+                .await
+                .in_phase(CopyPhase::SpliceOut, stats.bytes)?;
+            stats.splice_calls += 1;
+            match w
+                .try_write_io_n(|| try_splice(pipe.read_fd(), w.sink_fd(), self.len - stats.bytes))
+            {
+                Ok(0) => {
+                    return Err(CopyError::new(
+                        CopyPhase::SpliceOut,
+                        stats.bytes,
+                        Error::new(ErrorKind::WriteZero, "write zero byte into writer"),
+                    ))
+                }
+                Ok(n) => stats.bytes += n,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => stats.would_block += 1,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(CopyError::new(CopyPhase::SpliceOut, stats.bytes, err)),
+            }
+        }
+        w.flush().await.in_phase(CopyPhase::Flush, stats.bytes)?;
+        Ok(stats.finish())
+    }
+}
+
+fn try_splice(fd_in: RawFd, fd_out: RawFd, len: usize) -> Result<usize> {
+    match unsafe {
+        libc::splice(
+            fd_in,
+            std::ptr::null_mut(),
+            fd_out,
+            std::ptr::null_mut(),
+            len,
+            libc::SPLICE_F_NONBLOCK,
+        )
+    } {
+        -1 => Err(Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
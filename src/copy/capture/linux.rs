@@ -0,0 +1,75 @@
+use super::{CaptureFile, CaptureSink, CaptureTap};
+use crate::copy::pipe::Pipe;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+impl CaptureTap {
+    /// Records the `len` bytes at the head of a pipe without consuming them.
+    /// They are duplicated into the tap's own pipe with tee and spliced into the capture file,
+    /// or read from it to be wrapped in a packet.
+    pub(crate) fn record_pipe(&mut self, fd: RawFd, len: usize) -> Result<()> {
+        let pipe = match &mut self.pipe {
+            Some(pipe) => pipe,
+            pipe => pipe.insert(Pipe::new()?),
+        };
+        // the tap's pipe is empty, so it takes the whole chunk once it is as big as the source pipe
+        if pipe.size()? < len {
+            pipe.set_size(len)?;
+        }
+        let teed = match unsafe { libc::tee(fd, pipe.write_fd(), len, libc::SPLICE_F_NONBLOCK) } {
+            -1 => return Err(Error::last_os_error()),
+            n => n as usize,
+        };
+        let read_fd = pipe.read_fd();
+        match &*self.capture.inner {
+            CaptureSink::Frames(file) => {
+                CaptureFile::write_frame(file, self.direction, teed, |file, offset| {
+                    splice_into(read_fd, file, offset, teed)
+                })
+            }
+            CaptureSink::Pcapng(writer) => writer.record(self.direction, &read(read_fd, teed)?),
+        }
+    }
+}
+
+/// Splices `len` bytes from a pipe into a file at `offset`.
+fn splice_into(fd: RawFd, file: &std::fs::File, offset: u64, len: usize) -> Result<()> {
+    let mut off_out = offset as libc::loff_t;
+    let mut done = 0;
+    while done < len {
+        match unsafe {
+            libc::splice(
+                fd,
+                std::ptr::null_mut(),
+                file.as_raw_fd(),
+                &mut off_out,
+                len - done,
+                0,
+            )
+        } {
+            -1 => return Err(Error::last_os_error()),
+            0 => {
+                return Err(Error::new(
+                    ErrorKind::WriteZero,
+                    "write zero byte into capture file",
+                ))
+            }
+            n => done += n as usize,
+        }
+    }
+    Ok(())
+}
+
+/// Reads `len` bytes from a pipe which holds at least that many.
+fn read(fd: RawFd, len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    let mut done = 0;
+    while done < len {
+        match unsafe { libc::read(fd, buf[done..].as_mut_ptr().cast(), len - done) } {
+            -1 => return Err(Error::last_os_error()),
+            0 => return Err(Error::from(ErrorKind::UnexpectedEof)),
+            n => done += n as usize,
+        }
+    }
+    Ok(buf)
+}
@@ -0,0 +1,350 @@
+#[cfg(target_os = "linux")]
+mod linux;
+mod pcapng;
+
+use crate::copy::options::CopyOptions;
+use chrono::{DateTime, Utc};
+use pcapng::PcapngWriter;
+use serde::Serialize;
+use std::fmt;
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, BufReader};
+use tokio::net::TcpStream;
+
+/// the first bytes of every capture file
+const MAGIC: &[u8; 8] = b"IOCAP001";
+
+/// direction, timestamp and payload length of a frame
+const FRAME_HEADER_LEN: usize = 1 + 8 + 4;
+
+/// The direction of the captured data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureDirection {
+    /// From the source to the sink of a copy, or from `a` to `b` of a bidirectional copy.
+    Forward,
+    /// From `b` to `a` of a bidirectional copy.
+    Backward,
+}
+
+impl CaptureDirection {
+    fn to_byte(self) -> u8 {
+        match self {
+            CaptureDirection::Forward => 0,
+            CaptureDirection::Backward => 1,
+        }
+    }
+
+    fn reverse(self) -> Self {
+        match self {
+            CaptureDirection::Forward => CaptureDirection::Backward,
+            CaptureDirection::Backward => CaptureDirection::Forward,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self> {
+        match byte {
+            0 => Ok(CaptureDirection::Forward),
+            1 => Ok(CaptureDirection::Backward),
+            byte => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("invalid capture direction {byte}"),
+            )),
+        }
+    }
+}
+
+/// A chunk of data read from one side of a captured copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureFrame {
+    /// The direction the data was copied in.
+    pub direction: CaptureDirection,
+    /// When the data was read from the source, with microsecond precision.
+    pub timestamp: DateTime<Utc>,
+    /// The data.
+    pub payload: Vec<u8>,
+}
+
+/// A capture recording the data of copies made with [`CopyOptions::capture`](crate::CopyOptions::capture).
+///
+/// A capture created with [`Capture::create`] or [`Capture::new`] appends every chunk read from
+/// a source as a frame made of the direction (1 byte), the timestamp in microseconds since
+/// the unix epoch (8 bytes), the payload length (4 bytes), all big endian, and the payload.
+/// The file starts with the magic bytes `IOCAP001`. Use [`CaptureReader`] to parse it back.
+/// A capture created with [`Capture::pcapng`] writes a pcapng file instead.
+///
+/// On linux platforms the payload is duplicated from the splice pipe with tee,
+/// so forwarding stays zero-copy, and spliced into the capture file.
+/// Frames are written synchronously while the copy is polled, so a slow capture file blocks
+/// the runtime worker running the copy, and every other task on it, for the duration of the write.
+/// Pcapng packets are handed to a separate task instead. The capture is meant for debugging.
+/// A capture can be cloned and shared by many copies, their frames are interleaved.
+#[derive(Clone)]
+pub struct Capture {
+    inner: Arc<CaptureSink>,
+    /// whether unidirectional copies are recorded as backward
+    reversed: bool,
+}
+
+enum CaptureSink {
+    Frames(Mutex<CaptureFile>),
+    Pcapng(PcapngWriter),
+}
+
+struct CaptureFile {
+    file: std::fs::File,
+    /// where the next frame is written, splice cannot write to files opened in append mode
+    offset: u64,
+}
+
+impl Capture {
+    /// Create a capture file, truncating it if it exists.
+    pub async fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(File::create(path).await?).await
+    }
+
+    /// Use an open file as a capture file, it is truncated first.
+    pub async fn new(file: File) -> Result<Self> {
+        file.set_len(0).await?;
+        let file = file.into_std().await;
+        file.write_all_at(MAGIC, 0)?;
+        Ok(Self {
+            inner: Arc::new(CaptureSink::Frames(Mutex::new(CaptureFile {
+                file,
+                offset: MAGIC.len() as u64,
+            }))),
+            reversed: false,
+        })
+    }
+
+    /// Create a pcapng capture of a tcp session between `client` and `server`, which can be
+    /// opened in Wireshark. The payloads are wrapped in synthesised Ethernet, IP and TCP headers,
+    /// the data of the [`Forward`](CaptureDirection::Forward) direction is sent by the client.
+    /// The packets are written to `writer` by a background task, so this function must be called
+    /// from a tokio runtime. Call [`Capture::close`] to end the session and flush the writer.
+    pub fn pcapng<W>(writer: W, client: SocketAddr, server: SocketAddr) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        Self {
+            inner: Arc::new(CaptureSink::Pcapng(PcapngWriter::new(
+                writer, client, server,
+            ))),
+            reversed: false,
+        }
+    }
+
+    /// Create a pcapng capture file, truncating it if it exists, see [`Capture::pcapng`].
+    pub async fn create_pcapng(
+        path: impl AsRef<Path>,
+        client: SocketAddr,
+        server: SocketAddr,
+    ) -> Result<Self> {
+        Ok(Self::pcapng(File::create(path).await?, client, server))
+    }
+
+    /// Create a pcapng capture of a proxied tcp session, see [`Capture::pcapng`].
+    /// The addresses are the peers of the connections, `client` is the connection accepted
+    /// from the client and `upstream` the connection to the server.
+    pub fn pcapng_tcp<W>(writer: W, client: &TcpStream, upstream: &TcpStream) -> Result<Self>
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        Ok(Self::pcapng(
+            writer,
+            client.peer_addr()?,
+            upstream.peer_addr()?,
+        ))
+    }
+
+    /// Create a pcapng capture file of a proxied tcp session, truncating it if it exists,
+    /// see [`Capture::pcapng_tcp`].
+    pub async fn create_pcapng_tcp(
+        path: impl AsRef<Path>,
+        client: &TcpStream,
+        upstream: &TcpStream,
+    ) -> Result<Self> {
+        Self::pcapng_tcp(File::create(path).await?, client, upstream)
+    }
+
+    /// A handle on the same capture with swapped directions, it records unidirectional copies
+    /// and the data sent by `a` in bidirectional copies as [`Backward`](CaptureDirection::Backward).
+    /// A proxy copying each direction with [`copy_tcp_with`](crate::copy_tcp_with)
+    /// uses it for the responses of the server.
+    pub fn backward(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            reversed: !self.reversed,
+        }
+    }
+
+    /// The number of bytes written to the capture so far.
+    pub fn len(&self) -> u64 {
+        match &*self.inner {
+            CaptureSink::Frames(file) => lock(file).offset,
+            CaptureSink::Pcapng(writer) => writer.len(),
+        }
+    }
+
+    /// Whether no data has been captured yet.
+    pub fn is_empty(&self) -> bool {
+        match &*self.inner {
+            CaptureSink::Frames(file) => lock(file).offset == MAGIC.len() as u64,
+            CaptureSink::Pcapng(writer) => writer.is_empty(),
+        }
+    }
+
+    /// Finish the capture, waiting until everything is written.
+    /// A pcapng capture ends the tcp session, flushes and shuts its writer down and reports
+    /// the first error of the writer. Data captured afterwards fails the copy.
+    pub async fn close(&self) -> Result<()> {
+        match &*self.inner {
+            CaptureSink::Frames(_) => Ok(()),
+            CaptureSink::Pcapng(writer) => writer.close().await,
+        }
+    }
+}
+
+impl CaptureFile {
+    /// Writes a frame of `length` bytes, `payload` writes them to the file at the given offset.
+    fn write_frame<F>(
+        file: &Mutex<Self>,
+        direction: CaptureDirection,
+        length: usize,
+        payload: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&std::fs::File, u64) -> Result<()>,
+    {
+        let length = u32::try_from(length)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "capture frame too long"))?;
+        let mut header = [0; FRAME_HEADER_LEN];
+        header[0] = direction.to_byte();
+        header[1..9].copy_from_slice(&Utc::now().timestamp_micros().to_be_bytes());
+        header[9..].copy_from_slice(&length.to_be_bytes());
+        let mut file = lock(file);
+        file.file.write_all_at(&header, file.offset)?;
+        // the frame is only committed once the payload is written completely
+        payload(&file.file, file.offset + FRAME_HEADER_LEN as u64)?;
+        file.offset += (FRAME_HEADER_LEN + length as usize) as u64;
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl fmt::Debug for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Capture")
+            .field("len", &self.len())
+            .field("reversed", &self.reversed)
+            .finish()
+    }
+}
+
+/// Records the data of one direction of a copy.
+///
+/// Frames of a [`Capture::create`] file are written with blocking file writes, spliced on linux,
+/// on the task running the copy, which does not make progress while the frame is written.
+/// Pcapng payloads are copied into a channel and written by the writer's task.
+pub(crate) struct CaptureTap {
+    capture: Capture,
+    direction: CaptureDirection,
+    #[cfg(target_os = "linux")]
+    pipe: Option<crate::copy::pipe::Pipe>,
+}
+
+impl CaptureTap {
+    /// Creates a tap if the options enable capturing.
+    pub(crate) fn new(options: &CopyOptions, direction: CaptureDirection) -> Option<Self> {
+        options.get_capture().map(|capture| Self {
+            capture: capture.clone(),
+            direction: match capture.reversed {
+                true => direction.reverse(),
+                false => direction,
+            },
+            #[cfg(target_os = "linux")]
+            pipe: None,
+        })
+    }
+
+    /// Records data read into a userspace buffer.
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn record(&mut self, buf: &[u8]) -> Result<()> {
+        match &*self.capture.inner {
+            CaptureSink::Frames(file) => {
+                CaptureFile::write_frame(file, self.direction, buf.len(), |file, offset| {
+                    file.write_all_at(buf, offset)
+                })
+            }
+            CaptureSink::Pcapng(writer) => writer.record(self.direction, buf),
+        }
+    }
+}
+
+/// Parses a capture file written by [`Capture::create`] or [`Capture::new`].
+pub struct CaptureReader<R> {
+    reader: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    /// Open a capture file.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path).await?)).await
+    }
+}
+
+impl<R: AsyncRead + Unpin> CaptureReader<R> {
+    /// Read a capture from a reader, failing if it does not start with the magic bytes.
+    pub async fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic).await?;
+        if &magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a capture file"));
+        }
+        Ok(Self { reader })
+    }
+
+    /// Read the next frame, returns `None` at the end of the capture.
+    pub async fn next_frame(&mut self) -> Result<Option<CaptureFrame>> {
+        let mut header = [0; FRAME_HEADER_LEN];
+        let n = self.reader.read(&mut header).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        self.reader.read_exact(&mut header[n..]).await?;
+        let direction = CaptureDirection::from_byte(header[0])?;
+        let micros = i64::from_be_bytes(header[1..9].try_into().unwrap());
+        let timestamp = DateTime::from_timestamp_micros(micros)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid capture timestamp"))?;
+        let length = u32::from_be_bytes(header[9..].try_into().unwrap());
+        let mut payload = vec![0; length as usize];
+        self.reader.read_exact(&mut payload).await?;
+        Ok(Some(CaptureFrame {
+            direction,
+            timestamp,
+            payload,
+        }))
+    }
+
+    /// Read all remaining frames.
+    pub async fn frames(mut self) -> Result<Vec<CaptureFrame>> {
+        let mut frames = Vec::new();
+        while let Some(frame) = self.next_frame().await? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    /// Consumes the reader, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
@@ -0,0 +1,306 @@
+use super::{lock, CaptureDirection};
+use chrono::Utc;
+use essentials::debug;
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const LINKTYPE_ETHERNET: u16 = 1;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const CLIENT_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x01];
+const SERVER_MAC: [u8; 6] = [0x02, 0, 0, 0, 0, 0x02];
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// the largest payload of a synthesised segment, so the IP length fits into 16 bits
+const MAX_SEGMENT: usize = 65_000;
+
+enum Command {
+    Write(Vec<u8>),
+    Close,
+}
+
+/// The synthesised tcp session.
+struct Session {
+    client_seq: u32,
+    server_seq: u32,
+    /// the number of bytes sent to the writer task
+    len: u64,
+    /// the length of the file header and the handshake
+    start: u64,
+    closed: bool,
+}
+
+/// Writes the payloads of a tcp session as pcapng packets with synthesised headers.
+///
+/// The blocks are built while the copy is polled and written by a background task,
+/// the queue between them is unbounded.
+pub(crate) struct PcapngWriter {
+    client: SocketAddr,
+    server: SocketAddr,
+    tx: mpsc::UnboundedSender<Command>,
+    session: Mutex<Session>,
+    task: Mutex<Option<JoinHandle<Result<()>>>>,
+}
+
+impl PcapngWriter {
+    pub(crate) fn new<W>(writer: W, client: SocketAddr, server: SocketAddr) -> Self
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let writer = Self {
+            client,
+            server,
+            tx,
+            session: Mutex::new(Session {
+                client_seq: 0,
+                server_seq: 0,
+                len: 0,
+                start: 0,
+                closed: false,
+            }),
+            task: Mutex::new(Some(tokio::spawn(write_blocks(writer, rx)))),
+        };
+        let mut session = lock(&writer.session);
+        writer.send(&mut session, section_header());
+        writer.send(&mut session, interface_description());
+        // the handshake lets Wireshark follow the stream from its start
+        writer.segment(&mut session, true, TCP_SYN, &[]);
+        session.client_seq += 1;
+        writer.segment(&mut session, false, TCP_SYN | TCP_ACK, &[]);
+        session.server_seq += 1;
+        writer.segment(&mut session, true, TCP_ACK, &[]);
+        session.start = session.len;
+        drop(session);
+        writer
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        lock(&self.session).len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        let session = lock(&self.session);
+        session.len == session.start
+    }
+
+    /// Records the payload sent in the given direction.
+    pub(crate) fn record(&self, direction: CaptureDirection, payload: &[u8]) -> Result<()> {
+        let mut session = lock(&self.session);
+        // the writer task stops at the first error, which is reported by close
+        if session.closed || self.tx.is_closed() {
+            return Err(Error::new(
+                ErrorKind::BrokenPipe,
+                "pcapng capture is closed",
+            ));
+        }
+        let from_client = direction == CaptureDirection::Forward;
+        for chunk in payload.chunks(MAX_SEGMENT) {
+            self.segment(&mut session, from_client, TCP_PSH | TCP_ACK, chunk);
+            let seq = match from_client {
+                true => &mut session.client_seq,
+                false => &mut session.server_seq,
+            };
+            *seq = seq.wrapping_add(chunk.len() as u32);
+        }
+        Ok(())
+    }
+
+    /// Ends the session with a FIN from both sides and waits for the writer task.
+    pub(crate) async fn close(&self) -> Result<()> {
+        {
+            let mut session = lock(&self.session);
+            if !session.closed {
+                session.closed = true;
+                self.segment(&mut session, true, TCP_FIN | TCP_ACK, &[]);
+                session.client_seq += 1;
+                self.segment(&mut session, false, TCP_FIN | TCP_ACK, &[]);
+                session.server_seq += 1;
+                self.segment(&mut session, true, TCP_ACK, &[]);
+            }
+        }
+        let _ = self.tx.send(Command::Close);
+        let task = lock(&self.task).take();
+        match task {
+            Some(task) => task.await.map_err(Error::other)?,
+            None => Ok(()),
+        }
+    }
+
+    fn segment(&self, session: &mut Session, from_client: bool, flags: u8, payload: &[u8]) {
+        let (seq, ack) = match from_client {
+            true => (session.client_seq, session.server_seq),
+            false => (session.server_seq, session.client_seq),
+        };
+        // a fresh session has not acknowledged anything yet
+        let ack = if flags & TCP_ACK == 0 { 0 } else { ack };
+        let mut packet = Vec::with_capacity(14 + 40 + 20 + payload.len());
+        self.packet(&mut packet, from_client, flags, seq, ack, payload);
+        self.send(session, enhanced_packet(&packet));
+    }
+
+    fn send(&self, session: &mut Session, block: Vec<u8>) {
+        session.len += block.len() as u64;
+        let _ = self.tx.send(Command::Write(block));
+    }
+
+    /// Builds an Ethernet frame with an IP packet carrying a tcp segment.
+    fn packet(
+        &self,
+        packet: &mut Vec<u8>,
+        from_client: bool,
+        flags: u8,
+        seq: u32,
+        ack: u32,
+        payload: &[u8],
+    ) {
+        let (src, dst, src_mac, dst_mac) = match from_client {
+            true => (self.client, self.server, CLIENT_MAC, SERVER_MAC),
+            false => (self.server, self.client, SERVER_MAC, CLIENT_MAC),
+        };
+        let tcp_len = 20 + payload.len();
+        packet.extend_from_slice(&dst_mac);
+        packet.extend_from_slice(&src_mac);
+        let mut pseudo = Vec::with_capacity(40);
+        match (src.ip(), dst.ip()) {
+            (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+                packet.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+                let start = packet.len();
+                packet.extend_from_slice(&[0x45, 0]);
+                packet.extend_from_slice(&((20 + tcp_len) as u16).to_be_bytes());
+                // identification, don't fragment, ttl, protocol and an empty checksum
+                packet.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+                packet.extend_from_slice(&src_ip.octets());
+                packet.extend_from_slice(&dst_ip.octets());
+                let checksum = checksum(&[&packet[start..]]);
+                packet[start + 10..start + 12].copy_from_slice(&checksum.to_be_bytes());
+                pseudo.extend_from_slice(&src_ip.octets());
+                pseudo.extend_from_slice(&dst_ip.octets());
+                pseudo.extend_from_slice(&[0, 6]);
+                pseudo.extend_from_slice(&(tcp_len as u16).to_be_bytes());
+            }
+            // a session between families is recorded as IPv6 with mapped addresses
+            (src_ip, dst_ip) => {
+                let (src_ip, dst_ip) = (to_ipv6(src_ip), to_ipv6(dst_ip));
+                packet.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+                packet.extend_from_slice(&[0x60, 0, 0, 0]);
+                packet.extend_from_slice(&(tcp_len as u16).to_be_bytes());
+                // next header and hop limit
+                packet.extend_from_slice(&[6, 64]);
+                packet.extend_from_slice(&src_ip.octets());
+                packet.extend_from_slice(&dst_ip.octets());
+                pseudo.extend_from_slice(&src_ip.octets());
+                pseudo.extend_from_slice(&dst_ip.octets());
+                pseudo.extend_from_slice(&(tcp_len as u32).to_be_bytes());
+                pseudo.extend_from_slice(&[0, 0, 0, 6]);
+            }
+        }
+        let start = packet.len();
+        packet.extend_from_slice(&src.port().to_be_bytes());
+        packet.extend_from_slice(&dst.port().to_be_bytes());
+        packet.extend_from_slice(&seq.to_be_bytes());
+        packet.extend_from_slice(&ack.to_be_bytes());
+        // data offset, flags, window, an empty checksum and the urgent pointer
+        packet.extend_from_slice(&[5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        let checksum = checksum(&[&pseudo, &packet[start..], payload]);
+        packet[start + 16..start + 18].copy_from_slice(&checksum.to_be_bytes());
+        packet.extend_from_slice(payload);
+    }
+}
+
+fn to_ipv6(ip: IpAddr) -> std::net::Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// The internet checksum of the concatenated parts, every part but the last has an even length.
+fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for part in parts {
+        let mut words = part.chunks_exact(2);
+        for word in &mut words {
+            sum += u16::from_be_bytes([word[0], word[1]]) as u32;
+        }
+        if let [byte] = words.remainder() {
+            sum += (*byte as u32) << 8;
+        }
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Wraps a block body with its type and total length, padded to 32 bits.
+fn block(kind: u32, body: &[u8]) -> Vec<u8> {
+    let padding = (4 - body.len() % 4) % 4;
+    let len = (12 + body.len() + padding) as u32;
+    let mut block = Vec::with_capacity(len as usize);
+    block.extend_from_slice(&kind.to_le_bytes());
+    block.extend_from_slice(&len.to_le_bytes());
+    block.extend_from_slice(body);
+    block.resize(block.len() + padding, 0);
+    block.extend_from_slice(&len.to_le_bytes());
+    block
+}
+
+fn section_header() -> Vec<u8> {
+    let mut body = Vec::with_capacity(16);
+    body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    // version 1.0 and an unknown section length
+    body.extend_from_slice(&1u16.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    body.extend_from_slice(&(-1i64).to_le_bytes());
+    block(SECTION_HEADER_BLOCK, &body)
+}
+
+fn interface_description() -> Vec<u8> {
+    let mut body = Vec::with_capacity(8);
+    body.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    body.extend_from_slice(&0u16.to_le_bytes());
+    // no snapshot length limit, timestamps have the default microsecond resolution
+    body.extend_from_slice(&0u32.to_le_bytes());
+    block(INTERFACE_DESCRIPTION_BLOCK, &body)
+}
+
+fn enhanced_packet(packet: &[u8]) -> Vec<u8> {
+    let timestamp = Utc::now().timestamp_micros() as u64;
+    let mut body = Vec::with_capacity(20 + packet.len());
+    body.extend_from_slice(&0u32.to_le_bytes());
+    body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+    body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+    body.extend_from_slice(packet);
+    block(ENHANCED_PACKET_BLOCK, &body)
+}
+
+/// Writes the blocks until the capture is closed, the writer is shut down afterwards.
+async fn write_blocks<W>(mut writer: W, mut rx: mpsc::UnboundedReceiver<Command>) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(Command::Write(block)) = rx.recv().await {
+        if let Err(err) = writer.write_all(&block).await {
+            debug!("pcapng capture failed: {err}");
+            return Err(err);
+        }
+    }
+    writer.shutdown().await
+}
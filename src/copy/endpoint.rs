@@ -0,0 +1,208 @@
+use std::io::Result;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::task::{Context, Poll};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncWrite, Interest},
+    net::{
+        tcp::{
+            OwnedReadHalf as TcpRead, OwnedWriteHalf as TcpWrite, ReadHalf as TcpReadRef,
+            WriteHalf as TcpWriteRef,
+        },
+        unix::{
+            pipe::{Receiver, Sender},
+            OwnedReadHalf as UnixRead, OwnedWriteHalf as UnixWrite, ReadHalf as UnixReadRef,
+            WriteHalf as UnixWriteRef,
+        },
+        TcpStream, UnixStream,
+    },
+};
+
+/// A file descriptor backed source that data can be moved out of without copying it into userspace.
+///
+/// This trait is implemented for `TcpStream`, `UnixStream`, their read halves,
+/// tokio pipe receivers and files.
+/// Implement it for your own fd-backed types to use them with [`copy`](crate::copy).
+pub trait ZeroCopySource: AsyncRead + Unpin {
+    /// The file descriptor data is read from.
+    fn source_fd(&self) -> RawFd;
+
+    /// Polls for read readiness.
+    fn poll_read_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>>;
+
+    /// Runs a read operation on the file descriptor.
+    /// If it fails with `WouldBlock`, the read readiness must be cleared.
+    fn try_read_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R>;
+
+    /// Whether the file descriptor refers to a regular file, which can be sent with sendfile.
+    fn is_file(&self) -> bool {
+        false
+    }
+}
+
+/// A file descriptor backed sink that data can be moved into without copying it from userspace.
+///
+/// This trait is implemented for `TcpStream`, `UnixStream`, their write halves,
+/// tokio pipe senders and files.
+/// Implement it for your own fd-backed types to use them with [`copy`](crate::copy).
+pub trait ZeroCopySink: AsyncWrite + Unpin {
+    /// The file descriptor data is written to.
+    fn sink_fd(&self) -> RawFd;
+
+    /// Polls for write readiness.
+    fn poll_write_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>>;
+
+    /// Runs a write operation on the file descriptor.
+    /// If it fails with `WouldBlock`, the write readiness must be cleared.
+    fn try_write_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R>;
+}
+
+macro_rules! impl_zero_copy_for_stream {
+    ($stream: ty) => {
+        impl ZeroCopySource for $stream {
+            #[inline]
+            fn source_fd(&self) -> RawFd {
+                self.as_raw_fd()
+            }
+            #[inline]
+            fn poll_read_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+                self.poll_read_ready(cx)
+            }
+            #[inline]
+            fn try_read_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+                self.try_io(Interest::READABLE, f)
+            }
+        }
+
+        impl ZeroCopySink for $stream {
+            #[inline]
+            fn sink_fd(&self) -> RawFd {
+                self.as_raw_fd()
+            }
+            #[inline]
+            fn poll_write_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+                self.poll_write_ready(cx)
+            }
+            #[inline]
+            fn try_write_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+                self.try_io(Interest::WRITABLE, f)
+            }
+        }
+    };
+}
+impl_zero_copy_for_stream!(TcpStream);
+impl_zero_copy_for_stream!(UnixStream);
+
+macro_rules! impl_zero_copy_source_for_half {
+    ($half: ty, $stream: ty) => {
+        impl ZeroCopySource for $half {
+            #[inline]
+            fn source_fd(&self) -> RawFd {
+                AsRef::<$stream>::as_ref(self).source_fd()
+            }
+            #[inline]
+            fn poll_read_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+                AsRef::<$stream>::as_ref(self).poll_read_ready_n(cx)
+            }
+            #[inline]
+            fn try_read_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+                AsRef::<$stream>::as_ref(self).try_read_io_n(f)
+            }
+        }
+    };
+}
+impl_zero_copy_source_for_half!(TcpRead, TcpStream);
+impl_zero_copy_source_for_half!(TcpReadRef<'_>, TcpStream);
+impl_zero_copy_source_for_half!(UnixRead, UnixStream);
+impl_zero_copy_source_for_half!(UnixReadRef<'_>, UnixStream);
+
+macro_rules! impl_zero_copy_sink_for_half {
+    ($half: ty, $stream: ty) => {
+        impl ZeroCopySink for $half {
+            #[inline]
+            fn sink_fd(&self) -> RawFd {
+                AsRef::<$stream>::as_ref(self).sink_fd()
+            }
+            #[inline]
+            fn poll_write_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+                AsRef::<$stream>::as_ref(self).poll_write_ready_n(cx)
+            }
+            #[inline]
+            fn try_write_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+                AsRef::<$stream>::as_ref(self).try_write_io_n(f)
+            }
+        }
+    };
+}
+impl_zero_copy_sink_for_half!(TcpWrite, TcpStream);
+impl_zero_copy_sink_for_half!(TcpWriteRef<'_>, TcpStream);
+impl_zero_copy_sink_for_half!(UnixWrite, UnixStream);
+impl_zero_copy_sink_for_half!(UnixWriteRef<'_>, UnixStream);
+
+impl ZeroCopySource for Receiver {
+    #[inline]
+    fn source_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+    #[inline]
+    fn poll_read_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.poll_read_ready(cx)
+    }
+    #[inline]
+    fn try_read_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        self.try_io(f)
+    }
+}
+
+impl ZeroCopySink for Sender {
+    #[inline]
+    fn sink_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+    #[inline]
+    fn poll_write_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.poll_write_ready(cx)
+    }
+    #[inline]
+    fn try_write_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        self.try_io(f)
+    }
+}
+
+/// Regular files are always ready, reads and writes use and advance the file position.
+/// The file must not have any buffered or in-flight operations, e.g. seek or flush it first.
+impl ZeroCopySource for File {
+    #[inline]
+    fn source_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+    #[inline]
+    fn poll_read_ready_n(&self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    #[inline]
+    fn try_read_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        f()
+    }
+    #[inline]
+    fn is_file(&self) -> bool {
+        true
+    }
+}
+
+/// Regular files are always ready, writes use and advance the file position.
+/// The file must not have any buffered or in-flight operations, e.g. seek or flush it first.
+impl ZeroCopySink for File {
+    #[inline]
+    fn sink_fd(&self) -> RawFd {
+        self.as_raw_fd()
+    }
+    #[inline]
+    fn poll_write_ready_n(&self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+    #[inline]
+    fn try_write_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        f()
+    }
+}
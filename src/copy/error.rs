@@ -0,0 +1,142 @@
+use std::{error::Error, fmt, io};
+
+/// A specialized `Result` type for copy operations.
+pub type CopyResult<T> = Result<T, CopyError>;
+
+/// The phase of a copy in which an error occurred.
+///
+/// New copy mechanisms add new phases, so matching on it needs a wildcard arm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CopyPhase {
+    /// Creating the pipe used by splice.
+    Pipe,
+    /// Splicing from the source into the pipe.
+    SpliceIn,
+    /// Writing the prefix into the pipe.
+    Prefix,
+    /// Splicing from the pipe into the sink.
+    SpliceOut,
+    /// Sending a file with sendfile.
+    SendFile,
+    /// Copying between files with copy_file_range.
+    CopyFileRange,
+    /// Reading from the source in a buffered copy.
+    Read,
+    /// Writing to the sink in a buffered copy.
+    Write,
+    /// Creating a temporary file or querying the size or position of the source file.
+    File,
+    /// Preallocating the blocks of the destination file.
+    Allocate,
+    /// Flushing the sink.
+    Flush,
+    /// Flushing the destination file to disk.
+    Sync,
+    /// Shutting down the sink.
+    Shutdown,
+    /// Writing the copied data to the capture file.
+    Capture,
+}
+
+impl fmt::Display for CopyPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CopyPhase::Pipe => "pipe",
+            CopyPhase::SpliceIn => "splice-in",
+            CopyPhase::Prefix => "prefix",
+            CopyPhase::SpliceOut => "splice-out",
+            CopyPhase::SendFile => "sendfile",
+            CopyPhase::CopyFileRange => "copy-file-range",
+            CopyPhase::Read => "read",
+            CopyPhase::Write => "write",
+            CopyPhase::File => "file",
+            CopyPhase::Allocate => "fallocate",
+            CopyPhase::Flush => "flush",
+            CopyPhase::Sync => "sync",
+            CopyPhase::Shutdown => "shutdown",
+            CopyPhase::Capture => "capture",
+        })
+    }
+}
+
+/// An error that occurred while copying data.
+///
+/// Besides the underlying `io::Error`, it records the phase that failed and
+/// how many bytes had already been transferred to the sink,
+/// which allows resuming the copy.
+#[derive(Debug)]
+pub struct CopyError {
+    phase: CopyPhase,
+    transferred: usize,
+    source: io::Error,
+}
+
+impl CopyError {
+    pub(crate) fn new(phase: CopyPhase, transferred: usize, source: io::Error) -> Self {
+        Self {
+            phase,
+            transferred,
+            source,
+        }
+    }
+
+    /// The phase of the copy that failed.
+    pub fn phase(&self) -> CopyPhase {
+        self.phase
+    }
+
+    /// The number of bytes transferred to the sink before the error occurred.
+    pub fn transferred(&self) -> usize {
+        self.transferred
+    }
+
+    /// The underlying io error.
+    pub fn io_error(&self) -> &io::Error {
+        &self.source
+    }
+
+    /// The kind of the underlying io error.
+    pub fn kind(&self) -> io::ErrorKind {
+        self.source.kind()
+    }
+
+    /// Consumes the error, returning the underlying io error.
+    pub fn into_io_error(self) -> io::Error {
+        self.source
+    }
+}
+
+impl fmt::Display for CopyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} failed after {} bytes: {}",
+            self.phase, self.transferred, self.source
+        )
+    }
+}
+
+impl Error for CopyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl From<CopyError> for io::Error {
+    fn from(err: CopyError) -> Self {
+        io::Error::new(err.kind(), err)
+    }
+}
+
+pub(crate) trait IoResultExt<T> {
+    /// Attaches the copy phase and progress to an io error.
+    fn in_phase(self, phase: CopyPhase, transferred: usize) -> Result<T, CopyError>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    #[inline]
+    fn in_phase(self, phase: CopyPhase, transferred: usize) -> Result<T, CopyError> {
+        self.map_err(|err| CopyError::new(phase, transferred, err))
+    }
+}
@@ -0,0 +1,204 @@
+use crate::copy::stats::CopyStats;
+use std::future::Future;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::sync::Semaphore;
+
+/// the number of bytes probed at once, sendfile does not send past a probed window
+const COLD_WINDOW: usize = 2 * 1024 * 1024;
+
+/// What a sendfile copy does with file data that is not in the page cache.
+///
+/// sendfile runs on the runtime thread, when the data has to be read from disk first,
+/// it blocks the whole worker and every task scheduled on it.
+/// Unless the policy is [`Block`](ColdPagePolicy::Block), the copy probes the residency
+/// of every page of a window of the file with `mincore` before sending it,
+/// and sendfile only sends the part of the window found in the page cache.
+#[derive(Debug, Clone)]
+pub enum ColdPagePolicy {
+    /// Send the data anyway, blocking the worker until it is read from disk.
+    Block,
+    /// Prefetch cold windows with `readahead` and wait for them on tokio's blocking thread pool, then send them.
+    /// If a semaphore is given, a permit is held during every prefetch,
+    /// which bounds the number of concurrent prefetches across all copies sharing it.
+    Readahead(Option<Arc<Semaphore>>),
+}
+
+impl Default for ColdPagePolicy {
+    fn default() -> Self {
+        ColdPagePolicy::Readahead(None)
+    }
+}
+
+/// Tracks which part of a file has been probed and the prefetch of a cold window.
+pub(crate) struct ColdPages {
+    policy: ColdPagePolicy,
+    /// The file offset up to which the data is known to be cached.
+    warm_until: usize,
+    prefetch: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    /// Whether the window at the file position has just been prefetched.
+    prefetched: bool,
+}
+
+impl ColdPages {
+    pub(crate) fn new(policy: ColdPagePolicy) -> Self {
+        Self {
+            policy,
+            warm_until: 0,
+            prefetch: None,
+            prefetched: false,
+        }
+    }
+
+    /// Whether the file position has to be known to probe the file.
+    pub(crate) fn is_enabled(&self) -> bool {
+        !matches!(self.policy, ColdPagePolicy::Block)
+    }
+
+    /// Waits until the window starting at `position` is cached.
+    /// Cold windows are prefetched and counted in `stats.cold_chunks`.
+    pub(crate) fn poll_warm(
+        &mut self,
+        cx: &mut Context<'_>,
+        fd: RawFd,
+        position: usize,
+        remaining: usize,
+        stats: &mut CopyStats,
+    ) -> Poll<()> {
+        loop {
+            if let Some(prefetch) = self.prefetch.as_mut() {
+                ready!(prefetch.as_mut().poll(cx));
+                self.prefetch = None;
+                self.prefetched = true;
+            }
+            let ColdPagePolicy::Readahead(limit) = &self.policy else {
+                return Poll::Ready(());
+            };
+            if position < self.warm_until || remaining == 0 {
+                return Poll::Ready(());
+            }
+            let length = remaining.min(COLD_WINDOW);
+            let Some(cached) = cached_prefix(fd, position, length) else {
+                // the file cannot be probed, stop probing
+                self.policy = ColdPagePolicy::Block;
+                return Poll::Ready(());
+            };
+            if cached > 0 {
+                // only the cached part is sent, the rest of the window is probed again afterwards
+                self.warm_until = position + cached;
+                self.prefetched = false;
+                return Poll::Ready(());
+            }
+            if std::mem::take(&mut self.prefetched) {
+                // pages evicted again right after their prefetch are sent anyway, so the copy progresses
+                self.warm_until = position + length;
+                return Poll::Ready(());
+            }
+            // the copy may be dropped while prefetching, so the blocking task uses its own fd
+            let Some(fd) = dup(fd) else {
+                return Poll::Ready(());
+            };
+            stats.cold_chunks += 1;
+            let limit = limit.clone();
+            self.prefetch = Some(Box::pin(async move {
+                let _permit = match limit {
+                    Some(limit) => limit.acquire_owned().await.ok(),
+                    None => None,
+                };
+                // a failed prefetch only means sendfile blocks as it would without probing
+                let _ = tokio::task::spawn_blocking(move || readahead(&fd, position, length)).await;
+            }));
+        }
+    }
+
+    /// The number of bytes starting at `position` which can be sent without probing again.
+    pub(crate) fn limit(&self, position: usize) -> usize {
+        if self.is_enabled() {
+            self.warm_until.saturating_sub(position)
+        } else {
+            usize::MAX
+        }
+    }
+}
+
+/// The number of bytes starting at `offset`, up to `length`, which are in the page cache.
+/// The range is mapped without being touched and `mincore` reports which of its pages are resident.
+/// Returns `None` if the file cannot be mapped.
+fn cached_prefix(fd: RawFd, offset: usize, length: usize) -> Option<usize> {
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstat(fd, stat.as_mut_ptr()) } == -1 {
+        return None;
+    }
+    let size = unsafe { stat.assume_init() }.st_size as usize;
+    // there is nothing to read past the end of the file, sendfile does not block on it
+    if offset + length > size {
+        return match size.checked_sub(offset) {
+            Some(length) if length > 0 => cached_prefix(fd, offset, length),
+            _ => Some(length),
+        };
+    }
+    let page = page_size();
+    let start = offset - offset % page;
+    let len = offset + length - start;
+    let addr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            fd,
+            start as libc::off_t,
+        )
+    };
+    if addr == libc::MAP_FAILED {
+        return None;
+    }
+    let mut pages = vec![0u8; len.div_ceil(page)];
+    // the mapping is never read, so probing it does not fault any page in
+    let result = unsafe { libc::mincore(addr, len, pages.as_mut_ptr()) };
+    unsafe { libc::munmap(addr, len) };
+    if result == -1 {
+        return None;
+    }
+    let resident = pages.iter().take_while(|page| *page & 1 == 1).count();
+    Some((start + resident * page).saturating_sub(offset).min(length))
+}
+
+fn page_size() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        -1 => 4096,
+        size => size as usize,
+    }
+}
+
+fn dup(fd: RawFd) -> Option<OwnedFd> {
+    match unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) } {
+        -1 => None,
+        fd => Some(unsafe { OwnedFd::from_raw_fd(fd) }),
+    }
+}
+
+/// Reads the range into the page cache, blocking until it is done.
+/// readahead only starts reading the range, so it is read once more to wait for the reads.
+fn readahead(fd: &OwnedFd, offset: usize, length: usize) {
+    let fd = fd.as_raw_fd();
+    unsafe { libc::readahead(fd, offset as _, length) };
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut done = 0;
+    while done < length {
+        match unsafe {
+            libc::pread(
+                fd,
+                buf.as_mut_ptr().cast(),
+                buf.len().min(length - done),
+                (offset + done) as libc::off_t,
+            )
+        } {
+            // the end of the file or an error, sendfile runs into it as well
+            n if n <= 0 => return,
+            n => done += n as usize,
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use super::copy_range;
+use crate::copy::{
+    endpoint::ZeroCopySink,
+    error::{CopyError, CopyPhase, CopyResult, IoResultExt},
+    options::CopyOptions,
+    stats::{CopyMechanism, CopyStats},
+};
+use essentials::debug;
+#[cfg(target_os = "linux")]
+use std::io::Result;
+use std::io::{Error, ErrorKind, IoSlice};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
+use tokio::{fs::File, io::AsyncWriteExt};
+
+/// Holds back partial segments of a tcp sink until it is dropped,
+/// so the header, the body and the trailer leave in full segments.
+#[cfg(target_os = "linux")]
+struct Cork(RawFd);
+
+#[cfg(target_os = "linux")]
+impl Cork {
+    /// Corks the socket, `None` if it is not a tcp socket.
+    fn new(fd: RawFd) -> Option<Self> {
+        match set_cork(fd, true) {
+            Ok(()) => Some(Self(fd)),
+            Err(err) => {
+                debug!("sending without TCP_CORK: {}", err);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Cork {
+    fn drop(&mut self) {
+        // uncorking pushes out the last partial segment
+        let _ = set_cork(self.0, false);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_cork(fd: RawFd, cork: bool) -> Result<()> {
+    let value = libc::c_int::from(cork);
+    match unsafe {
+        libc::setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_CORK,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    } {
+        -1 => Err(Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+/// Sends a header, a byte range of a file and an optional trailer to a sink.
+/// The file position is left untouched.
+/// On linux platforms a tcp sink is corked while the header is written with sendmsg,
+/// the body is sent with sendfile and the trailer is written, other platforms use buffered writes.
+pub async fn send_with_header<'a, W: ZeroCopySink>(
+    w: &'a mut W,
+    header: &[IoSlice<'_>],
+    file: &'a mut File,
+    offset: u64,
+    length: usize,
+    trailer: Option<&[u8]>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    #[cfg(target_os = "linux")]
+    let _cork = Cork::new(w.sink_fd());
+    debug!("sending header, file range and trailer");
+    let mut head = CopyStats::new(CopyMechanism::SendFile);
+    write_vectored(w, header, true, &mut head).await?;
+    let body = copy_range(file, w, offset, length, options)
+        .await
+        .map_err(|err| {
+            CopyError::new(
+                err.phase(),
+                head.bytes + err.transferred(),
+                err.into_io_error(),
+            )
+        })?;
+    let mut stats = CopyStats {
+        bytes: head.bytes + body.bytes,
+        write_calls: head.write_calls + body.write_calls,
+        would_block: head.would_block + body.would_block,
+        started_at: head.started_at,
+        ..body
+    };
+    if let Some(trailer) = trailer {
+        write_vectored(w, &[IoSlice::new(trailer)], false, &mut stats).await?;
+    }
+    w.flush().await.in_phase(CopyPhase::Flush, stats.bytes)?;
+    Ok(stats.finish())
+}
+
+/// Writes all buffers to the sink, adding the written bytes and write calls to the stats.
+/// With `more` set, the data is held back for the data sent next.
+async fn write_vectored<W: ZeroCopySink>(
+    w: &mut W,
+    bufs: &[IoSlice<'_>],
+    more: bool,
+    stats: &mut CopyStats,
+) -> CopyResult<()> {
+    let mut bufs = bufs.to_vec();
+    let mut bufs = bufs.as_mut_slice();
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match write_some(w, bufs, more, stats).await {
+            Ok(0) => {
+                return Err(CopyError::new(
+                    CopyPhase::Write,
+                    stats.bytes,
+                    Error::new(ErrorKind::WriteZero, "write zero byte into sink"),
+                ))
+            }
+            Ok(n) => {
+                stats.bytes += n;
+                IoSlice::advance_slices(&mut bufs, n);
+            }
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(CopyError::new(CopyPhase::Write, stats.bytes, err)),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+async fn write_some<W: ZeroCopySink>(
+    w: &mut W,
+    bufs: &[IoSlice<'_>],
+    more: bool,
+    stats: &mut CopyStats,
+) -> Result<usize> {
+    loop {
+        std::future::poll_fn(|cx| w.poll_write_ready_n(cx)).await?;
+        stats.write_calls += 1;
+        match w.try_write_io_n(|| send_vectored(w.sink_fd(), bufs, more)) {
+            Err(err) if err.kind() == ErrorKind::WouldBlock => stats.would_block += 1,
+            result => return result,
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+async fn write_some<W: ZeroCopySink>(
+    w: &mut W,
+    bufs: &[IoSlice<'_>],
+    _more: bool,
+    stats: &mut CopyStats,
+) -> std::io::Result<usize> {
+    stats.write_calls += 1;
+    w.write_vectored(bufs).await
+}
+
+/// Writes the buffers to a socket with sendmsg, or with writev if the sink is not a socket.
+#[cfg(target_os = "linux")]
+fn send_vectored(fd: RawFd, bufs: &[IoSlice<'_>], more: bool) -> Result<usize> {
+    let iovlen = bufs.len().min(libc::UIO_MAXIOV as usize);
+    // IoSlice is guaranteed to be ABI compatible with iovec
+    let iov = bufs.as_ptr().cast::<libc::iovec>();
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = iov.cast_mut();
+    msg.msg_iovlen = iovlen as _;
+    let flags = if more {
+        libc::MSG_NOSIGNAL | libc::MSG_MORE
+    } else {
+        libc::MSG_NOSIGNAL
+    };
+    let n = match unsafe { libc::sendmsg(fd, &msg, flags) } {
+        -1 if Error::last_os_error().raw_os_error() == Some(libc::ENOTSOCK) => unsafe {
+            libc::writev(fd, iov, iovlen as libc::c_int)
+        },
+        n => n,
+    };
+    match n {
+        -1 => Err(Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
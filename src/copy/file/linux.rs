@@ -1,40 +1,89 @@
+use super::cold::ColdPages;
+use crate::copy::budget::{CopyBudget, PollBudget};
+use crate::copy::endpoint::{ZeroCopySink, ZeroCopySource};
+use crate::copy::error::{CopyError, CopyPhase, CopyResult, IoResultExt};
+use crate::copy::options::CopyOptions;
+use crate::copy::stats::{CopyMechanism, CopyStats};
+#[cfg(feature = "io-uring")]
+use crate::copy::uring;
 use essentials::debug;
 use libc::off_t;
 use std::future::Future;
-use std::io::Result;
+use std::io::{Result, SeekFrom};
 use std::os::{fd::RawFd, unix::prelude::AsRawFd};
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::task::{ready, Context, Poll};
 use tokio::{
     fs::File,
-    io::{self, AsyncReadExt},
-    net::tcp::OwnedWriteHalf,
+    io::{self, AsyncSeekExt},
 };
 
 pub const MAX_LENGTH: usize = off_t::MAX as usize;
 pub const MAX_CHUNK: usize = 0x7ffff000; // according to the Linux docs, 0x7ffff000 is the maximum length for one sendfile()
 
-pub struct SendFile {
+pub struct SendFile<'a, W> {
     r: RawFd,
-    w: RawFd,
-    offset: usize,
+    w: &'a W,
+    /// `None` uses and advances the file position of `r`.
+    offset: Option<usize>,
+    /// The file position of `r` when `offset` is `None`, queried when the file is probed first.
+    position: Option<usize>,
     remaining: usize,
-    copied: usize,
+    cold: ColdPages,
+    budget: CopyBudget,
+    stats: CopyStats,
 }
 
-impl SendFile {
+impl<'a, W: ZeroCopySink> SendFile<'a, W> {
+    fn new(
+        r: RawFd,
+        w: &'a W,
+        offset: Option<usize>,
+        remaining: usize,
+        options: &CopyOptions,
+    ) -> Self {
+        Self {
+            r,
+            w,
+            offset,
+            position: None,
+            remaining,
+            cold: ColdPages::new(options.get_cold_page_policy().clone()),
+            budget: *options.get_budget(),
+            stats: CopyStats::new(CopyMechanism::SendFile),
+        }
+    }
+
+    /// The offset in the file the next sendfile starts at.
+    fn position(&mut self) -> Option<usize> {
+        if self.offset.is_some() {
+            return self.offset;
+        }
+        if self.position.is_none() {
+            self.position = match unsafe { libc::lseek(self.r, 0, libc::SEEK_CUR) } {
+                -1 => None,
+                position => Some(position as usize),
+            };
+        }
+        self.position
+    }
+
     fn raw_send_file(&mut self) -> Result<usize> {
-        match sendfile_n(
-            self.r,
-            self.w,
-            &mut self.offset,
-            MAX_CHUNK.min(self.remaining),
-        ) {
+        self.stats.sendfile_calls += 1;
+        let mut n = MAX_CHUNK.min(self.remaining);
+        if let Some(position) = self.offset.or(self.position) {
+            // do not send past the window probed for cold pages
+            n = n.min(self.cold.limit(position));
+        }
+        match sendfile_n(self.r, self.w.sink_fd(), self.offset.as_mut(), n) {
             -1 => Err(io::Error::last_os_error()),
             n => {
                 let n = n as usize;
-                self.copied += n;
+                self.stats.bytes += n;
                 self.remaining -= n;
+                if let Some(position) = self.position.as_mut() {
+                    *position += n;
+                }
                 Ok(n)
             }
         }
@@ -42,68 +91,153 @@ impl SendFile {
 }
 
 // Impl async trait
-impl Future for SendFile {
-    type Output = io::Result<usize>;
+impl<W: ZeroCopySink> Future for SendFile<'_, W> {
+    type Output = CopyResult<CopyStats>;
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let w = this.w;
+        let budget = PollBudget::start(this.budget, &this.stats);
         loop {
-            match self.raw_send_file() {
-                Ok(0) => break Poll::Ready(Ok(self.copied)),
-                Ok(_) => continue, // Attempt to write some more bytes.
+            let coop = ready!(budget.poll_proceed(cx, &mut this.stats));
+            if this.cold.is_enabled() {
+                if let Some(position) = this.position() {
+                    ready!(this.cold.poll_warm(
+                        cx,
+                        this.r,
+                        position,
+                        this.remaining,
+                        &mut this.stats
+                    ));
+                }
+            }
+            ready!(w.poll_write_ready_n(cx)).in_phase(CopyPhase::SendFile, this.stats.bytes)?;
+
+            // try_io clears the write readiness when sendfile fails with WouldBlock,
+            // so the next poll_write_ready parks the task until the socket drains.
+            match w.try_write_io_n(|| this.raw_send_file()) {
+                Ok(0) => break Poll::Ready(Ok(this.stats.clone().finish())),
+                Ok(_) => {
+                    coop.made_progress();
+                    continue; // Attempt to write some more bytes.
+                }
                 Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => {
-                    cx.waker().wake_by_ref();
-                    break Poll::Pending;
+                    this.stats.would_block += 1;
+                    continue; // Wait for readiness.
                 }
                 Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue, // Try again.
-                Err(err) => break Poll::Ready(Err(err)),
+                Err(err) => {
+                    break Poll::Ready(Err(CopyError::new(
+                        CopyPhase::SendFile,
+                        this.stats.bytes,
+                        err,
+                    )))
+                }
             }
         }
     }
 }
 
-/// Copy data from a file to a write half.
+/// Copy data from a file to a write half, starting at the current file position.
+/// The file position is advanced by the number of bytes copied.
 /// This function is only available on linux platforms and uses sendfile.
-pub async fn copy<'a>(r: &'a mut File, w: &'a mut OwnedWriteHalf) -> io::Result<usize> {
-    copy_exact(r, w, r.metadata().await?.len() as usize).await
+pub async fn copy<'a, W: ZeroCopySink>(
+    r: &'a mut File,
+    w: &'a mut W,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    let offset = r.stream_position().await.in_phase(CopyPhase::File, 0)?;
+    let length = r
+        .metadata()
+        .await
+        .in_phase(CopyPhase::File, 0)?
+        .len()
+        .saturating_sub(offset);
+    copy_exact(r, w, length as usize, options).await
 }
 
-/// Copy data from a file to a write half.
+/// Copy data from a file to a write half, starting at the current file position.
+/// The file position is advanced by the number of bytes copied, also when the copy fails.
 /// This function is only available on linux platforms and uses sendfile.
-pub async fn copy_exact<'a>(
+pub async fn copy_exact<'a, W: ZeroCopySink>(
     r: &'a mut File,
-    w: &'a mut OwnedWriteHalf,
+    w: &'a mut W,
     length: usize,
-) -> io::Result<usize> {
-    debug!("copying file to tcp stream using sendfile");
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    let offset = r.stream_position().await.in_phase(CopyPhase::File, 0)?;
+    match copy_range(r, w, offset, length, options).await {
+        Ok(stats) => {
+            r.seek(SeekFrom::Start(offset + stats.bytes as u64))
+                .await
+                .in_phase(CopyPhase::File, stats.bytes)?;
+            Ok(stats)
+        }
+        Err(err) => {
+            // the copy error is more useful than a failing seek
+            let _ = r
+                .seek(SeekFrom::Start(offset + err.transferred() as u64))
+                .await;
+            Err(err)
+        }
+    }
+}
+
+/// Copy a byte range of a file to a write half.
+/// The file position is left untouched.
+/// This function is only available on linux platforms and uses sendfile.
+pub async fn copy_range<'a, W: ZeroCopySink>(
+    r: &'a mut File,
+    w: &'a mut W,
+    offset: u64,
+    length: usize,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
     if length == 0 {
-        return Ok(0);
+        return Ok(CopyStats::new(CopyMechanism::SendFile));
     };
-    let mut buffered_length = 0;
-    let sendfile_length = if length > MAX_LENGTH {
-        buffered_length = length - MAX_LENGTH; // bytes that exceed sendfile()'s capacity
-        MAX_LENGTH
-    } else {
-        length
-    };
-    let rfd = r.as_raw_fd();
-    let wfd = w.as_ref().as_raw_fd();
-    let mut n = SendFile {
-        r: rfd,
-        w: wfd,
-        offset: 0,
-        remaining: sendfile_length,
-        copied: 0,
+    #[cfg(feature = "io-uring")]
+    if let Some(ring) = uring::ring(options) {
+        debug!("copying file to tcp stream using io_uring splice");
+        return uring::splice(ring, r, w, Some(offset), Some(length), options).await;
     }
-    .await?;
-    if buffered_length > 0 {
-        n += tokio::io::copy(&mut r.take(length as u64), w).await? as usize;
+    debug!("copying file to tcp stream using sendfile");
+    // a file cannot have any bytes past off_t::MAX, so sendfile() can always address the whole range
+    let length = length.min(MAX_LENGTH.saturating_sub(offset as usize));
+    SendFile::new(r.as_raw_fd(), w, Some(offset as usize), length, options).await
+}
+
+/// Copy data from a file source to a sink, starting at the source's file position.
+/// Copies `length` bytes if given, otherwise until EOF. The file position is advanced.
+/// This function is only available on linux platforms and uses sendfile.
+pub async fn send<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    #[cfg(feature = "io-uring")]
+    if let Some(ring) = uring::ring(options) {
+        debug!("copying file using io_uring splice");
+        return uring::splice(ring, r, w, None, length, options).await;
     }
-    Ok(n)
+    debug!("copying file using sendfile");
+    let length = length.unwrap_or(MAX_LENGTH).min(MAX_LENGTH);
+    SendFile::new(r.source_fd(), w, None, length, options).await
 }
 
-fn sendfile_n(r: i32, w: i32, offset: &mut usize, n: usize) -> isize {
-    let inner_offset = *offset as i64;
-    let result = unsafe { libc::sendfile(w, r, inner_offset as *mut i64, n) };
-    *offset = inner_offset as usize;
-    result
+fn sendfile_n(r: i32, w: i32, offset: Option<&mut usize>, n: usize) -> isize {
+    match offset {
+        Some(offset) => {
+            let mut inner_offset = *offset as off_t;
+            let result = unsafe { libc::sendfile(w, r, &mut inner_offset, n) };
+            *offset = inner_offset as usize;
+            result
+        }
+        None => unsafe { libc::sendfile(w, r, std::ptr::null_mut(), n) },
+    }
 }
@@ -0,0 +1,167 @@
+use crate::copy::{
+    endpoint::ZeroCopySink,
+    error::{CopyPhase, CopyResult, IoResultExt},
+    file,
+    options::CopyOptions,
+    stats::CopyStats,
+};
+use essentials::debug;
+use std::ffi::CString;
+use std::fs::File as StdFile;
+use std::io::{Error, ErrorKind, IoSlice, Result, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::fs::File;
+use tokio::io::AsyncWrite;
+
+/// the seals which make the content of a memfd immutable
+const SEALS: libc::c_int =
+    libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+
+/// Generated content, e.g. a CSV export or a tarball, held in memory and served like a file.
+///
+/// The content lives in an anonymous memory backed file created with `memfd_create`,
+/// nothing is written to disk. It is written with a [`MemFileWriter`] and sealed afterwards,
+/// so it can never change. [`MemFile::send`] sends the whole content to a sink with sendfile,
+/// and every client can open its own [`File`] with [`MemFile::open`], which works with
+/// [`copy_file`](crate::copy_file), [`copy_file_range`](crate::copy_file_range) and the other file copies.
+///
+/// The file is cheap to clone and can be shared across tasks,
+/// its memory is released once the last clone and the last opened file are dropped.
+/// This type is only available on linux platforms.
+#[derive(Debug, Clone)]
+pub struct MemFile {
+    file: Arc<StdFile>,
+    len: u64,
+}
+
+impl MemFile {
+    /// Create an empty memory file and a writer for its content.
+    /// The name is only used for debugging, it shows up in `/proc/self/fd`.
+    pub fn create(name: &str) -> Result<MemFileWriter> {
+        Ok(MemFileWriter {
+            file: memfd(name)?,
+            len: 0,
+        })
+    }
+
+    /// The length of the content.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the content is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Open the content for reading, with its own file position starting at 0.
+    ///
+    /// The file is reopened through `/proc/self/fd`, which creates a new open file description.
+    /// Without procfs, e.g. in a minimal container, this fails with [`ErrorKind::Unsupported`],
+    /// a duplicate would share its file position with every other client.
+    /// [`send`](MemFile::send) reads at explicit offsets and works without procfs.
+    pub async fn open(&self) -> Result<File> {
+        match File::open(format!("/proc/self/fd/{}", self.file.as_raw_fd())).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => Err(Error::new(
+                ErrorKind::Unsupported,
+                "procfs is not available to reopen the memory file, send it with MemFile::send",
+            )),
+            result => result,
+        }
+    }
+
+    /// Send the whole content to a sink, returns the number of bytes sent.
+    pub async fn send<W: ZeroCopySink>(&self, w: &mut W) -> CopyResult<usize> {
+        Ok(self.send_with(w, &CopyOptions::default()).await?.bytes)
+    }
+
+    /// Same as [`send`](MemFile::send), but uses the given options and returns the statistics of the copy.
+    pub async fn send_with<W: ZeroCopySink>(
+        &self,
+        w: &mut W,
+        options: &CopyOptions,
+    ) -> CopyResult<CopyStats> {
+        // sendfile reads at an explicit offset, so sends can share the file position
+        let mut file = File::from_std(self.file.try_clone().in_phase(CopyPhase::File, 0)?);
+        file::copy_range(&mut file, w, 0, self.len as usize, options).await
+    }
+}
+
+/// Writes the content of a [`MemFile`], created with [`MemFile::create`].
+///
+/// Writes go straight into memory and never block.
+/// Once the content is complete, [`seal`](MemFileWriter::seal) turns the writer into the file.
+#[derive(Debug)]
+pub struct MemFileWriter {
+    file: StdFile,
+    len: u64,
+}
+
+impl MemFileWriter {
+    /// The number of bytes written so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether nothing has been written yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Seal the content, so it can neither be written nor resized, and return the file.
+    pub fn seal(self) -> Result<MemFile> {
+        if unsafe { libc::fcntl(self.file.as_raw_fd(), libc::F_ADD_SEALS, SEALS) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        debug!("sealed memory file of {} bytes", self.len);
+        Ok(MemFile {
+            file: Arc::new(self.file),
+            len: self.len,
+        })
+    }
+}
+
+impl AsyncWrite for MemFileWriter {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        let n = this.file.write(buf)?;
+        this.len += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        let n = this.file.write_vectored(bufs)?;
+        this.len += n as u64;
+        Poll::Ready(Ok(n))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Creates an empty memfd which can be sealed.
+pub(crate) fn memfd(name: &str) -> Result<StdFile> {
+    let name = CString::new(name).map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+    match unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) }
+    {
+        -1 => Err(Error::last_os_error()),
+        fd => Ok(unsafe { StdFile::from_raw_fd(fd) }),
+    }
+}
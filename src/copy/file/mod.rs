@@ -1,5 +1,25 @@
 #[cfg(target_os = "linux")]
+mod cold;
+mod header;
+#[cfg(target_os = "linux")]
 mod linux;
+#[cfg(target_os = "linux")]
+mod mem;
+mod receive;
+#[cfg(target_os = "linux")]
+mod spool;
+mod to_file;
+
+#[cfg(target_os = "linux")]
+pub use cold::ColdPagePolicy;
+#[cfg(target_os = "linux")]
+pub use mem::{MemFile, MemFileWriter};
+
+pub use header::send_with_header;
+pub use receive::{receive, SyncPolicy};
+#[cfg(target_os = "linux")]
+pub use spool::{Spool, SpoolConfig};
+pub use to_file::{copy_to_file, FileRange};
 
 #[cfg(target_os = "linux")]
 pub use linux::copy;
@@ -7,32 +27,98 @@ pub use linux::copy;
 #[cfg(target_os = "linux")]
 pub use linux::copy_exact;
 
+#[cfg(target_os = "linux")]
+pub use linux::copy_range;
+
+#[cfg(target_os = "linux")]
+pub use linux::send;
+
+#[cfg(not(target_os = "linux"))]
+use crate::copy::{
+    buffered,
+    endpoint::{ZeroCopySink, ZeroCopySource},
+    error::CopyResult,
+    options::CopyOptions,
+    stats::CopyStats,
+};
 #[cfg(not(target_os = "linux"))]
-use tokio::{fs::File, io, net::tcp::OwnedWriteHalf};
+use tokio::fs::File;
 
-/// Copy data from a file to a write half.
-/// This function is only available on non-linux platforms and uses tokio::io::copy.
+/// Copy data from a file to a write half, starting at the current file position.
+/// This function is only available on non-linux platforms and uses a buffered copy.
 #[cfg(not(target_os = "linux"))]
-pub async fn copy<'a>(r: &'a mut File, w: &'a mut OwnedWriteHalf) -> io::Result<usize> {
+pub async fn copy<'a, W: ZeroCopySink>(
+    r: &'a mut File,
+    w: &'a mut W,
+    _options: &CopyOptions,
+) -> CopyResult<CopyStats> {
     use essentials::debug;
 
-    debug!("copying file to tcp stream using tokio::io::copy");
-    io::copy(r, w).await.map(|x| x as usize)
+    debug!("copying file to tcp stream using a buffered copy");
+    buffered::copy(r, w, None, None).await
 }
 
-/// Copy data from a file to a write half.
-/// This function is only available on linux platforms and uses sendfile.
+/// Copy data from a file to a write half, starting at the current file position.
+/// This function is only available on non-linux platforms and uses a buffered copy.
 #[cfg(not(target_os = "linux"))]
-pub async fn copy_exact<'a>(
+pub async fn copy_exact<'a, W: ZeroCopySink>(
     r: &'a mut File,
-    w: &'a mut OwnedWriteHalf,
+    w: &'a mut W,
     length: usize,
-) -> io::Result<usize> {
+    _options: &CopyOptions,
+) -> CopyResult<CopyStats> {
     use essentials::debug;
-    use tokio::io::AsyncReadExt;
 
-    debug!("copying file to tcp stream using tokio::io::copy");
-    io::copy(&mut r.take(length as u64), w)
+    debug!("copying file to tcp stream using a buffered copy");
+    buffered::copy(r, w, Some(length), None).await
+}
+
+/// Copy a byte range of a file to a write half.
+/// The file position is restored afterwards.
+/// This function is only available on non-linux platforms and uses a buffered copy.
+#[cfg(not(target_os = "linux"))]
+pub async fn copy_range<'a, W: ZeroCopySink>(
+    r: &'a mut File,
+    w: &'a mut W,
+    offset: u64,
+    length: usize,
+    _options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    use crate::copy::error::{CopyPhase, IoResultExt};
+    use essentials::debug;
+    use std::io::SeekFrom;
+    use tokio::io::AsyncSeekExt;
+
+    debug!("copying file range to tcp stream using a buffered copy");
+    let position = r.stream_position().await.in_phase(CopyPhase::File, 0)?;
+    r.seek(SeekFrom::Start(offset))
         .await
-        .map(|x| x as usize)
+        .in_phase(CopyPhase::File, 0)?;
+    let result = buffered::copy(r, w, Some(length), None).await;
+    let n = result
+        .as_ref()
+        .map_or_else(|err| err.transferred(), |stats| stats.bytes);
+    r.seek(SeekFrom::Start(position))
+        .await
+        .in_phase(CopyPhase::File, n)?;
+    result
+}
+
+/// Copy data from a file source to a sink, starting at the source's file position.
+/// This function is only available on non-linux platforms and uses a buffered copy.
+#[cfg(not(target_os = "linux"))]
+pub async fn send<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    length: Option<usize>,
+    _options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    use essentials::debug;
+
+    debug!("copying file using a buffered copy");
+    buffered::copy(r, w, length, None).await
 }
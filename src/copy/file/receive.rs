@@ -0,0 +1,88 @@
+use crate::copy::{
+    endpoint::ZeroCopySource,
+    error::{CopyError, CopyPhase, CopyResult, IoResultExt},
+    options::CopyOptions,
+    stats::CopyStats,
+    tcp,
+};
+use essentials::debug;
+use std::io::{Error, ErrorKind, SeekFrom};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use tokio::{fs::File, io::AsyncSeekExt};
+
+/// Whether the received data is flushed to disk before the copy finishes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Leave the data in the page cache, the kernel writes it back later.
+    #[default]
+    None,
+    /// Flush the data with fdatasync, metadata is only flushed if needed to read the data back.
+    Data,
+    /// Flush the data and all metadata with fsync.
+    All,
+}
+
+/// Copy data from a source into a file at `offset`.
+/// Copies exactly `length` bytes if given, failing if the source ends early, otherwise until EOF.
+/// The file position is left after the received data.
+pub async fn receive<'a, R: ZeroCopySource>(
+    r: &'a mut R,
+    file: &'a mut File,
+    offset: u64,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    #[cfg(target_os = "linux")]
+    if let Some(length) = length.filter(|length| options.get_preallocate() && *length > 0) {
+        preallocate(file, offset, length).in_phase(CopyPhase::Allocate, 0)?;
+    }
+    file.seek(SeekFrom::Start(offset))
+        .await
+        .in_phase(CopyPhase::File, 0)?;
+    debug!("receiving stream into a file");
+    let stats = tcp::splice(r, file, length, options).await?;
+    if length.is_some_and(|length| stats.bytes < length) {
+        let phase = if cfg!(target_os = "linux") {
+            CopyPhase::SpliceIn
+        } else {
+            CopyPhase::Read
+        };
+        return Err(CopyError::new(
+            phase,
+            stats.bytes,
+            Error::new(
+                ErrorKind::UnexpectedEof,
+                "source closed before the whole length was received",
+            ),
+        ));
+    }
+    match options.get_sync_policy() {
+        SyncPolicy::None => Ok(()),
+        SyncPolicy::Data => file.sync_data().await,
+        SyncPolicy::All => file.sync_all().await,
+    }
+    .in_phase(CopyPhase::Sync, stats.bytes)?;
+    Ok(stats)
+}
+
+/// Reserves the blocks of the range without changing the file size,
+/// so the copy fails early when the disk is full and the file is less fragmented.
+#[cfg(target_os = "linux")]
+fn preallocate(file: &File, offset: u64, length: usize) -> std::io::Result<()> {
+    match unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            length as libc::off_t,
+        )
+    } {
+        0 => Ok(()),
+        _ => match Error::last_os_error() {
+            // preallocation is only an optimisation
+            err if err.raw_os_error() == Some(libc::EOPNOTSUPP) => Ok(()),
+            err => Err(err),
+        },
+    }
+}
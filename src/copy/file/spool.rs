@@ -0,0 +1,279 @@
+use crate::copy::{
+    endpoint::{ZeroCopySink, ZeroCopySource},
+    error::{CopyError, CopyPhase, CopyResult, IoResultExt},
+    file::{self, mem::memfd, FileRange},
+    options::CopyOptions,
+    stats::CopyStats,
+    tcp,
+};
+use essentials::debug;
+use std::fs::{File as StdFile, OpenOptions};
+use std::io::{Error, ErrorKind, Result, SeekFrom};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::{fs::File, io::AsyncSeekExt};
+
+/// the default largest body kept in memory
+const MEMORY_LIMIT: usize = 1024 * 1024;
+
+/// the default largest body spooled at all
+const DISK_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Where and how much of a body a [`Spool`] stores.
+///
+/// Bodies up to the memory limit are kept in a memfd, bigger bodies are written
+/// to an unnamed temporary file in the spool directory. Bodies bigger than the disk limit
+/// are not spooled, receiving them fails with `FileTooLarge`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpoolConfig {
+    memory_limit: usize,
+    disk_limit: usize,
+    dir: PathBuf,
+}
+
+impl Default for SpoolConfig {
+    fn default() -> Self {
+        Self {
+            memory_limit: MEMORY_LIMIT,
+            disk_limit: DISK_LIMIT,
+            dir: std::env::temp_dir(),
+        }
+    }
+}
+
+impl SpoolConfig {
+    /// Create the default config, bodies up to 1 MiB are kept in memory
+    /// and bodies up to 64 MiB are spooled to the system's temporary directory.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The largest body in bytes kept in memory.
+    pub fn memory_limit(mut self, limit: usize) -> Self {
+        self.memory_limit = limit;
+        self
+    }
+
+    /// The largest body in bytes spooled at all, whether in memory or on disk.
+    pub fn disk_limit(mut self, limit: usize) -> Self {
+        self.disk_limit = limit;
+        self
+    }
+
+    /// The directory bodies bigger than the memory limit are spooled to.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    pub(crate) fn get_memory_limit(&self) -> usize {
+        self.memory_limit.min(self.disk_limit)
+    }
+
+    pub(crate) fn get_disk_limit(&self) -> usize {
+        self.disk_limit
+    }
+
+    pub(crate) fn get_dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// A body received from a source, e.g. a request body, stored so it can be sent again.
+///
+/// The body is spliced from the source into a memfd or, if it is bigger than the
+/// [memory limit](SpoolConfig::memory_limit), into an unnamed `O_TMPFILE` file,
+/// so it never enters userspace. It can be sent any number of times with sendfile,
+/// e.g. to retry a request against another upstream.
+///
+/// Neither file has a name, their storage is released once the last clone of the spool is dropped.
+/// On filesystems without `O_TMPFILE` a named file is created and unlinked right away.
+/// This type is only available on linux platforms.
+#[derive(Debug, Clone)]
+pub struct Spool {
+    file: Arc<StdFile>,
+    len: usize,
+    in_memory: bool,
+}
+
+impl Spool {
+    /// Receive a body from a source.
+    /// Receives exactly `length` bytes if given and fails with `UnexpectedEof` if the source ends early,
+    /// otherwise receives until EOF.
+    pub async fn receive<R: ZeroCopySource>(
+        r: &mut R,
+        length: Option<usize>,
+        config: &SpoolConfig,
+    ) -> CopyResult<Self> {
+        Ok(
+            Self::receive_with(r, length, config, &CopyOptions::default())
+                .await?
+                .0,
+        )
+    }
+
+    /// Same as [`receive`](Spool::receive), but uses the given options
+    /// and returns the statistics of the copy as well.
+    pub async fn receive_with<R: ZeroCopySource>(
+        r: &mut R,
+        length: Option<usize>,
+        config: &SpoolConfig,
+        options: &CopyOptions,
+    ) -> CopyResult<(Self, CopyStats)> {
+        let memory_limit = config.get_memory_limit();
+        let disk_limit = config.get_disk_limit();
+        if let Some(length) = length {
+            if length > disk_limit {
+                return Err(too_large(0));
+            }
+            let in_memory = length <= memory_limit;
+            let mut file = if in_memory {
+                memfd("spool")
+            } else {
+                tmpfile(config.get_dir())
+            }
+            .map(File::from_std)
+            .in_phase(CopyPhase::File, 0)?;
+            debug!("spooling {} bytes", length);
+            let stats = file::receive(r, &mut file, 0, Some(length), options).await?;
+            return Ok((Self::new(file, stats.bytes, in_memory).await, stats));
+        }
+
+        // the body is received into memory first, one byte past the limit shows whether it fits
+        let mut memory = File::from_std(memfd("spool").in_phase(CopyPhase::File, 0)?);
+        let head = tcp::splice(
+            r,
+            &mut memory,
+            Some(memory_limit.saturating_add(1)),
+            options,
+        )
+        .await?;
+        if head.bytes <= memory_limit {
+            debug!("spooled {} bytes in memory", head.bytes);
+            return Ok((Self::new(memory, head.bytes, true).await, head));
+        }
+        if head.bytes > disk_limit {
+            return Err(too_large(head.bytes));
+        }
+        debug!("spilling spooled body to disk");
+        let mut disk = tmpfile(config.get_dir())
+            .map(File::from_std)
+            .in_phase(CopyPhase::File, head.bytes)?;
+        let spill = file::copy_to_file(
+            &mut memory,
+            &mut disk,
+            FileRange::new().length(head.bytes),
+            options,
+        )
+        .await
+        .map_err(|err| CopyError::new(err.phase(), head.bytes, err.into_io_error()))?;
+        drop(memory);
+        disk.seek(SeekFrom::Start(head.bytes as u64))
+            .await
+            .in_phase(CopyPhase::File, head.bytes)?;
+        let tail = tcp::splice(r, &mut disk, Some(disk_limit - head.bytes + 1), options)
+            .await
+            .map_err(|err| {
+                CopyError::new(
+                    err.phase(),
+                    head.bytes + err.transferred(),
+                    err.into_io_error(),
+                )
+            })?;
+        let stats = CopyStats {
+            bytes: head.bytes + tail.bytes,
+            splice_calls: head.splice_calls + tail.splice_calls,
+            read_calls: head.read_calls + tail.read_calls,
+            write_calls: head.write_calls + tail.write_calls,
+            copy_file_range_calls: spill.copy_file_range_calls,
+            would_block: head.would_block + tail.would_block,
+            started_at: head.started_at,
+            ..tail
+        };
+        if stats.bytes > disk_limit {
+            return Err(too_large(stats.bytes));
+        }
+        debug!("spooled {} bytes on disk", stats.bytes);
+        Ok((Self::new(disk, stats.bytes, false).await, stats))
+    }
+
+    async fn new(file: File, len: usize, in_memory: bool) -> Self {
+        Self {
+            file: Arc::new(file.into_std().await),
+            len,
+            in_memory,
+        }
+    }
+
+    /// The length of the body.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the body is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the body is kept in memory rather than on disk.
+    pub fn is_in_memory(&self) -> bool {
+        self.in_memory
+    }
+
+    /// Send the whole body to a sink, returns the number of bytes sent.
+    pub async fn send<W: ZeroCopySink>(&self, w: &mut W) -> CopyResult<usize> {
+        Ok(self.send_with(w, &CopyOptions::default()).await?.bytes)
+    }
+
+    /// Same as [`send`](Spool::send), but uses the given options and returns the statistics of the copy.
+    pub async fn send_with<W: ZeroCopySink>(
+        &self,
+        w: &mut W,
+        options: &CopyOptions,
+    ) -> CopyResult<CopyStats> {
+        // sendfile reads at an explicit offset, so sends can share the file position
+        let mut file = File::from_std(self.file.try_clone().in_phase(CopyPhase::File, 0)?);
+        file::copy_range(&mut file, w, 0, self.len, options).await
+    }
+}
+
+/// Creates an unnamed temporary file in the directory.
+fn tmpfile(dir: &Path) -> Result<StdFile> {
+    match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_TMPFILE)
+        .mode(0o600)
+        .open(dir)
+    {
+        // not every filesystem supports unnamed temporary files
+        Err(err) if matches!(err.raw_os_error(), Some(libc::EOPNOTSUPP | libc::EISDIR)) => {
+            let path = dir.join(format!(
+                ".spool-{}-{:016x}",
+                std::process::id(),
+                rand::random::<u64>()
+            ));
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path)?;
+            std::fs::remove_file(&path)?;
+            Ok(file)
+        }
+        result => result,
+    }
+}
+
+fn too_large(transferred: usize) -> CopyError {
+    CopyError::new(
+        CopyPhase::SpliceIn,
+        transferred,
+        Error::new(
+            ErrorKind::FileTooLarge,
+            "body exceeds the spool's disk limit",
+        ),
+    )
+}
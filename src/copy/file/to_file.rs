@@ -0,0 +1,378 @@
+#[cfg(target_os = "linux")]
+use crate::copy::pipe::Pipe;
+use crate::copy::{
+    error::{CopyError, CopyPhase, CopyResult, IoResultExt},
+    options::CopyOptions,
+    stats::{CopyMechanism, CopyProgress, CopyStats},
+};
+use essentials::debug;
+#[cfg(target_os = "linux")]
+use std::io::Result;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::FileExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, RawFd};
+use tokio::fs::File;
+
+/// the number of bytes copied by a single blocking task
+const CHUNK: usize = 16 * 1024 * 1024;
+
+/// the buffer size of a buffered copy
+const BUF_SIZE: usize = 64 * 1024;
+
+#[cfg(target_os = "linux")]
+const FICLONERANGE: libc::c_ulong = 0x4020940d;
+
+#[cfg(target_os = "linux")]
+#[repr(C)]
+struct FileCloneRange {
+    src_fd: i64,
+    src_offset: u64,
+    src_length: u64,
+    dest_offset: u64,
+}
+
+/// The byte range copied by [`copy_file_to_file`](crate::copy_file_to_file).
+///
+/// By default the whole source file is copied to the start of the destination file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileRange {
+    src_offset: u64,
+    dst_offset: u64,
+    length: Option<usize>,
+}
+
+impl FileRange {
+    /// Create the default range, the whole source file.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The offset in the source file the copy starts at.
+    pub fn src_offset(mut self, offset: u64) -> Self {
+        self.src_offset = offset;
+        self
+    }
+
+    /// The offset in the destination file the data is written to.
+    pub fn dst_offset(mut self, offset: u64) -> Self {
+        self.dst_offset = offset;
+        self
+    }
+
+    /// The number of bytes to copy, by default everything up to the end of the source file.
+    pub fn length(mut self, length: usize) -> Self {
+        self.length = Some(length);
+        self
+    }
+}
+
+/// A copy between two files, moved to the blocking thread pool for every chunk.
+struct FileCopy {
+    src: std::fs::File,
+    dst: std::fs::File,
+    src_offset: u64,
+    dst_offset: u64,
+    stats: CopyStats,
+    #[cfg(target_os = "linux")]
+    pipe: Option<Pipe>,
+}
+
+impl FileCopy {
+    fn new(src: std::fs::File, dst: std::fs::File, range: &FileRange) -> Self {
+        #[cfg(target_os = "linux")]
+        let mechanism = CopyMechanism::CopyFileRange;
+        #[cfg(not(target_os = "linux"))]
+        let mechanism = CopyMechanism::Buffered;
+        Self {
+            src,
+            dst,
+            src_offset: range.src_offset,
+            dst_offset: range.dst_offset,
+            stats: CopyStats::new(mechanism),
+            #[cfg(target_os = "linux")]
+            pipe: None,
+        }
+    }
+
+    /// Clones the extents of `length` bytes, returns whether the file system supports it.
+    #[cfg(target_os = "linux")]
+    fn reflink(&mut self, length: usize) -> bool {
+        let range = FileCloneRange {
+            src_fd: self.src.as_raw_fd() as i64,
+            src_offset: self.src_offset,
+            src_length: length as u64,
+            dest_offset: self.dst_offset,
+        };
+        match unsafe { libc::ioctl(self.dst.as_raw_fd(), FICLONERANGE as _, &range) } {
+            -1 => {
+                debug!("reflink failed: {}", Error::last_os_error());
+                false
+            }
+            _ => {
+                self.stats.mechanism = CopyMechanism::Reflink;
+                self.advance(length);
+                true
+            }
+        }
+    }
+
+    /// Copies up to `length` bytes, falling back to a slower mechanism when the current one
+    /// is not supported for these files. Returns 0 at the end of the source file.
+    fn step(&mut self, length: usize) -> CopyResult<usize> {
+        let result = match self.stats.mechanism {
+            #[cfg(target_os = "linux")]
+            CopyMechanism::CopyFileRange => self
+                .copy_file_range(length)
+                .in_phase(CopyPhase::CopyFileRange, self.stats.bytes),
+            #[cfg(target_os = "linux")]
+            CopyMechanism::Splice => self.splice(length),
+            _ => self.buffered(length),
+        };
+        #[cfg(target_os = "linux")]
+        if let Err(err) = &result {
+            let fallback = match self.stats.mechanism {
+                CopyMechanism::CopyFileRange => CopyMechanism::Splice,
+                CopyMechanism::Splice => CopyMechanism::Buffered,
+                _ => return result,
+            };
+            // only fall back before anything was copied, later errors are real failures
+            if self.stats.bytes == 0 && is_unsupported(err.io_error()) {
+                debug!(
+                    "{:?} is not supported, falling back to {:?}: {}",
+                    self.stats.mechanism,
+                    fallback,
+                    err.io_error()
+                );
+                self.stats.mechanism = fallback;
+                return self.step(length);
+            }
+        }
+        result
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.src_offset += n as u64;
+        self.dst_offset += n as u64;
+        self.stats.bytes += n;
+    }
+
+    #[cfg(target_os = "linux")]
+    fn copy_file_range(&mut self, length: usize) -> Result<usize> {
+        let mut done = 0;
+        while done < length {
+            self.stats.copy_file_range_calls += 1;
+            let mut off_in = self.src_offset as libc::off64_t;
+            let mut off_out = self.dst_offset as libc::off64_t;
+            let n = match unsafe {
+                libc::copy_file_range(
+                    self.src.as_raw_fd(),
+                    &mut off_in,
+                    self.dst.as_raw_fd(),
+                    &mut off_out,
+                    length - done,
+                    0,
+                )
+            } {
+                -1 => return partial(done, Error::last_os_error()),
+                0 => break,
+                n => n as usize,
+            };
+            self.advance(n);
+            done += n;
+        }
+        Ok(done)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn splice(&mut self, length: usize) -> CopyResult<usize> {
+        if self.pipe.is_none() {
+            self.pipe = Some(Pipe::new().in_phase(CopyPhase::Pipe, self.stats.bytes)?);
+        }
+        let (read_fd, write_fd) = match &self.pipe {
+            Some(pipe) => (pipe.read_fd(), pipe.write_fd()),
+            None => unreachable!(),
+        };
+        let mut done = 0;
+        while done < length {
+            self.stats.splice_calls += 1;
+            let mut off_in = self.src_offset as libc::loff_t;
+            let filled = match splice(
+                self.src.as_raw_fd(),
+                Some(&mut off_in),
+                write_fd,
+                None,
+                length - done,
+            ) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) => {
+                    return partial(done, err).in_phase(CopyPhase::SpliceIn, self.stats.bytes)
+                }
+            };
+            let mut drained = 0;
+            while drained < filled {
+                self.stats.splice_calls += 1;
+                let mut off_out = self.dst_offset as libc::loff_t;
+                // the offsets only advance once the data is written, so a fallback starts over
+                let n = splice(
+                    read_fd,
+                    None,
+                    self.dst.as_raw_fd(),
+                    Some(&mut off_out),
+                    filled - drained,
+                )
+                .and_then(|n| match n {
+                    0 => Err(Error::new(
+                        ErrorKind::WriteZero,
+                        "write zero byte into file",
+                    )),
+                    n => Ok(n),
+                })
+                .in_phase(CopyPhase::SpliceOut, self.stats.bytes)?;
+                self.advance(n);
+                drained += n;
+            }
+            done += filled;
+        }
+        Ok(done)
+    }
+
+    fn buffered(&mut self, length: usize) -> CopyResult<usize> {
+        let mut buf = vec![0; BUF_SIZE.min(length)];
+        let mut done = 0;
+        while done < length {
+            let limit = buf.len().min(length - done);
+            self.stats.read_calls += 1;
+            let n = match self.src.read_at(&mut buf[..limit], self.src_offset) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(CopyError::new(CopyPhase::Read, self.stats.bytes, err)),
+            };
+            self.stats.write_calls += 1;
+            self.dst
+                .write_all_at(&buf[..n], self.dst_offset)
+                .in_phase(CopyPhase::Write, self.stats.bytes)?;
+            self.advance(n);
+            done += n;
+        }
+        Ok(done)
+    }
+}
+
+/// Returns the bytes copied before the error, or the error if nothing was copied,
+/// so the error is reported by the next step.
+#[cfg(target_os = "linux")]
+fn partial(done: usize, err: Error) -> Result<usize> {
+    match done {
+        0 => Err(err),
+        done => Ok(done),
+    }
+}
+
+/// Whether the error means that the mechanism cannot copy between these files.
+#[cfg(target_os = "linux")]
+fn is_unsupported(err: &Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EXDEV | libc::EINVAL | libc::ENOSYS | libc::EOPNOTSUPP | libc::EBADF)
+    )
+}
+
+/// A blocking splice, one of the file descriptors must be a pipe.
+#[cfg(target_os = "linux")]
+fn splice(
+    fd_in: RawFd,
+    off_in: Option<&mut libc::loff_t>,
+    fd_out: RawFd,
+    off_out: Option<&mut libc::loff_t>,
+    len: usize,
+) -> Result<usize> {
+    let off_in = off_in.map_or(std::ptr::null_mut(), |off| off as *mut _);
+    let off_out = off_out.map_or(std::ptr::null_mut(), |off| off as *mut _);
+    match unsafe { libc::splice(fd_in, off_in, fd_out, off_out, len, 0) } {
+        -1 => Err(Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
+
+/// Copy a byte range from one file to another on the blocking thread pool.
+/// The file positions are left untouched.
+pub async fn copy_to_file(
+    src: &mut File,
+    dst: &mut File,
+    range: FileRange,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    let length = match range.length {
+        Some(length) => length,
+        None => src
+            .metadata()
+            .await
+            .in_phase(CopyPhase::File, 0)?
+            .len()
+            .saturating_sub(range.src_offset) as usize,
+    };
+    // the blocking tasks use their own descriptors, so a cancelled copy cannot outlive the files
+    let src = src
+        .try_clone()
+        .await
+        .in_phase(CopyPhase::File, 0)?
+        .into_std()
+        .await;
+    let dst = dst
+        .try_clone()
+        .await
+        .in_phase(CopyPhase::File, 0)?
+        .into_std()
+        .await;
+    let mut copy = FileCopy::new(src, dst, &range);
+    let report = |stats: &CopyStats| {
+        if let Some(progress) = options.get_progress() {
+            progress.send_replace(CopyProgress {
+                bytes: stats.bytes,
+                remaining: length - stats.bytes,
+            });
+        }
+    };
+    report(&copy.stats);
+    #[cfg(target_os = "linux")]
+    if options.get_reflink() && length > 0 {
+        let (returned, cloned) = blocking(copy, move |copy| copy.reflink(length)).await?;
+        copy = returned;
+        if cloned {
+            debug!("copied file range using reflink");
+            report(&copy.stats);
+            return Ok(copy.stats.finish());
+        }
+    }
+    debug!("copying file range using {:?}", copy.stats.mechanism);
+    while copy.stats.bytes < length {
+        let chunk = CHUNK.min(length - copy.stats.bytes);
+        let (returned, result) = blocking(copy, move |copy| copy.step(chunk)).await?;
+        copy = returned;
+        let n = result?;
+        report(&copy.stats);
+        if n == 0 {
+            // the source file is shorter than the range
+            break;
+        }
+    }
+    Ok(copy.stats.finish())
+}
+
+/// Runs `f` on the blocking thread pool and returns the copy back.
+async fn blocking<T, F>(mut copy: FileCopy, f: F) -> CopyResult<(FileCopy, T)>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut FileCopy) -> T + Send + 'static,
+{
+    let bytes = copy.stats.bytes;
+    tokio::task::spawn_blocking(move || {
+        let result = f(&mut copy);
+        (copy, result)
+    })
+    .await
+    .map_err(|err| CopyError::new(CopyPhase::File, bytes, Error::other(err)))
+}
@@ -1,35 +1,533 @@
+#[cfg(target_os = "linux")]
+mod budget;
+#[cfg(not(target_os = "linux"))]
+mod buffered;
+#[cfg(target_os = "linux")]
+mod cache;
+mod capture;
+mod endpoint;
+mod error;
 mod file;
+mod options;
+#[cfg(target_os = "linux")]
+mod pipe;
+mod stats;
 mod tcp;
+mod tee;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+mod zerocopy;
 
+#[cfg(target_os = "linux")]
+pub use budget::CopyBudget;
+#[cfg(target_os = "linux")]
+pub use cache::PipeCache;
+pub use capture::{Capture, CaptureDirection, CaptureFrame, CaptureReader};
+pub use endpoint::{ZeroCopySink, ZeroCopySource};
+pub use error::{CopyError, CopyPhase, CopyResult};
+#[cfg(target_os = "linux")]
+pub use file::{ColdPagePolicy, MemFile, MemFileWriter, Spool, SpoolConfig};
+pub use file::{FileRange, SyncPolicy};
+pub use options::CopyOptions;
+#[cfg(target_os = "linux")]
+pub use pipe::{PipePool, SpliceConfig};
+pub use stats::{CopyMechanism, CopyProgress, CopyStats};
+pub use tee::{ShadowPolicy, TeeStats};
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use uring::CopyBackend;
+pub use zerocopy::ZeroCopySender;
+
+use error::IoResultExt;
+
+use std::{
+    io::IoSlice,
+    ops::{Bound, RangeBounds},
+};
 use tokio::{
     fs::File,
-    io,
-    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        unix::{OwnedReadHalf as UnixReadHalf, OwnedWriteHalf as UnixWriteHalf},
+    },
 };
 
-/// Copy data from a read half to a write half.
-/// This function is only available on linux platforms and uses splice.
+/// Copy data from any zero-copy source to any zero-copy sink.
+/// Copies `length` bytes if given, otherwise until EOF. The sink is flushed but not shut down.
+/// On linux platforms files are sent with sendfile and all other sources are spliced through a pipe.
+pub async fn copy<'a, R, W>(r: &'a mut R, w: &'a mut W, length: Option<usize>) -> CopyResult<usize>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    Ok(copy_with_stats(r, w, length).await?.bytes)
+}
+
+/// Same as [`copy`], but returns the statistics of the copy.
+pub async fn copy_with_stats<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    length: Option<usize>,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    copy_with(r, w, length, &CopyOptions::default()).await
+}
+
+/// Same as [`copy`], but uses the given options and returns the statistics of the copy.
+pub async fn copy_with<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    if r.is_file() {
+        file::send(r, w, length, options).await
+    } else {
+        tcp::splice(r, w, length, options).await
+    }
+}
+
+/// Copy a prefix built in userspace, e.g. response headers, followed by data from a source,
+/// e.g. a body from an upstream socket, to a sink. Copies `length` bytes of the source if given,
+/// otherwise until EOF. The sink is flushed but not shut down.
+/// Returns the number of bytes copied, the prefix included.
+///
+/// On linux platforms the prefix is written into the same pipe as the data of the source,
+/// so the prefix and the data leave through one splice path. The prefix is copied into the pipe
+/// rather than vmspliced: a tcp socket references spliced pages until the peer acknowledges them,
+/// so a retransmission could carry different bytes once the buffer is released and reused.
+/// The prefix is only borrowed until it has been written into the pipe.
+/// Other platforms use a buffered copy.
+pub async fn copy_with_prefix<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    prefix: &[u8],
+    length: Option<usize>,
+) -> CopyResult<usize>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    Ok(
+        copy_with_prefix_with(r, w, prefix, length, &CopyOptions::default())
+            .await?
+            .bytes,
+    )
+}
+
+/// Same as [`copy_with_prefix`], but uses the given options and returns the statistics of the copy.
+pub async fn copy_with_prefix_with<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    prefix: &[u8],
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    tcp::splice_with_prefix(r, w, prefix, length, options).await
+}
+
+/// Copy data from a tcp read half to a tcp write half.
+/// The write half is shut down afterwards.
+/// On linux platforms this function uses splice.
 pub async fn copy_tcp<'a>(
     r: &'a mut OwnedReadHalf,
     w: &'a mut OwnedWriteHalf,
     length: Option<usize>,
-) -> io::Result<usize> {
+) -> CopyResult<usize> {
+    Ok(copy_tcp_with_stats(r, w, length).await?.bytes)
+}
+
+/// Same as [`copy_tcp`], but returns the statistics of the copy.
+pub async fn copy_tcp_with_stats<'a>(
+    r: &'a mut OwnedReadHalf,
+    w: &'a mut OwnedWriteHalf,
+    length: Option<usize>,
+) -> CopyResult<CopyStats> {
+    copy_stream(r, w, length, &CopyOptions::default()).await
+}
+
+/// Same as [`copy_tcp`], but uses the given options and returns the statistics of the copy.
+pub async fn copy_tcp_with<'a>(
+    r: &'a mut OwnedReadHalf,
+    w: &'a mut OwnedWriteHalf,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    copy_stream(r, w, length, options).await
+}
+
+/// Copy data from a unix socket read half to a unix socket write half.
+/// The write half is shut down afterwards.
+/// On linux platforms this function uses splice.
+pub async fn copy_unix<'a>(
+    r: &'a mut UnixReadHalf,
+    w: &'a mut UnixWriteHalf,
+    length: Option<usize>,
+) -> CopyResult<usize> {
+    Ok(copy_unix_with_stats(r, w, length).await?.bytes)
+}
+
+/// Same as [`copy_unix`], but returns the statistics of the copy.
+pub async fn copy_unix_with_stats<'a>(
+    r: &'a mut UnixReadHalf,
+    w: &'a mut UnixWriteHalf,
+    length: Option<usize>,
+) -> CopyResult<CopyStats> {
+    copy_stream(r, w, length, &CopyOptions::default()).await
+}
+
+/// Same as [`copy_unix`], but uses the given options and returns the statistics of the copy.
+pub async fn copy_unix_with<'a>(
+    r: &'a mut UnixReadHalf,
+    w: &'a mut UnixWriteHalf,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    copy_stream(r, w, length, options).await
+}
+
+/// Copy data from a tcp read half to a unix socket write half.
+/// The write half is shut down afterwards.
+/// On linux platforms this function uses splice.
+pub async fn copy_tcp_to_unix<'a>(
+    r: &'a mut OwnedReadHalf,
+    w: &'a mut UnixWriteHalf,
+    length: Option<usize>,
+) -> CopyResult<usize> {
+    Ok(copy_tcp_to_unix_with_stats(r, w, length).await?.bytes)
+}
+
+/// Same as [`copy_tcp_to_unix`], but returns the statistics of the copy.
+pub async fn copy_tcp_to_unix_with_stats<'a>(
+    r: &'a mut OwnedReadHalf,
+    w: &'a mut UnixWriteHalf,
+    length: Option<usize>,
+) -> CopyResult<CopyStats> {
+    copy_stream(r, w, length, &CopyOptions::default()).await
+}
+
+/// Same as [`copy_tcp_to_unix`], but uses the given options and returns the statistics of the copy.
+pub async fn copy_tcp_to_unix_with<'a>(
+    r: &'a mut OwnedReadHalf,
+    w: &'a mut UnixWriteHalf,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    copy_stream(r, w, length, options).await
+}
+
+/// Copy data from a unix socket read half to a tcp write half.
+/// The write half is shut down afterwards.
+/// On linux platforms this function uses splice.
+pub async fn copy_unix_to_tcp<'a>(
+    r: &'a mut UnixReadHalf,
+    w: &'a mut OwnedWriteHalf,
+    length: Option<usize>,
+) -> CopyResult<usize> {
+    Ok(copy_unix_to_tcp_with_stats(r, w, length).await?.bytes)
+}
+
+/// Same as [`copy_unix_to_tcp`], but returns the statistics of the copy.
+pub async fn copy_unix_to_tcp_with_stats<'a>(
+    r: &'a mut UnixReadHalf,
+    w: &'a mut OwnedWriteHalf,
+    length: Option<usize>,
+) -> CopyResult<CopyStats> {
+    copy_stream(r, w, length, &CopyOptions::default()).await
+}
+
+/// Same as [`copy_unix_to_tcp`], but uses the given options and returns the statistics of the copy.
+pub async fn copy_unix_to_tcp_with<'a>(
+    r: &'a mut UnixReadHalf,
+    w: &'a mut OwnedWriteHalf,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    copy_stream(r, w, length, options).await
+}
+
+async fn copy_stream<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
     if let Some(length) = length {
-        tcp::copy_exact(r, w, length).await
+        tcp::copy_exact(r, w, length, options).await
     } else {
-        tcp::copy(r, w).await
+        tcp::copy(r, w, options).await
     }
 }
-/// Copy data from a file to a write half.
-/// This function is only available on linux platforms and uses sendfile.
-pub async fn copy_file<'a>(
+
+/// Copy data in both directions between two streams, e.g. `TcpStream`s or `UnixStream`s.
+/// When one stream reaches EOF, the write side of the other one is shut down.
+/// Returns the number of bytes copied from `a` to `b` and from `b` to `a`.
+/// On linux platforms this function uses splice.
+pub async fn copy_bidirectional<'a, A, B>(a: &'a mut A, b: &'a mut B) -> CopyResult<(usize, usize)>
+where
+    A: ZeroCopySource + ZeroCopySink,
+    B: ZeroCopySource + ZeroCopySink,
+{
+    let (a_to_b, b_to_a) = copy_bidirectional_with_stats(a, b).await?;
+    Ok((a_to_b.bytes, b_to_a.bytes))
+}
+
+/// Same as [`copy_bidirectional`], but returns the statistics of both directions.
+pub async fn copy_bidirectional_with_stats<'a, A, B>(
+    a: &'a mut A,
+    b: &'a mut B,
+) -> CopyResult<(CopyStats, CopyStats)>
+where
+    A: ZeroCopySource + ZeroCopySink,
+    B: ZeroCopySource + ZeroCopySink,
+{
+    copy_bidirectional_with(a, b, &CopyOptions::default()).await
+}
+
+/// Same as [`copy_bidirectional`], but uses the given options
+/// and returns the statistics of both directions.
+pub async fn copy_bidirectional_with<'a, A, B>(
+    a: &'a mut A,
+    b: &'a mut B,
+    options: &CopyOptions,
+) -> CopyResult<(CopyStats, CopyStats)>
+where
+    A: ZeroCopySource + ZeroCopySink,
+    B: ZeroCopySource + ZeroCopySink,
+{
+    tcp::copy_bidirectional(a, b, options).await
+}
+
+/// Copy data from a source to two sinks, e.g. to mirror the traffic of a client
+/// to a primary and a shadow upstream. Both sinks are shut down afterwards.
+/// Returns the number of bytes copied to the primary and to the secondary sink.
+/// The primary sink waits for the secondary one, use [`copy_tee_with`]
+/// with a [`ShadowPolicy`] to drop data for it or disconnect it instead.
+/// On linux platforms the data is spliced into a pipe and duplicated with tee.
+pub async fn copy_tee<'a, R, P, S>(
+    r: &'a mut R,
+    primary: &'a mut P,
+    secondary: &'a mut S,
+) -> CopyResult<(usize, usize)>
+where
+    R: ZeroCopySource,
+    P: ZeroCopySink,
+    S: ZeroCopySink,
+{
+    let stats = copy_tee_with(r, primary, secondary, &CopyOptions::default()).await?;
+    Ok((stats.primary.bytes, stats.secondary.bytes))
+}
+
+/// Same as [`copy_tee`], but uses the given options and returns the statistics of the copy.
+/// Failures of the secondary sink never fail the copy, they disconnect the secondary sink.
+pub async fn copy_tee_with<'a, R, P, S>(
+    r: &'a mut R,
+    primary: &'a mut P,
+    secondary: &'a mut S,
+    options: &CopyOptions,
+) -> CopyResult<TeeStats>
+where
+    R: ZeroCopySource,
+    P: ZeroCopySink,
+    S: ZeroCopySink,
+{
+    tee::copy_tee(r, primary, secondary, options).await
+}
+
+/// Copy data from a file to a write half, e.g. a tcp or unix socket write half.
+/// Copying starts at the current file position, which is advanced by the number of bytes copied.
+/// On linux platforms this function uses sendfile.
+pub async fn copy_file<'a, W: ZeroCopySink>(
     r: &'a mut File,
-    w: &'a mut OwnedWriteHalf,
+    w: &'a mut W,
+    length: Option<usize>,
+) -> CopyResult<usize> {
+    Ok(copy_file_with_stats(r, w, length).await?.bytes)
+}
+
+/// Same as [`copy_file`], but returns the statistics of the copy.
+pub async fn copy_file_with_stats<'a, W: ZeroCopySink>(
+    r: &'a mut File,
+    w: &'a mut W,
+    length: Option<usize>,
+) -> CopyResult<CopyStats> {
+    copy_file_with(r, w, length, &CopyOptions::default()).await
+}
+
+/// Same as [`copy_file`], but uses the given options and returns the statistics of the copy.
+pub async fn copy_file_with<'a, W: ZeroCopySink>(
+    r: &'a mut File,
+    w: &'a mut W,
     length: Option<usize>,
-) -> io::Result<usize> {
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
     if let Some(length) = length {
-        file::copy_exact(r, w, length).await
+        file::copy_exact(r, w, length, options).await
     } else {
-        file::copy(r, w).await
+        file::copy(r, w, options).await
     }
 }
+
+/// Copy a byte range of a file to a write half, e.g. a tcp or unix socket write half.
+/// Copies `length` bytes starting at `offset`, or everything up to EOF if `length` is `None`.
+/// The file position is left untouched, seek the file and use [`copy_file`] to advance it instead.
+/// On linux platforms this function uses sendfile.
+pub async fn copy_file_range<'a, W: ZeroCopySink>(
+    r: &'a mut File,
+    w: &'a mut W,
+    offset: u64,
+    length: Option<usize>,
+) -> CopyResult<usize> {
+    Ok(copy_file_range_with_stats(r, w, offset, length)
+        .await?
+        .bytes)
+}
+
+/// Same as [`copy_file_range`], but returns the statistics of the copy.
+pub async fn copy_file_range_with_stats<'a, W: ZeroCopySink>(
+    r: &'a mut File,
+    w: &'a mut W,
+    offset: u64,
+    length: Option<usize>,
+) -> CopyResult<CopyStats> {
+    let length = match length {
+        Some(length) => length,
+        None => r
+            .metadata()
+            .await
+            .in_phase(CopyPhase::File, 0)?
+            .len()
+            .saturating_sub(offset) as usize,
+    };
+    file::copy_range(r, w, offset, length, &CopyOptions::default()).await
+}
+
+/// Send a header, a byte range of a file and an optional trailer to a write half,
+/// e.g. to serve an HTTP response with a tcp write half. Returns the total number of bytes sent.
+/// The range is clamped to the end of the file and the file position is left untouched.
+/// On linux platforms a tcp sink is corked with `TCP_CORK` while the header is written with
+/// vectored I/O, the body is sent with sendfile and the trailer is written, so no small segment
+/// is sent between them. Other platforms use buffered writes.
+pub async fn send_with_header<'a, W: ZeroCopySink>(
+    w: &'a mut W,
+    header: &[IoSlice<'_>],
+    file: &'a mut File,
+    range: impl RangeBounds<u64>,
+    trailer: Option<&[u8]>,
+) -> CopyResult<usize> {
+    Ok(
+        send_with_header_with(w, header, file, range, trailer, &CopyOptions::default())
+            .await?
+            .bytes,
+    )
+}
+
+/// Same as [`send_with_header`], but uses the given options and returns the statistics of the copy.
+pub async fn send_with_header_with<'a, W: ZeroCopySink>(
+    w: &'a mut W,
+    header: &[IoSlice<'_>],
+    file: &'a mut File,
+    range: impl RangeBounds<u64>,
+    trailer: Option<&[u8]>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    let len = file.metadata().await.in_phase(CopyPhase::File, 0)?.len();
+    let start = match range.start_bound() {
+        Bound::Included(start) => *start,
+        Bound::Excluded(start) => start.saturating_add(1),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(end) => end.saturating_add(1),
+        Bound::Excluded(end) => *end,
+        Bound::Unbounded => len,
+    };
+    let length = end.min(len).saturating_sub(start) as usize;
+    file::send_with_header(w, header, file, start, length, trailer, options).await
+}
+
+/// Send an in-memory buffer, e.g. a `Vec<u8>` or `Bytes`, to a tcp stream owned by a [`ZeroCopySender`].
+/// Returns the number of bytes sent.
+/// On linux platforms the buffer is sent with `MSG_ZEROCOPY`, so the kernel reads it in place
+/// instead of copying it. The buffer is held until the kernel reports that it is done with it,
+/// by the sender if the returned future is dropped before that.
+/// Buffers smaller than [`CopyOptions::zerocopy_threshold`], sockets without `SO_ZEROCOPY` support
+/// and other platforms use plain writes.
+pub async fn send_zerocopy<B: AsRef<[u8]> + Send + 'static>(
+    w: &mut ZeroCopySender,
+    buf: B,
+) -> CopyResult<usize> {
+    Ok(send_zerocopy_with(w, buf, &CopyOptions::default())
+        .await?
+        .bytes)
+}
+
+/// Same as [`send_zerocopy`], but uses the given options and returns the statistics of the copy.
+pub async fn send_zerocopy_with<B: AsRef<[u8]> + Send + 'static>(
+    w: &mut ZeroCopySender,
+    buf: B,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    w.send(buf, options).await
+}
+
+/// Copy a byte range from one file to another, e.g. to back up large files.
+/// The data is copied in chunks on tokio's blocking thread pool and the file positions are left untouched.
+/// Progress is published after every chunk if [`CopyOptions::progress`] is set.
+///
+/// On linux platforms the files are copied with copy_file_range, falling back to splice
+/// and then a buffered copy when the file systems do not support it, e.g. on older kernels
+/// when the files are on different file systems. With [`CopyOptions::reflink`]
+/// the extents of the source file are cloned first if the file system supports it.
+/// Other platforms use a buffered copy.
+pub async fn copy_file_to_file(
+    src: &mut File,
+    dst: &mut File,
+    range: FileRange,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    file::copy_to_file(src, dst, range, options).await
+}
+
+/// Receive data from a source, e.g. a tcp or unix socket read half, into a file at `offset`.
+/// Receives exactly `length` bytes if given and fails with `UnexpectedEof` if the source ends early,
+/// otherwise receives until EOF. The file position is left after the received data.
+/// On linux platforms the data is spliced through a pipe and never copied into userspace.
+pub async fn receive_to_file<'a, R: ZeroCopySource>(
+    r: &'a mut R,
+    file: &'a mut File,
+    offset: u64,
+    length: Option<usize>,
+) -> CopyResult<usize> {
+    Ok(
+        receive_to_file_with(r, file, offset, length, &CopyOptions::default())
+            .await?
+            .bytes,
+    )
+}
+
+/// Same as [`receive_to_file`], but uses the given options and returns the statistics of the copy.
+/// See [`CopyOptions::preallocate`] and [`CopyOptions::sync_policy`] for options specific to files.
+pub async fn receive_to_file_with<'a, R: ZeroCopySource>(
+    r: &'a mut R,
+    file: &'a mut File,
+    offset: u64,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    file::receive(r, file, offset, length, options).await
+}
@@ -0,0 +1,205 @@
+use crate::copy::capture::Capture;
+use crate::copy::file::SyncPolicy;
+use crate::copy::stats::CopyProgress;
+use crate::copy::tee::ShadowPolicy;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::copy::uring::CopyBackend;
+#[cfg(target_os = "linux")]
+use crate::copy::{
+    budget::CopyBudget,
+    file::ColdPagePolicy,
+    pipe::{PipePool, SpliceConfig},
+};
+use tokio::sync::watch;
+
+/// the default smallest buffer sent with `MSG_ZEROCOPY`
+#[cfg(target_os = "linux")]
+const ZEROCOPY_THRESHOLD: usize = 16 * 1024;
+
+/// Options of a copy made with [`copy_with`](crate::copy_with)
+/// or [`copy_bidirectional_with`](crate::copy_bidirectional_with).
+#[derive(Debug, Clone, Default)]
+pub struct CopyOptions {
+    #[cfg(target_os = "linux")]
+    pipe_pool: Option<PipePool>,
+    #[cfg(target_os = "linux")]
+    splice: SpliceConfig,
+    #[cfg(target_os = "linux")]
+    cold_pages: ColdPagePolicy,
+    #[cfg(target_os = "linux")]
+    budget: CopyBudget,
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    backend: Option<CopyBackend>,
+    #[cfg(target_os = "linux")]
+    reflink: bool,
+    progress: Option<watch::Sender<CopyProgress>>,
+    #[cfg(target_os = "linux")]
+    preallocate: bool,
+    sync: SyncPolicy,
+    shadow: ShadowPolicy,
+    capture: Option<Capture>,
+    #[cfg(target_os = "linux")]
+    zerocopy_threshold: Option<usize>,
+}
+
+impl CopyOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lease the pipes used by splice from a pool instead of creating new ones.
+    /// Leased pipes have the size configured by the pool.
+    /// This function is only available on linux platforms.
+    #[cfg(target_os = "linux")]
+    pub fn pipe_pool(mut self, pool: PipePool) -> Self {
+        self.pipe_pool = Some(pool);
+        self
+    }
+
+    /// Configure the pipes used by splice.
+    /// This function is only available on linux platforms.
+    #[cfg(target_os = "linux")]
+    pub fn splice_config(mut self, config: SpliceConfig) -> Self {
+        self.splice = config;
+        self
+    }
+
+    /// What sendfile does with file data that is not in the page cache,
+    /// by default cold data is prefetched on the blocking thread pool.
+    /// This function is only available on linux platforms.
+    #[cfg(target_os = "linux")]
+    pub fn cold_page_policy(mut self, policy: ColdPagePolicy) -> Self {
+        self.cold_pages = policy;
+        self
+    }
+
+    /// How much work the copy does in a single poll before it yields to other tasks.
+    /// The buffered copy used on other platforms relies on tokio's cooperative budget alone.
+    /// This function is only available on linux platforms.
+    #[cfg(target_os = "linux")]
+    pub fn budget(mut self, budget: CopyBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// How the copy drives its syscalls, by default [`CopyBackend::get_default`].
+    /// This function is only available on linux platforms with the `io-uring` feature.
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub fn backend(mut self, backend: CopyBackend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Try to clone the extents of the source file first when copying between files,
+    /// which shares the data on copy-on-write file systems like btrfs or xfs.
+    /// This function is only available on linux platforms.
+    #[cfg(target_os = "linux")]
+    pub fn reflink(mut self, reflink: bool) -> Self {
+        self.reflink = reflink;
+        self
+    }
+
+    /// Publish the progress of the copy while it runs.
+    /// Progress is reported by [`copy_file_to_file`](crate::copy_file_to_file) after every chunk.
+    pub fn progress(mut self, progress: watch::Sender<CopyProgress>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Reserve the blocks of the received range with fallocate before receiving into a file,
+    /// so the copy fails early if the disk is full. Only used if the length is known.
+    /// This function is only available on linux platforms.
+    #[cfg(target_os = "linux")]
+    pub fn preallocate(mut self, preallocate: bool) -> Self {
+        self.preallocate = preallocate;
+        self
+    }
+
+    /// Whether data received into a file is flushed to disk before the copy finishes,
+    /// by default it is not.
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync = policy;
+        self
+    }
+
+    /// What a tee copy does when its secondary sink cannot keep up,
+    /// by default it waits for it.
+    pub fn shadow_policy(mut self, policy: ShadowPolicy) -> Self {
+        self.shadow = policy;
+        self
+    }
+
+    /// Record the data of stream copies, in both directions of a bidirectional copy,
+    /// into a capture. Files sent with sendfile are not captured.
+    /// On linux platforms capturing always uses readiness based splice.
+    pub fn capture(mut self, capture: Capture) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// The smallest buffer sent with `MSG_ZEROCOPY` by [`send_zerocopy`](crate::send_zerocopy),
+    /// smaller buffers are cheaper to copy than to pin. Defaults to 16 KiB.
+    /// This function is only available on linux platforms.
+    #[cfg(target_os = "linux")]
+    pub fn zerocopy_threshold(mut self, threshold: usize) -> Self {
+        self.zerocopy_threshold = Some(threshold);
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn get_pipe_pool(&self) -> Option<&PipePool> {
+        self.pipe_pool.as_ref()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn get_splice_config(&self) -> &SpliceConfig {
+        &self.splice
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn get_cold_page_policy(&self) -> &ColdPagePolicy {
+        &self.cold_pages
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn get_budget(&self) -> &CopyBudget {
+        &self.budget
+    }
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    pub(crate) fn get_backend(&self) -> CopyBackend {
+        self.backend.unwrap_or_else(CopyBackend::get_default)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn get_reflink(&self) -> bool {
+        self.reflink
+    }
+
+    pub(crate) fn get_progress(&self) -> Option<&watch::Sender<CopyProgress>> {
+        self.progress.as_ref()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn get_preallocate(&self) -> bool {
+        self.preallocate
+    }
+
+    pub(crate) fn get_sync_policy(&self) -> SyncPolicy {
+        self.sync
+    }
+
+    pub(crate) fn get_shadow_policy(&self) -> ShadowPolicy {
+        self.shadow
+    }
+
+    pub(crate) fn get_capture(&self) -> Option<&Capture> {
+        self.capture.as_ref()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn get_zerocopy_threshold(&self) -> usize {
+        self.zerocopy_threshold.unwrap_or(ZEROCOPY_THRESHOLD)
+    }
+}
@@ -0,0 +1,538 @@
+use slab::Slab;
+use std::io::{Error, Result};
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// the size of PIPE_BUF
+const PIPE_SIZE: usize = 65536;
+
+/// the smallest pipe the kernel creates, one page
+const MIN_PIPE_SIZE: usize = 4096;
+
+/// the default value of /proc/sys/fs/pipe-max-size
+const DEFAULT_MAX_PIPE_SIZE: usize = 1024 * 1024;
+
+/// the number of small fills in a row after which an adaptive pipe is shrunk
+const SHRINK_AFTER_FILLS: u8 = 4;
+
+/// Linux Pipe
+#[derive(Debug)]
+#[repr(C)]
+pub(crate) struct Pipe(RawFd, RawFd);
+
+impl Pipe {
+    /// Create a pipe
+    pub(crate) fn new() -> Result<Self> {
+        let mut pipe = std::mem::MaybeUninit::<[libc::c_int; 2]>::uninit();
+        unsafe {
+            // pipe() creates a pipe, a unidirectional data channel that can be used for interprocess communication.
+            // The array pipefd is used to return two file descriptors referring to the ends of the pipe.
+            // pipefd[0] refers to the read end of the pipe.
+            // pipefd[1] refers to the write end of the pipe.
+            // Data written to the write end of the pipe is buffered by the kernel until
+            // it is read from the read end of the pipe.  For further details, see pipe(7).
+            //
+            // O_DIRECT (since Linux 3.4)
+            //    Create a pipe that performs I/O in "packet" mode.  Each write(2) to the pipe is dealt with as a separate packet, and
+            //    read(2)s from the pipe will read one packet at a time.  Note the following points:
+            //    •  Writes  of  greater than PIPE_BUF bytes (see pipe(7)) will be split into multiple packets.  The constant PIPE_BUF
+            //       is defined in <limits.h>.
+            //    •  If a read(2) specifies a buffer size that is smaller than the next packet, then the requested number of bytes are
+            //       read,  and the excess bytes in the packet are discarded.  Specifying a buffer size of PIPE_BUF will be sufficient
+            //       to read the largest possible packets (see the previous point).
+            //    •  Zero-length packets are not supported.  (A read(2) that specifies a buffer size of zero is a no-op,  and  returns
+            //         0.)
+            if libc::pipe2(
+                pipe.as_mut_ptr() as *mut libc::c_int,
+                // libc::O_DIRECT |libc::O_CLOEXEC | libc::O_NONBLOCK,
+                libc::O_CLOEXEC | libc::O_NONBLOCK,
+            ) < 0
+            {
+                return Err(Error::last_os_error());
+            }
+            let [r_fd, w_fd] = pipe.assume_init();
+            Ok(Pipe(r_fd, w_fd))
+        }
+    }
+
+    /// Create a pipe and try to resize it, returning the pipe with the size granted by the kernel.
+    pub(crate) fn with_size(size: usize) -> Result<(Self, usize)> {
+        let pipe = Self::new()?;
+        // the kernel refuses to grow the pipe once the user's pipe buffer limit is reached,
+        // the pipe is still usable with its default size
+        let size = match pipe.set_size(size) {
+            Ok(size) => size,
+            Err(_) => pipe.size()?,
+        };
+        Ok((pipe, size))
+    }
+
+    /// Resize the pipe, returning the size granted by the kernel.
+    /// The kernel rounds the size up to a power of two pages.
+    pub(crate) fn set_size(&self, size: usize) -> Result<usize> {
+        set_pipe_size(self.1, size)
+    }
+
+    /// The size of the pipe.
+    pub(crate) fn size(&self) -> Result<usize> {
+        match unsafe { libc::fcntl(self.1, libc::F_GETPIPE_SZ) } {
+            -1 => Err(Error::last_os_error()),
+            size => Ok(size as usize),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn read_fd(&self) -> RawFd {
+        self.0
+    }
+
+    #[inline]
+    pub(crate) fn write_fd(&self) -> RawFd {
+        self.1
+    }
+
+    /// Whether there are no bytes left in the pipe.
+    fn is_drained(&self) -> bool {
+        let mut n: libc::c_int = 0;
+        unsafe { libc::ioctl(self.0, libc::FIONREAD, &mut n) == 0 && n == 0 }
+    }
+}
+
+/// Resize the pipe with the given write end, returning the size granted by the kernel.
+fn set_pipe_size(fd: RawFd, size: usize) -> Result<usize> {
+    match unsafe { libc::fcntl(fd, libc::F_SETPIPE_SZ, size as libc::c_int) } {
+        -1 => Err(Error::last_os_error()),
+        size => Ok(size as usize),
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+            libc::close(self.1);
+        }
+    }
+}
+
+/// How splice copies size their pipes.
+///
+/// The pipe size limits how many bytes a single splice moves,
+/// bigger pipes need fewer syscalls for bulk transfers but use more kernel memory.
+/// Sizes are clamped to `/proc/sys/fs/pipe-max-size`, the size actually granted
+/// by the kernel is reported in [`CopyStats::pipe_size`](crate::CopyStats::pipe_size).
+///
+/// In adaptive mode the pipe is grown while the source keeps filling it
+/// and shrunk while only small chunks arrive, e.g. for interactive traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpliceConfig {
+    pipe_size: usize,
+    adaptive: Option<(usize, usize)>,
+}
+
+impl Default for SpliceConfig {
+    fn default() -> Self {
+        Self {
+            pipe_size: PIPE_SIZE,
+            adaptive: None,
+        }
+    }
+}
+
+impl SpliceConfig {
+    /// Create the default config, a fixed 64 KiB pipe.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The size of new pipes in bytes.
+    pub fn pipe_size(mut self, size: usize) -> Self {
+        self.pipe_size = clamp_pipe_size(size);
+        self
+    }
+
+    /// Grow and shrink the pipe between `min` and `max` bytes depending on the traffic.
+    pub fn adaptive(mut self, min: usize, max: usize) -> Self {
+        let min = clamp_pipe_size(min);
+        self.adaptive = Some((min, clamp_pipe_size(max).max(min)));
+        self
+    }
+
+    /// The maximum size of a pipe an unprivileged process can request, read from `/proc/sys/fs/pipe-max-size`.
+    pub fn max_pipe_size() -> usize {
+        static MAX_PIPE_SIZE: OnceLock<usize> = OnceLock::new();
+        *MAX_PIPE_SIZE.get_or_init(|| {
+            std::fs::read_to_string("/proc/sys/fs/pipe-max-size")
+                .ok()
+                .and_then(|size| size.trim().parse().ok())
+                .unwrap_or(DEFAULT_MAX_PIPE_SIZE)
+        })
+    }
+
+    pub(crate) fn get_pipe_size(&self) -> usize {
+        self.pipe_size
+    }
+
+    pub(crate) fn get_adaptive(&self) -> Option<(usize, usize)> {
+        self.adaptive
+    }
+}
+
+fn clamp_pipe_size(size: usize) -> usize {
+    size.clamp(MIN_PIPE_SIZE, SpliceConfig::max_pipe_size())
+}
+
+/// A pool of pipes reused by splice copies.
+///
+/// Creating a pipe costs a `pipe2` and a `fcntl` syscall and two file descriptors per copy.
+/// The pool hands out pre-sized pipes and takes them back once the copy is done,
+/// as long as they were fully drained. Pipes that still hold data are closed instead.
+///
+/// The pool owns at most `max_pipes` pipes and at most `max_memory` bytes of pipe buffers.
+/// When all of them are in use, copies wait until a pipe is returned, unless the pool allows
+/// temporary pipes with [`overflow`](PipePool::overflow). Copies needing more pipes
+/// than the pool can ever hand out, e.g. bidirectional copies from a pool of one pipe, fail.
+///
+/// The pool is cheap to clone and can be shared across tasks.
+/// Pass it to a copy with [`CopyOptions::pipe_pool`](crate::CopyOptions::pipe_pool).
+#[derive(Debug, Clone)]
+pub struct PipePool {
+    inner: Arc<Mutex<PoolInner>>,
+    /// One permit for every pipe a copy may lease, pooled or temporary.
+    permits: Arc<Semaphore>,
+}
+
+#[derive(Debug)]
+struct PoolInner {
+    /// The pooled pipes with the size granted when they were created.
+    pipes: Slab<(Pipe, usize)>,
+    idle: Vec<usize>,
+    pipe_size: usize,
+    max_pipes: usize,
+    overflow: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl PipePool {
+    /// Create an empty pool of 64 KiB pipes owning at most `max_pipes` pipes and `max_memory` bytes of pipe buffers.
+    /// Every pipe uses two file descriptors. A `max_memory` below the size of one pipe still allows one pipe.
+    pub fn new(max_pipes: usize, max_memory: usize) -> Self {
+        Self::with_config(max_pipes, max_memory, SpliceConfig::default())
+    }
+
+    /// Create an empty pool of pipes sized according to the config.
+    /// Pipes resized by adaptive copies are restored to this size when they are returned.
+    pub fn with_config(max_pipes: usize, max_memory: usize, config: SpliceConfig) -> Self {
+        // the kernel rounds pipe sizes up to a power of two pages
+        let pipe_size = config.get_pipe_size().next_power_of_two();
+        // the memory limit always allows one pipe, so copies never wait for a pipe the pool cannot create
+        let max_pipes = max_pipes.min((max_memory / pipe_size).max(1));
+        Self {
+            inner: Arc::new(Mutex::new(PoolInner {
+                pipes: Slab::new(),
+                idle: Vec::new(),
+                pipe_size,
+                max_pipes,
+                overflow: 0,
+                hits: 0,
+                misses: 0,
+            })),
+            permits: Arc::new(Semaphore::new(max_pipes)),
+        }
+    }
+
+    /// Allow up to `pipes` temporary pipes on top of the pooled ones while all pooled pipes are in use,
+    /// they are closed once their copy is done. By default there are none and copies wait for a pooled pipe.
+    /// Meant to be set when the pool is created, before any copy uses it.
+    pub fn overflow(self, pipes: usize) -> Self {
+        {
+            let mut inner = self.lock();
+            if pipes > inner.overflow {
+                self.permits.add_permits(pipes - inner.overflow);
+            } else {
+                self.permits.forget_permits(inner.overflow - pipes);
+            }
+            inner.overflow = pipes;
+        }
+        self
+    }
+
+    /// Create up to `n` pipes ahead of time, so the first copies do not have to.
+    pub fn prefill(&self, n: usize) -> Result<()> {
+        let mut inner = self.lock();
+        while inner.idle.len() < n && inner.pipes.len() < inner.max_pipes {
+            let pipe = Pipe::with_size(inner.pipe_size)?;
+            let key = inner.pipes.insert(pipe);
+            inner.idle.push(key);
+        }
+        Ok(())
+    }
+
+    /// The maximum number of pipes owned by the pool.
+    pub fn max_pipes(&self) -> usize {
+        self.lock().max_pipes
+    }
+
+    /// The maximum number of temporary pipes, see [`overflow`](PipePool::overflow).
+    pub fn max_overflow(&self) -> usize {
+        self.lock().overflow
+    }
+
+    /// The number of pipes waiting to be reused.
+    pub fn idle(&self) -> usize {
+        self.lock().idle.len()
+    }
+
+    /// The number of pooled pipes currently used by copies.
+    pub fn in_use(&self) -> usize {
+        let inner = self.lock();
+        inner.pipes.len() - inner.idle.len()
+    }
+
+    /// How many times a copy reused an idle pipe.
+    pub fn hits(&self) -> u64 {
+        self.lock().hits
+    }
+
+    /// How many times a copy had to create a new pipe, pooled or temporary.
+    pub fn misses(&self) -> u64 {
+        self.lock().misses
+    }
+
+    /// Leases `n` pipes at once, waiting until that many are available.
+    /// Taking them together keeps copies which need several pipes from starving each other.
+    pub(crate) async fn acquire(&self, n: usize) -> Result<Vec<SplicePipe>> {
+        let capacity = {
+            let inner = self.lock();
+            inner.max_pipes + inner.overflow
+        };
+        if n > capacity {
+            return Err(Error::other(format!(
+                "the copy needs {n} pipes, but the pipe pool hands out at most {capacity}"
+            )));
+        }
+        // the semaphore is never closed
+        let mut permit = self
+            .permits
+            .clone()
+            .acquire_many_owned(n as u32)
+            .await
+            .map_err(Error::other)?;
+        let mut pipes = Vec::with_capacity(n);
+        for _ in 0..n {
+            let permit = permit.split(1).expect("a permit for every pipe");
+            pipes.push(self.lease(permit)?);
+        }
+        Ok(pipes)
+    }
+
+    /// Hands out an idle pipe, a new pooled one or, if the pool is full, a temporary one.
+    fn lease(&self, permit: OwnedSemaphorePermit) -> Result<SplicePipe> {
+        let mut inner = self.lock();
+        let key = match inner.idle.pop() {
+            Some(key) => {
+                inner.hits += 1;
+                key
+            }
+            // the permit guarantees that this is within the overflow
+            None if inner.pipes.len() >= inner.max_pipes => {
+                let mut pipe = SplicePipe::owned(inner.pipe_size)?;
+                inner.misses += 1;
+                pipe.permit = Some(permit);
+                return Ok(pipe);
+            }
+            None => {
+                let pipe = Pipe::with_size(inner.pipe_size)?;
+                inner.misses += 1;
+                inner.pipes.insert(pipe)
+            }
+        };
+        let (pipe, size) = &inner.pipes[key];
+        Ok(SplicePipe {
+            lease: Lease::Pooled {
+                read_fd: pipe.read_fd(),
+                write_fd: pipe.write_fd(),
+                key,
+                pool: self.clone(),
+            },
+            size: *size,
+            permit: Some(permit),
+            poisoned: false,
+        })
+    }
+
+    fn release(&self, key: usize, size: usize, poisoned: bool) {
+        let mut inner = self.lock();
+        let (pipe, granted) = &inner.pipes[key];
+        // restore the size granted when the pipe was created, shrinking fails while the pipe
+        // holds more data than fits, such a pipe is not reused anyway
+        let restored =
+            size == *granted || pipe.set_size(*granted).is_ok_and(|size| size == *granted);
+        let reusable = !poisoned && restored && pipe.is_drained();
+        if reusable {
+            inner.idle.push(key);
+        } else {
+            inner.pipes.remove(key);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolInner> {
+        // the pool is never left in an inconsistent state, so a poisoned lock is still usable
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// The pipe used by a single splice copy, either owned by it or leased from a [`PipePool`].
+pub(crate) struct SplicePipe {
+    lease: Lease,
+    size: usize,
+    /// The pool's permit for the pipe, released after the pipe is returned or closed.
+    permit: Option<OwnedSemaphorePermit>,
+    /// Whether the kernel may still use the pipe, so it must not be reused.
+    poisoned: bool,
+}
+
+enum Lease {
+    Owned(Pipe),
+    Pooled {
+        read_fd: RawFd,
+        write_fd: RawFd,
+        key: usize,
+        pool: PipePool,
+    },
+}
+
+impl SplicePipe {
+    /// Lease a pipe from the pool if given, otherwise create a new one of the configured size.
+    pub(crate) async fn new(pool: Option<&PipePool>, config: &SpliceConfig) -> Result<Self> {
+        let [pipe] = Self::many(pool, config).await?;
+        Ok(pipe)
+    }
+
+    /// Lease two pipes from the pool at once if given, otherwise create two new ones of the configured size.
+    pub(crate) async fn pair(
+        pool: Option<&PipePool>,
+        config: &SpliceConfig,
+    ) -> Result<(Self, Self)> {
+        let [a, b] = Self::many(pool, config).await?;
+        Ok((a, b))
+    }
+
+    async fn many<const N: usize>(
+        pool: Option<&PipePool>,
+        config: &SpliceConfig,
+    ) -> Result<[Self; N]> {
+        let pipes = match pool {
+            Some(pool) => pool.acquire(N).await?,
+            None => (0..N)
+                .map(|_| Self::owned(config.get_pipe_size()))
+                .collect::<Result<_>>()?,
+        };
+        Ok(pipes.try_into().unwrap_or_else(|_| unreachable!()))
+    }
+
+    fn owned(size: usize) -> Result<Self> {
+        let (pipe, size) = Pipe::with_size(size)?;
+        Ok(Self {
+            lease: Lease::Owned(pipe),
+            size,
+            permit: None,
+            poisoned: false,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn read_fd(&self) -> RawFd {
+        match &self.lease {
+            Lease::Owned(pipe) => pipe.read_fd(),
+            Lease::Pooled { read_fd, .. } => *read_fd,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn write_fd(&self) -> RawFd {
+        match &self.lease {
+            Lease::Owned(pipe) => pipe.write_fd(),
+            Lease::Pooled { write_fd, .. } => *write_fd,
+        }
+    }
+
+    /// The size of the pipe granted by the kernel.
+    #[inline]
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Resize the pipe, returning the size granted by the kernel.
+    pub(crate) fn set_size(&mut self, size: usize) -> Result<usize> {
+        self.size = set_pipe_size(self.write_fd(), size)?;
+        Ok(self.size)
+    }
+
+    /// Close the pipe on drop instead of returning it to the pool,
+    /// e.g. because an operation which could not be cancelled still uses it.
+    #[cfg(feature = "io-uring")]
+    pub(crate) fn poison(&mut self) {
+        self.poisoned = true;
+    }
+}
+
+impl Drop for SplicePipe {
+    fn drop(&mut self) {
+        if let Lease::Pooled { key, pool, .. } = &self.lease {
+            pool.release(*key, self.size, self.poisoned);
+        }
+    }
+}
+
+/// Resizes the pipe of a splice copy in adaptive mode, see [`SpliceConfig::adaptive`].
+pub(crate) struct AdaptiveSize {
+    /// The bounds of the pipe size, `None` if the size is fixed.
+    bounds: Option<(usize, usize)>,
+    /// How many fills in a row used only a small part of the pipe.
+    small_fills: u8,
+}
+
+impl AdaptiveSize {
+    pub(crate) fn new(config: &SpliceConfig) -> Self {
+        Self {
+            bounds: config.get_adaptive(),
+            small_fills: 0,
+        }
+    }
+
+    /// Grows the pipe while the source fills at least half of it and shrinks it
+    /// after a few fills which used less than a quarter of it.
+    /// `filled` is the number of bytes the last fill moved into the pipe.
+    /// Must only be called while the pipe is empty.
+    pub(crate) fn adapt(&mut self, pipe: &mut SplicePipe, filled: usize) {
+        let Some((min, max)) = self.bounds else {
+            return;
+        };
+        // nothing has been read since the last call, e.g. the previous fill was pending
+        if filled == 0 {
+            return;
+        }
+        let size = pipe.size();
+        let target = if filled >= size / 2 {
+            self.small_fills = 0;
+            (size * 2).min(max)
+        } else if filled < size / 4 {
+            self.small_fills += 1;
+            if self.small_fills < SHRINK_AFTER_FILLS {
+                return;
+            }
+            self.small_fills = 0;
+            (size / 2).max(min)
+        } else {
+            self.small_fills = 0;
+            return;
+        };
+        if target != size && pipe.set_size(target).is_err() {
+            // the user's pipe buffer limit is reached, keep the current size
+            self.bounds = Some((min.min(size), size));
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// The mechanism used to move the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CopyMechanism {
+    /// Zero-copy splice through a pipe.
+    Splice,
+    /// Zero-copy sendfile from a file.
+    SendFile,
+    /// Copy through a userspace buffer, used on non-linux platforms
+    /// and for buffers too small to be sent without copying.
+    Buffered,
+    /// Zero-copy splice operations submitted to an io_uring, used with the `io-uring` feature.
+    IoUring,
+    /// In-kernel copy between two files with copy_file_range.
+    CopyFileRange,
+    /// The destination file shares the extents of the source file, nothing is copied.
+    Reflink,
+    /// Zero-copy send of a userspace buffer with `MSG_ZEROCOPY`.
+    ZeroCopySend,
+}
+
+/// Statistics of a finished copy.
+///
+/// New copy mechanisms add new counters, so the statistics can only be created by the copies.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct CopyStats {
+    /// The number of bytes moved to the sink.
+    pub bytes: usize,
+    /// The mechanism used to move the data.
+    pub mechanism: CopyMechanism,
+    /// The number of splice syscalls, or splice operations submitted to an io_uring.
+    pub splice_calls: usize,
+    /// The number of sendfile syscalls.
+    pub sendfile_calls: usize,
+    /// The number of copy_file_range syscalls.
+    pub copy_file_range_calls: usize,
+    /// The number of read calls of a buffered copy.
+    pub read_calls: usize,
+    /// The number of write calls of a buffered copy, or of sendmsg calls.
+    pub write_calls: usize,
+    /// How many times a syscall failed with EAGAIN.
+    pub would_block: usize,
+    /// How many times the copy yielded to other tasks because its budget was spent.
+    pub yields: usize,
+    /// The number of file windows which were not in the page cache and had to be prefetched.
+    pub cold_chunks: usize,
+    /// The number of zero-copy sends the kernel completed by copying the data anyway,
+    /// e.g. over loopback.
+    pub zerocopy_copied: usize,
+    /// The size of the splice pipe granted by the kernel when the copy finished.
+    pub pipe_size: Option<usize>,
+    /// When the copy started.
+    pub started_at: DateTime<Utc>,
+    /// When the copy finished.
+    pub finished_at: DateTime<Utc>,
+}
+
+impl CopyStats {
+    pub(crate) fn new(mechanism: CopyMechanism) -> Self {
+        let now = Utc::now();
+        Self {
+            bytes: 0,
+            mechanism,
+            splice_calls: 0,
+            sendfile_calls: 0,
+            copy_file_range_calls: 0,
+            read_calls: 0,
+            write_calls: 0,
+            would_block: 0,
+            yields: 0,
+            cold_chunks: 0,
+            zerocopy_copied: 0,
+            pipe_size: None,
+            started_at: now,
+            finished_at: now,
+        }
+    }
+
+    /// The number of syscalls made so far.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn syscalls(&self) -> usize {
+        self.splice_calls
+            + self.sendfile_calls
+            + self.copy_file_range_calls
+            + self.read_calls
+            + self.write_calls
+    }
+
+    /// Marks the copy as finished now.
+    pub(crate) fn finish(mut self) -> Self {
+        self.finished_at = Utc::now();
+        self
+    }
+
+    /// How long the copy took.
+    pub fn duration(&self) -> chrono::Duration {
+        self.finished_at - self.started_at
+    }
+}
+
+/// The progress of a running copy, see [`CopyOptions::progress`](crate::CopyOptions::progress).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CopyProgress {
+    /// The number of bytes copied so far.
+    pub bytes: usize,
+    /// The number of bytes left to copy.
+    pub remaining: usize,
+}
@@ -1,28 +1,122 @@
 mod zero_copy;
 
+use crate::copy::endpoint::{ZeroCopySink, ZeroCopySource};
+use crate::copy::error::CopyResult;
+use crate::copy::options::CopyOptions;
+use crate::copy::stats::{CopyMechanism, CopyStats};
+#[cfg(feature = "io-uring")]
+use crate::copy::uring;
 use essentials::debug;
-use tokio::net::tcp::OwnedReadHalf;
-use tokio::{io, net::tcp::OwnedWriteHalf};
-use zero_copy::zero_copy_unidirectional;
+use zero_copy::{
+    zero_copy, zero_copy_bidirectional, zero_copy_unidirectional, zero_copy_with_prefix, Prefix,
+};
 
-/// Copy data from a file to a write half.
-/// This function is only available on linux platforms and uses sendfile.
-pub async fn copy<'a>(r: &'a mut OwnedReadHalf, w: &'a mut OwnedWriteHalf) -> io::Result<usize> {
+/// Copy data from a source to a sink and shut the sink down afterwards.
+/// This function is only available on linux platforms and uses splice.
+pub async fn copy<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    #[cfg(feature = "io-uring")]
+    if let Some(ring) = uring::ring(options) {
+        debug!("copying tcp stream using io_uring splice");
+        return uring::copy(ring, r, w, None, options).await;
+    }
     debug!("copying tcp stream using splice");
-    Ok(zero_copy_unidirectional(r, w, None).await? as usize)
+    zero_copy_unidirectional(r, w, None, options).await
 }
 
-/// Copy data from a file to a write half.
-/// This function is only available on linux platforms and uses sendfile.
-pub async fn copy_exact<'a>(
-    r: &'a mut OwnedReadHalf,
-    w: &'a mut OwnedWriteHalf,
+/// Copy exactly `length` bytes from a source to a sink and shut the sink down afterwards.
+/// This function is only available on linux platforms and uses splice.
+pub async fn copy_exact<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
     length: usize,
-) -> io::Result<usize> {
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
     debug!("copying tcp stream using splice");
     if length == 0 {
-        return Ok(0);
+        return Ok(CopyStats::new(CopyMechanism::Splice));
     };
-    debug!("copying tcp stream using splice");
-    Ok(zero_copy_unidirectional(r, w, Some(length as u64)).await? as usize)
+    #[cfg(feature = "io-uring")]
+    if let Some(ring) = uring::ring(options) {
+        debug!("copying tcp stream using io_uring splice");
+        return uring::copy(ring, r, w, Some(length), options).await;
+    }
+    zero_copy_unidirectional(r, w, Some(length as u64), options).await
+}
+
+/// Copy data from a source to a sink, the sink is not shut down.
+/// This function is only available on linux platforms and uses splice.
+pub async fn splice<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    #[cfg(feature = "io-uring")]
+    if let Some(ring) = uring::ring(options) {
+        debug!("copying using io_uring splice");
+        return uring::splice(ring, r, w, None, length, options).await;
+    }
+    debug!("copying using splice");
+    zero_copy(r, w, length.map(|length| length as u64), options).await
+}
+
+/// Copy a prefix and then data from a source to a sink, the sink is not shut down.
+/// Copies `length` bytes of the source if given, otherwise until EOF.
+/// This function is only available on linux platforms and uses a pipe write and splice.
+pub async fn splice_with_prefix<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    prefix: &[u8],
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    debug!("copying prefix using a pipe write and stream using splice");
+    zero_copy_with_prefix(
+        r,
+        w,
+        Prefix::new(prefix),
+        length.map(|length| length as u64),
+        options,
+    )
+    .await
+}
+
+/// Copy data in both directions between two streams.
+/// This function is only available on linux platforms and uses splice.
+pub async fn copy_bidirectional<'a, A, B>(
+    a: &'a mut A,
+    b: &'a mut B,
+    options: &CopyOptions,
+) -> CopyResult<(CopyStats, CopyStats)>
+where
+    A: ZeroCopySource + ZeroCopySink,
+    B: ZeroCopySource + ZeroCopySink,
+{
+    #[cfg(feature = "io-uring")]
+    if let Some((forward, backward)) = uring::ring(options).zip(uring::ring(options)) {
+        debug!("copying tcp streams bidirectionally using io_uring splice");
+        return uring::copy_bidirectional(forward, backward, a, b, options).await;
+    }
+    debug!("copying tcp streams bidirectionally using splice");
+    zero_copy_bidirectional(a, b, options).await
 }
@@ -1,13 +1,16 @@
+use crate::copy::budget::{CopyBudget, PollBudget};
+use crate::copy::capture::{CaptureDirection, CaptureTap};
+use crate::copy::endpoint::{ZeroCopySink, ZeroCopySource};
+use crate::copy::error::{CopyError, CopyPhase, CopyResult, IoResultExt};
+use crate::copy::options::CopyOptions;
+use crate::copy::pipe::{AdaptiveSize, SpliceConfig, SplicePipe};
+use crate::copy::stats::{CopyMechanism, CopyStats};
 use std::future::poll_fn;
 use std::io::{Error, ErrorKind, Result};
 use std::marker::PhantomData;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::RawFd;
 use std::pin::Pin;
 use std::task::{ready, Context, Poll};
-use tokio::io::{AsyncRead, AsyncWrite, Interest};
-
-/// the size of PIPE_BUF
-const PIPE_SIZE: usize = 65536;
 
 /// splice()  moves  data between two file descriptors without copying between kernel address space and user address space.
 /// It transfers up to len bytes of data from the file descriptor fd_in to the file descriptor fd_out,
@@ -52,88 +55,82 @@ fn splice(fd_in: RawFd, fd_out: RawFd, size: usize) -> isize {
     }
 }
 
-/// Linux Pipe
-#[repr(C)]
-struct Pipe(RawFd, RawFd);
-
-impl Pipe {
-    /// Create a pipe
-    fn new() -> Result<Self> {
-        let mut pipe = std::mem::MaybeUninit::<[libc::c_int; 2]>::uninit();
-        unsafe {
-            // pipe() creates a pipe, a unidirectional data channel that can be used for interprocess communication.
-            // The array pipefd is used to return two file descriptors referring to the ends of the pipe.
-            // pipefd[0] refers to the read end of the pipe.
-            // pipefd[1] refers to the write end of the pipe.
-            // Data written to the write end of the pipe is buffered by the kernel until
-            // it is read from the read end of the pipe.  For further details, see pipe(7).
-            //
-            // O_DIRECT (since Linux 3.4)
-            //    Create a pipe that performs I/O in "packet" mode.  Each write(2) to the pipe is dealt with as a separate packet, and
-            //    read(2)s from the pipe will read one packet at a time.  Note the following points:
-            //    •  Writes  of  greater than PIPE_BUF bytes (see pipe(7)) will be split into multiple packets.  The constant PIPE_BUF
-            //       is defined in <limits.h>.
-            //    •  If a read(2) specifies a buffer size that is smaller than the next packet, then the requested number of bytes are
-            //       read,  and the excess bytes in the packet are discarded.  Specifying a buffer size of PIPE_BUF will be sufficient
-            //       to read the largest possible packets (see the previous point).
-            //    •  Zero-length packets are not supported.  (A read(2) that specifies a buffer size of zero is a no-op,  and  returns
-            //         0.)
-            if libc::pipe2(
-                pipe.as_mut_ptr() as *mut libc::c_int,
-                // libc::O_DIRECT |libc::O_CLOEXEC | libc::O_NONBLOCK,
-                libc::O_CLOEXEC | libc::O_NONBLOCK,
-            ) < 0
-            {
-                return Err(Error::last_os_error());
-            }
-            let [r_fd, w_fd] = pipe.assume_init();
-            libc::fcntl(w_fd, libc::F_SETPIPE_SZ, PIPE_SIZE);
-            Ok(Pipe(r_fd, w_fd))
-        }
+/// Calls splice and converts its result into an io::Result.
+/// EAGAIN is reported as ErrorKind::WouldBlock, so it can be returned from try_io closures.
+#[inline]
+fn try_splice(fd_in: RawFd, fd_out: RawFd, size: usize) -> Result<usize> {
+    match splice(fd_in, fd_out, size) {
+        size if size >= 0 => Ok(size as usize),
+        _ => Err(Error::last_os_error()),
     }
+}
 
-    #[inline]
-    fn read_fd(&self) -> RawFd {
-        self.0
+/// Copies a userspace buffer into a pipe.
+///
+/// vmsplice() would map the pages of the buffer into the pipe instead, but a tcp socket keeps
+/// referencing spliced pages until the peer acknowledges them. A retransmission after the buffer
+/// has been released and its memory reused would then carry different bytes.
+#[inline]
+fn try_write(fd: RawFd, buf: &[u8]) -> Result<usize> {
+    match unsafe { libc::write(fd, buf.as_ptr().cast(), buf.len()) } {
+        -1 => Err(Error::last_os_error()),
+        size => Ok(size as usize),
     }
+}
 
-    #[inline]
-    fn write_fd(&self) -> RawFd {
-        self.1
-    }
+/// A userspace buffer written into the pipe ahead of the data of the source.
+pub(crate) struct Prefix<'a> {
+    buf: &'a [u8],
+    pos: usize,
 }
 
-impl Drop for Pipe {
-    fn drop(&mut self) {
-        unsafe {
-            libc::close(self.0);
-            libc::close(self.1);
-        }
+impl<'a> Prefix<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// The bytes which have not been written into the pipe yet.
+    fn remaining(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+
+    fn is_written(&self) -> bool {
+        self.pos == self.len()
     }
 }
 
-struct CopyBuffer<R, RInner, W, WInner> {
+struct CopyBuffer<'p, R, W> {
     read_done: bool,
     need_flush: bool,
     pos: usize,
     cap: usize,
     amt: u64,
-    buf: Pipe,
+    buf: SplicePipe,
+    stats: CopyStats,
+    adaptive: AdaptiveSize,
+    budget: CopyBudget,
+    capture: Option<CaptureTap>,
+    prefix: Option<Prefix<'p>>,
     //
     _marker_r: PhantomData<R>,
-    _marker_r_inner: PhantomData<RInner>,
     _marker_w: PhantomData<W>,
-    _marker_w_inner: PhantomData<WInner>,
 }
 
-impl<R, RInner, W, WInner> CopyBuffer<R, RInner, W, WInner>
+impl<R, W> CopyBuffer<'_, R, W>
 where
-    RInner: AsRawFd,
-    R: Stream + AsyncRead + AsRef<RInner> + Unpin,
-    WInner: AsRawFd,
-    W: Stream + AsyncWrite + AsRef<WInner> + Unpin,
+    R: ZeroCopySource,
+    W: ZeroCopySink,
 {
-    fn new(buf: Pipe) -> Self {
+    fn new(
+        buf: SplicePipe,
+        config: &SpliceConfig,
+        budget: CopyBudget,
+        capture: Option<CaptureTap>,
+    ) -> Self {
         Self {
             read_done: false,
             need_flush: false,
@@ -141,13 +138,21 @@ where
             cap: 0,
             amt: 0,
             buf,
+            stats: CopyStats::new(CopyMechanism::Splice),
+            adaptive: AdaptiveSize::new(config),
+            budget,
+            capture,
+            prefix: None,
             _marker_r: PhantomData,
-            _marker_r_inner: PhantomData,
             _marker_w: PhantomData,
-            _marker_w_inner: PhantomData,
         }
     }
 
+    /// The number of bytes of the prefix, which are copied before the data of the source.
+    fn prefix_len(&self) -> u64 {
+        self.prefix.as_ref().map_or(0, |prefix| prefix.len() as u64)
+    }
+
     fn poll_fill_buf(
         &mut self,
         cx: &mut Context<'_>,
@@ -157,23 +162,13 @@ where
         loop {
             ready!(stream.poll_read_ready_n(cx))?;
 
-            let res = stream.try_io_n(Interest::READABLE, || {
-                match splice(
-                    stream.as_ref().as_raw_fd(),
+            let res = stream.try_read_io_n(|| {
+                self.stats.splice_calls += 1;
+                try_splice(
+                    stream.source_fd(),
                     self.buf.write_fd(),
                     amount.map_or(isize::MAX as usize, |amount| amount as usize),
-                ) {
-                    size if size >= 0 => Ok(size as usize),
-                    _ => {
-                        let err = Error::last_os_error();
-                        match err.raw_os_error() {
-                            Some(e) if e == libc::EWOULDBLOCK || e == libc::EAGAIN => {
-                                Err(ErrorKind::WouldBlock.into())
-                            }
-                            _ => Err(err),
-                        }
-                    }
-                }
+                )
             });
 
             match res {
@@ -191,50 +186,35 @@ where
                     self.cap = size;
                     return Poll::Ready(res);
                 }
-                Err(e) => {
-                    if e.kind() == ErrorKind::WouldBlock {
-                        continue;
-                        // return Poll::Pending;
-                    }
-
-                    return Poll::Ready(Err(e));
+                // try_io has cleared the read readiness, so polling it again
+                // registers the waker and yields until new data arrives.
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.stats.would_block += 1;
+                    continue;
                 }
+                Err(e) => return Poll::Ready(Err(e)),
             }
         }
     }
 
     fn poll_write_buf(&mut self, cx: &mut Context<'_>, stream: &mut W) -> Poll<Result<usize>> {
         loop {
-            ready!(stream.poll_write_ready_n(cx)?);
-
-            let res = stream.try_io_n(Interest::WRITABLE, || {
-                match splice(
-                    self.buf.read_fd(),
-                    stream.as_ref().as_raw_fd(),
-                    self.cap - self.pos,
-                ) {
-                    size if size >= 0 => Ok(size as usize),
-                    _ => {
-                        let err = Error::last_os_error();
-                        match err.raw_os_error() {
-                            Some(e) if e == libc::EWOULDBLOCK || e == libc::EAGAIN => {
-                                Err(ErrorKind::WouldBlock.into())
-                            }
-                            _ => Err(err),
-                        }
-                    }
-                }
+            ready!(stream.poll_write_ready_n(cx))?;
+
+            let res = stream.try_write_io_n(|| {
+                self.stats.splice_calls += 1;
+                try_splice(self.buf.read_fd(), stream.sink_fd(), self.cap - self.pos)
             });
 
             match res {
                 Ok(_) => return Poll::Ready(res),
-                Err(e) => {
-                    if e.kind() == ErrorKind::WouldBlock {
-                        continue;
-                    }
-
-                    return Poll::Ready(Err(e));
+                // try_io has cleared the write readiness, so polling it again
+                // registers the waker and yields until the socket drains.
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    self.stats.would_block += 1;
+                    continue;
                 }
+                Err(e) => return Poll::Ready(Err(e)),
             }
         }
     }
@@ -242,14 +222,18 @@ where
     fn poll_flush_buf(&mut self, cx: &mut Context<'_>, stream: &mut W) -> Poll<Result<()>> {
         Pin::new(stream).poll_flush(cx)
     }
+
+    fn finish(&mut self) -> CopyStats {
+        self.stats.bytes = self.amt as usize;
+        self.stats.pipe_size = Some(self.buf.size());
+        self.stats.clone().finish()
+    }
 }
 
-impl<R, RInner, W, WInner> CopyBuffer<R, RInner, W, WInner>
+impl<R, W> CopyBuffer<'_, R, W>
 where
-    RInner: AsRawFd,
-    R: Stream + AsyncRead + AsRef<RInner> + Unpin,
-    WInner: AsRawFd,
-    W: Stream + AsyncWrite + AsRef<WInner> + Unpin,
+    R: ZeroCopySource,
+    W: ZeroCopySink,
 {
     fn poll_copy(
         &mut self,
@@ -257,48 +241,89 @@ where
         r: &mut R,
         w: &mut W,
         amount: Option<u64>,
-    ) -> Poll<Result<u64>> {
-        if amount.is_some_and(|amount| amount == 0) {
-            return Poll::Ready(Ok(0));
+    ) -> Poll<CopyResult<CopyStats>> {
+        if amount.is_some_and(|amount| amount == 0) && self.prefix_len() == 0 {
+            return Poll::Ready(Ok(self.finish()));
         }
+        let budget = PollBudget::start(self.budget, &self.stats);
         loop {
+            let coop = ready!(budget.poll_proceed(cx, &mut self.stats));
+
             // If our buffer is empty, then we need to read some data to
             // continue.
             if self.pos == self.cap && !self.read_done {
+                // everything has been written, so the pipe is empty and can be resized
+                self.adaptive.adapt(&mut self.buf, self.cap);
                 self.pos = 0;
                 self.cap = 0;
 
-                match self.poll_fill_buf(cx, r, amount) {
-                    Poll::Ready(Ok(_)) => (),
-                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
-                    Poll::Pending => {
-                        // Try flushing when the reader has no progress to avoid deadlock
-                        // when the reader depends on buffered writer.
-                        if self.need_flush {
-                            ready!(self.poll_flush_buf(cx, w))?;
-                            self.need_flush = false;
+                let size = match self.prefix.as_mut().filter(|prefix| !prefix.is_written()) {
+                    // the pipe is empty, so the prefix is written without blocking
+                    Some(prefix) => {
+                        self.stats.write_calls += 1;
+                        let size = try_write(self.buf.write_fd(), prefix.remaining())
+                            .in_phase(CopyPhase::Prefix, self.amt as usize)?;
+                        prefix.pos += size;
+                        self.cap = size;
+                        if prefix.is_written() && amount.is_some_and(|amount| amount == 0) {
+                            self.read_done = true;
+                        }
+                        size
+                    }
+                    None => {
+                        // everything read so far has been written, so amt is the amount read
+                        let remaining = amount.map(|amount| amount + self.prefix_len() - self.amt);
+                        match self.poll_fill_buf(cx, r, remaining) {
+                            Poll::Ready(Ok(size)) => size,
+                            Poll::Ready(Err(err)) => {
+                                return Poll::Ready(Err(CopyError::new(
+                                    CopyPhase::SpliceIn,
+                                    self.amt as usize,
+                                    err,
+                                )))
+                            }
+                            Poll::Pending => {
+                                // Try flushing when the reader has no progress to avoid deadlock
+                                // when the reader depends on buffered writer.
+                                if self.need_flush {
+                                    ready!(self.poll_flush_buf(cx, w))
+                                        .in_phase(CopyPhase::Flush, self.amt as usize)?;
+                                    self.need_flush = false;
+                                }
+
+                                return Poll::Pending;
+                            }
                         }
-
-                        return Poll::Pending;
                     }
                 };
+                // the data is recorded before it is spliced out of the pipe
+                if let Some(capture) = self.capture.as_mut().filter(|_| size > 0) {
+                    capture
+                        .record_pipe(self.buf.read_fd(), size)
+                        .in_phase(CopyPhase::Capture, self.amt as usize)?;
+                }
             }
 
             while self.pos < self.cap {
-                let size = ready!(self.poll_write_buf(cx, w))?;
+                let size = ready!(self.poll_write_buf(cx, w))
+                    .in_phase(CopyPhase::SpliceOut, self.amt as usize)?;
 
                 if size == 0 {
-                    return Poll::Ready(Err(Error::new(
-                        ErrorKind::WriteZero,
-                        "write zero byte into writer",
+                    return Poll::Ready(Err(CopyError::new(
+                        CopyPhase::SpliceOut,
+                        self.amt as usize,
+                        Error::new(ErrorKind::WriteZero, "write zero byte into writer"),
                     )));
                 } else {
                     self.pos += size;
                     self.amt += size as u64;
+                    self.stats.bytes += size;
                     self.need_flush = true;
                 }
             }
 
+            coop.made_progress();
+
             // If pos larger than cap, this loop will never stop.
             // In particular, user's wrong poll_write implementation returning
             // incorrect written length may lead to thread blocking.
@@ -310,57 +335,95 @@ where
             // If we've written all the data and we've seen EOF, flush out the
             // data and finish the transfer.
             if self.pos == self.cap && self.read_done {
-                ready!(self.poll_flush_buf(cx, w))?;
-                return Poll::Ready(Ok(self.amt));
+                ready!(self.poll_flush_buf(cx, w)).in_phase(CopyPhase::Flush, self.amt as usize)?;
+                return Poll::Ready(Ok(self.finish()));
             }
         }
     }
 }
 
-enum TransferState<SR, SRInner, SW, SWInner> {
-    Running(CopyBuffer<SR, SRInner, SW, SWInner>),
-    ShuttingDown(u64),
-    Done(u64),
+async fn new_buffer<'p, R, W>(
+    options: &CopyOptions,
+    direction: CaptureDirection,
+) -> CopyResult<CopyBuffer<'p, R, W>>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    let pipe = SplicePipe::new(options.get_pipe_pool(), options.get_splice_config())
+        .await
+        .in_phase(CopyPhase::Pipe, 0)?;
+    Ok(with_pipe(pipe, options, direction))
 }
 
-fn transfer_one_direction<SL, SLInner, SR, SRInner>(
+fn with_pipe<'p, R, W>(
+    pipe: SplicePipe,
+    options: &CopyOptions,
+    direction: CaptureDirection,
+) -> CopyBuffer<'p, R, W>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    CopyBuffer::new(
+        pipe,
+        options.get_splice_config(),
+        *options.get_budget(),
+        CaptureTap::new(options, direction),
+    )
+}
+
+enum TransferState<SR, SW> {
+    Running(CopyBuffer<'static, SR, SW>),
+    ShuttingDown(CopyStats),
+    Done(CopyStats),
+}
+
+fn transfer_one_direction<SL, SR>(
     cx: &mut Context<'_>,
-    state: &mut TransferState<SL, SLInner, SR, SRInner>,
+    state: &mut TransferState<SL, SR>,
     r: &mut SL,
     w: &mut SR,
-    mut amount: Option<u64>,
-) -> Poll<Result<u64>>
+    amount: Option<u64>,
+) -> Poll<CopyResult<CopyStats>>
 where
-    SLInner: AsRawFd,
-    SL: Stream + AsyncRead + AsRef<SLInner> + Unpin,
-    SRInner: AsRawFd,
-    SR: Stream + AsyncWrite + AsRef<SRInner> + Unpin,
+    SL: ZeroCopySource,
+    SR: ZeroCopySink,
 {
     loop {
         match state {
             TransferState::Running(buf) => {
-                let count = ready!(buf.poll_copy(cx, r, w, amount))?;
-                if let Some(amount) = amount.as_mut() {
-                    *amount -= count;
-                }
-
-                *state = TransferState::ShuttingDown(count);
+                let stats = ready!(buf.poll_copy(cx, r, w, amount))?;
+                *state = TransferState::ShuttingDown(stats);
             }
-            TransferState::ShuttingDown(count) => {
-                ready!(Pin::new(&mut *w).poll_shutdown(cx))?;
+            TransferState::ShuttingDown(stats) => {
+                ready!(Pin::new(&mut *w).poll_shutdown(cx))
+                    .in_phase(CopyPhase::Shutdown, stats.bytes)?;
 
-                *state = TransferState::Done(*count);
+                *state = TransferState::Done(stats.clone());
             }
-            TransferState::Done(count) => return Poll::Ready(Ok(*count)),
+            TransferState::Done(stats) => return Poll::Ready(Ok(stats.clone())),
         }
     }
 }
 
-/// This trait is auto implemented for `TcpStream` and `UnixStream`, and their owned halves.
-pub trait Stream {
-    fn poll_read_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>>;
-    fn poll_write_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>>;
-    fn try_io_n<R>(&self, interest: Interest, f: impl FnOnce() -> Result<R>) -> Result<R>;
+/// Copies data from `a` to `b`.
+///
+/// This function returns a future that will read from `a`,
+/// writing any data read to `b` until EOF or until `amount` bytes are copied.
+/// The write side of `b` is shut down afterwards.
+pub async fn zero_copy_unidirectional<A, B>(
+    a: &mut A,
+    b: &mut B,
+    amount: Option<u64>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    A: ZeroCopySource,
+    B: ZeroCopySink,
+{
+    let mut a_to_b = TransferState::Running(new_buffer(options, CaptureDirection::Forward).await?);
+    poll_fn(|cx| transfer_one_direction(cx, &mut a_to_b, a, b, amount)).await
 }
 
 /// Copies data in both directions between `a` and `b`.
@@ -368,113 +431,72 @@ pub trait Stream {
 /// This function returns a future that will read from both streams,
 /// writing any data read to the opposing stream.
 /// This happens in both directions concurrently.
-pub async fn zero_copy_unidirectional<A, AInner, B, BInner>(
+/// When one side reaches EOF, the write side of the opposing stream is shut down,
+/// so the half-close is propagated to the peer.
+pub async fn zero_copy_bidirectional<A, B>(
     a: &mut A,
     b: &mut B,
-    amount: Option<u64>,
-) -> Result<u64>
+    options: &CopyOptions,
+) -> CopyResult<(CopyStats, CopyStats)>
 where
-    AInner: AsRawFd,
-    A: Stream + AsyncRead + AsRef<AInner> + Unpin,
-    BInner: AsRawFd,
-    B: Stream + AsyncWrite + AsRef<BInner> + Unpin,
+    A: ZeroCopySource + ZeroCopySink,
+    B: ZeroCopySource + ZeroCopySink,
 {
-    let mut a_to_b = TransferState::Running(CopyBuffer::new(Pipe::new()?));
-    poll_fn(|cx| transfer_one_direction(cx, &mut a_to_b, a, b, amount)).await
-}
-
-mod tests {}
-
-use tokio::net::{
-    tcp::{OwnedReadHalf as TcpRead, OwnedWriteHalf as TcpWrite},
-    unix::{OwnedReadHalf as UnixRead, OwnedWriteHalf as UnixWrite},
-    TcpStream, UnixStream,
-};
-macro_rules! impl_stream_for {
-    ($stream: ident) => {
-        impl Stream for $stream {
-            #[inline]
-            fn poll_read_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-                self.poll_read_ready(cx)
-            }
-            #[inline]
-            fn poll_write_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-                self.poll_write_ready(cx)
-            }
-            #[inline]
-            fn try_io_n<R>(&self, interest: Interest, f: impl FnOnce() -> Result<R>) -> Result<R> {
-                self.try_io(interest, f)
-            }
-        }
-    };
-}
-impl_stream_for!(TcpStream);
-impl_stream_for!(UnixStream);
-impl_stream_for!(TcpRead);
-impl_stream_for!(TcpWrite);
-impl_stream_for!(UnixRead);
-impl_stream_for!(UnixWrite);
-
-trait PollReady {
-    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>>;
-
-    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>>;
-
-    fn try_io<R>(&self, interest: Interest, f: impl FnOnce() -> Result<R>) -> Result<R>;
-}
-
-impl PollReady for TcpRead {
-    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        AsRef::<TcpStream>::as_ref(self).poll_read_ready(cx)
-    }
-
-    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        AsRef::<TcpStream>::as_ref(self).poll_write_ready(cx)
-    }
-
-    fn try_io<R>(&self, interest: Interest, f: impl FnOnce() -> Result<R>) -> Result<R> {
-        AsRef::<TcpStream>::as_ref(self).try_io(interest, f)
-    }
+    let (forward, backward) =
+        SplicePipe::pair(options.get_pipe_pool(), options.get_splice_config())
+            .await
+            .in_phase(CopyPhase::Pipe, 0)?;
+    let mut a_to_b = TransferState::Running(with_pipe(forward, options, CaptureDirection::Forward));
+    let mut b_to_a =
+        TransferState::Running(with_pipe(backward, options, CaptureDirection::Backward));
+    poll_fn(|cx| {
+        let a_to_b = transfer_one_direction(cx, &mut a_to_b, a, b, None)?;
+        let b_to_a = transfer_one_direction(cx, &mut b_to_a, b, a, None)?;
+
+        // It is not a problem if ready! returns early because transfer_one_direction for the
+        // other direction will keep returning Poll::Ready(Ok(stats)) once it is done.
+        let a_to_b = ready!(a_to_b);
+        let b_to_a = ready!(b_to_a);
+
+        Poll::Ready(Ok((a_to_b, b_to_a)))
+    })
+    .await
 }
 
-impl PollReady for TcpWrite {
-    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        AsRef::<TcpStream>::as_ref(self).poll_read_ready(cx)
-    }
-
-    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        AsRef::<TcpStream>::as_ref(self).poll_write_ready(cx)
-    }
-
-    fn try_io<R>(&self, interest: Interest, f: impl FnOnce() -> Result<R>) -> Result<R> {
-        AsRef::<TcpStream>::as_ref(self).try_io(interest, f)
-    }
-}
-
-impl PollReady for UnixRead {
-    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        AsRef::<UnixStream>::as_ref(self).poll_read_ready(cx)
-    }
-
-    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        AsRef::<UnixStream>::as_ref(self).poll_write_ready(cx)
-    }
-
-    fn try_io<R>(&self, interest: Interest, f: impl FnOnce() -> Result<R>) -> Result<R> {
-        AsRef::<UnixStream>::as_ref(self).try_io(interest, f)
-    }
+/// Copies data from `r` to `w` until EOF or until `amount` bytes are copied.
+///
+/// Unlike [`zero_copy_unidirectional`], the write side of `w` is only flushed, not shut down.
+pub async fn zero_copy<R, W>(
+    r: &mut R,
+    w: &mut W,
+    amount: Option<u64>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    let mut buf = new_buffer(options, CaptureDirection::Forward).await?;
+    poll_fn(|cx| buf.poll_copy(cx, r, w, amount)).await
 }
 
-impl PollReady for UnixWrite {
-    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        AsRef::<UnixStream>::as_ref(self).poll_read_ready(cx)
-    }
-
-    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        AsRef::<UnixStream>::as_ref(self).poll_write_ready(cx)
-    }
-
-    fn try_io<R>(&self, interest: Interest, f: impl FnOnce() -> Result<R>) -> Result<R> {
-        AsRef::<UnixStream>::as_ref(self).try_io(interest, f)
-    }
+/// Copies a prefix followed by the data of `r` to `w`,
+/// until EOF or until `amount` bytes of `r` are copied.
+///
+/// The prefix is written into the pipe, so it leaves through the same splice path
+/// as the data of `r`. Like [`zero_copy`], the write side of `w` is only flushed, not shut down.
+pub async fn zero_copy_with_prefix<R, W>(
+    r: &mut R,
+    w: &mut W,
+    prefix: Prefix<'_>,
+    amount: Option<u64>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    let mut buf = new_buffer(options, CaptureDirection::Forward).await?;
+    buf.prefix = Some(prefix);
+    poll_fn(|cx| buf.poll_copy(cx, r, w, amount)).await
 }
@@ -7,32 +7,219 @@ pub use linux::copy;
 #[cfg(target_os = "linux")]
 pub use linux::copy_exact;
 
+#[cfg(target_os = "linux")]
+pub use linux::splice;
+
+#[cfg(target_os = "linux")]
+pub use linux::splice_with_prefix;
+
+#[cfg(target_os = "linux")]
+pub use linux::copy_bidirectional;
+
 #[cfg(not(target_os = "linux"))]
-use tokio::{
-    io,
-    net::tcp::{OwnedReadHalf, OwnedWriteHalf},
+use crate::copy::{
+    buffered,
+    capture::{CaptureDirection, CaptureTap},
+    endpoint::{ZeroCopySink, ZeroCopySource},
+    error::{CopyPhase, CopyResult, IoResultExt},
+    options::CopyOptions,
+    stats::CopyStats,
 };
-/// Copy data from a read half to a write half.
-/// This function is only available on non-linux platforms and uses tokio::io::copy.
 #[cfg(not(target_os = "linux"))]
-pub async fn copy<'a>(r: &'a mut OwnedReadHalf, w: &'a mut OwnedWriteHalf) -> io::Result<usize> {
+use tokio::io::{self, AsyncWriteExt};
+
+/// Copy data from a source to a sink and shut the sink down afterwards.
+/// This function is only available on non-linux platforms and uses a buffered copy.
+#[cfg(not(target_os = "linux"))]
+pub async fn copy<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
     use essentials::debug;
 
-    debug!("copying tcp stream using tokio::io::copy");
-    Ok(io::copy(r, w).await? as usize)
+    debug!("copying tcp stream using a buffered copy");
+    let stats = buffered::copy(
+        r,
+        w,
+        None,
+        CaptureTap::new(options, CaptureDirection::Forward),
+    )
+    .await?;
+    w.shutdown()
+        .await
+        .in_phase(CopyPhase::Shutdown, stats.bytes)?;
+    Ok(stats)
 }
 
-/// Copy data from a read half to a write half.
-/// This function is only available on linux platforms and uses splice.
+/// Copy exactly `length` bytes from a source to a sink and shut the sink down afterwards.
+/// This function is only available on non-linux platforms and uses a buffered copy.
 #[cfg(not(target_os = "linux"))]
-pub async fn copy_exact<'a>(
-    r: &'a mut OwnedReadHalf,
-    w: &'a mut OwnedWriteHalf,
+pub async fn copy_exact<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
     length: usize,
-) -> io::Result<usize> {
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    use essentials::debug;
+
+    debug!("copying tcp stream using a buffered copy");
+    let stats = buffered::copy(
+        r,
+        w,
+        Some(length),
+        CaptureTap::new(options, CaptureDirection::Forward),
+    )
+    .await?;
+    w.shutdown()
+        .await
+        .in_phase(CopyPhase::Shutdown, stats.bytes)?;
+    Ok(stats)
+}
+
+/// Copy data from a source to a sink, the sink is not shut down.
+/// This function is only available on non-linux platforms and uses a buffered copy.
+#[cfg(not(target_os = "linux"))]
+pub async fn splice<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    use essentials::debug;
+
+    debug!("copying using a buffered copy");
+    buffered::copy(
+        r,
+        w,
+        length,
+        CaptureTap::new(options, CaptureDirection::Forward),
+    )
+    .await
+}
+
+/// Copy a prefix and then data from a source to a sink, the sink is not shut down.
+/// This function is only available on non-linux platforms and uses a buffered copy.
+#[cfg(not(target_os = "linux"))]
+pub async fn splice_with_prefix<'a, R, W>(
+    r: &'a mut R,
+    w: &'a mut W,
+    prefix: &[u8],
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    use crate::copy::error::CopyError;
+    use essentials::debug;
+
+    debug!("copying prefix and stream using a buffered copy");
+    let mut capture = CaptureTap::new(options, CaptureDirection::Forward);
+    if let Some(capture) = capture.as_mut().filter(|_| !prefix.is_empty()) {
+        capture.record(prefix).in_phase(CopyPhase::Capture, 0)?;
+    }
+    w.write_all(prefix).await.in_phase(CopyPhase::Write, 0)?;
+    let mut stats = buffered::copy(r, w, length, capture).await.map_err(|err| {
+        CopyError::new(
+            err.phase(),
+            prefix.len() + err.transferred(),
+            err.into_io_error(),
+        )
+    })?;
+    stats.bytes += prefix.len();
+    stats.write_calls += 1;
+    Ok(stats)
+}
+
+/// Copy data in both directions between two streams.
+/// This function is only available on non-linux platforms and uses [`tokio::io::copy_bidirectional`],
+/// captured copies use buffered copies which record the data.
+#[cfg(not(target_os = "linux"))]
+pub async fn copy_bidirectional<'a, A, B>(
+    a: &'a mut A,
+    b: &'a mut B,
+    options: &CopyOptions,
+) -> CopyResult<(CopyStats, CopyStats)>
+where
+    A: ZeroCopySource + ZeroCopySink,
+    B: ZeroCopySource + ZeroCopySink,
+{
+    use crate::copy::stats::CopyMechanism;
+    use essentials::debug;
+
+    if options.get_capture().is_some() {
+        return copy_bidirectional_captured(a, b, options).await;
+    }
+    debug!("copying tcp streams bidirectionally using tokio::io::copy_bidirectional");
+    let start = CopyStats::new(CopyMechanism::Buffered);
+    let (a_to_b, b_to_a) = io::copy_bidirectional(a, b)
+        .await
+        .in_phase(CopyPhase::Write, 0)?;
+    let stats = |bytes: u64| {
+        let mut stats = start.clone();
+        stats.bytes = bytes as usize;
+        stats.finish()
+    };
+    Ok((stats(a_to_b), stats(b_to_a)))
+}
+
+/// Copy data in both directions between two streams with buffered copies which record the data.
+#[cfg(not(target_os = "linux"))]
+async fn copy_bidirectional_captured<A, B>(
+    a: &mut A,
+    b: &mut B,
+    options: &CopyOptions,
+) -> CopyResult<(CopyStats, CopyStats)>
+where
+    A: ZeroCopySource + ZeroCopySink,
+    B: ZeroCopySource + ZeroCopySink,
+{
     use essentials::debug;
-    use tokio::io::AsyncReadExt;
 
-    debug!("copying tcp stream using tokio::io::copy");
-    Ok(io::copy(&mut r.take(length as u64), w).await? as usize)
+    debug!("copying tcp streams bidirectionally using captured buffered copies");
+    let (mut a_read, mut a_write) = io::split(a);
+    let (mut b_read, mut b_write) = io::split(b);
+    let a_to_b = async {
+        let stats = buffered::copy(
+            &mut a_read,
+            &mut b_write,
+            None,
+            CaptureTap::new(options, CaptureDirection::Forward),
+        )
+        .await?;
+        b_write
+            .shutdown()
+            .await
+            .in_phase(CopyPhase::Shutdown, stats.bytes)?;
+        Ok(stats)
+    };
+    let b_to_a = async {
+        let stats = buffered::copy(
+            &mut b_read,
+            &mut a_write,
+            None,
+            CaptureTap::new(options, CaptureDirection::Backward),
+        )
+        .await?;
+        a_write
+            .shutdown()
+            .await
+            .in_phase(CopyPhase::Shutdown, stats.bytes)?;
+        Ok(stats)
+    };
+    tokio::try_join!(a_to_b, b_to_a)
 }
@@ -0,0 +1,316 @@
+use super::{ShadowPolicy, TeeStats};
+use crate::copy::budget::{CopyBudget, PollBudget};
+use crate::copy::endpoint::{ZeroCopySink, ZeroCopySource};
+use crate::copy::error::{CopyPhase, CopyResult, IoResultExt};
+use crate::copy::options::CopyOptions;
+use crate::copy::pipe::SplicePipe;
+use crate::copy::stats::{CopyMechanism, CopyStats};
+use essentials::debug;
+use std::future::poll_fn;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+fn try_splice(fd_in: RawFd, fd_out: RawFd, len: usize) -> Result<usize> {
+    match unsafe {
+        libc::splice(
+            fd_in,
+            std::ptr::null_mut(),
+            fd_out,
+            std::ptr::null_mut(),
+            len,
+            libc::SPLICE_F_NONBLOCK,
+        )
+    } {
+        -1 => Err(Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
+
+/// Duplicates up to `len` bytes from the head of one pipe into another without consuming them.
+fn try_tee(fd_in: RawFd, fd_out: RawFd, len: usize) -> Result<usize> {
+    match unsafe { libc::tee(fd_in, fd_out, len, libc::SPLICE_F_NONBLOCK) } {
+        -1 => Err(Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
+
+/// Splices the source into the main pipe, tees every fill into the shadow pipe
+/// and splices both pipes into their sinks.
+///
+/// tee always duplicates the head of the main pipe, so a fill is only teed once the shadow pipe
+/// is empty, which guarantees that it has room for the whole fill.
+struct Tee {
+    main: SplicePipe,
+    shadow: SplicePipe,
+    policy: ShadowPolicy,
+    /// The bytes in the main pipe which have not been written to the primary sink.
+    main_len: usize,
+    /// The bytes in the shadow pipe which have not been written to the secondary sink.
+    shadow_len: usize,
+    /// Whether the current fill has been teed, dropped or the shadow is disconnected.
+    teed: bool,
+    /// Whether the disconnected secondary sink is still being shut down.
+    closing: bool,
+    read_done: bool,
+    budget: CopyBudget,
+    stats: TeeStats,
+}
+
+impl Tee {
+    async fn new(options: &CopyOptions) -> CopyResult<Self> {
+        let (main, shadow) = SplicePipe::pair(options.get_pipe_pool(), options.get_splice_config())
+            .await
+            .in_phase(CopyPhase::Pipe, 0)?;
+        Ok(Self {
+            main,
+            shadow,
+            policy: options.get_shadow_policy(),
+            main_len: 0,
+            shadow_len: 0,
+            teed: true,
+            closing: false,
+            read_done: false,
+            budget: *options.get_budget(),
+            stats: TeeStats {
+                primary: CopyStats::new(CopyMechanism::Splice),
+                secondary: CopyStats::new(CopyMechanism::Splice),
+                tee_calls: 0,
+                dropped: 0,
+                disconnected: false,
+            },
+        })
+    }
+
+    fn disconnect<S: ZeroCopySink>(&mut self, cx: &mut Context<'_>, secondary: &mut S) {
+        debug!("disconnecting the secondary sink");
+        self.stats.disconnected = true;
+        self.teed = true;
+        self.closing = true;
+        // the shutdown does not hold up the primary sink, it is polled along with the copy
+        let _ = self.poll_close_shadow(cx, secondary);
+    }
+
+    /// Shuts the disconnected secondary sink down, its errors are ignored.
+    fn poll_close_shadow<S: ZeroCopySink>(
+        &mut self,
+        cx: &mut Context<'_>,
+        secondary: &mut S,
+    ) -> Poll<()> {
+        if self.closing {
+            let _ = ready!(Pin::new(secondary).poll_shutdown(cx));
+            self.closing = false;
+        }
+        Poll::Ready(())
+    }
+
+    /// Writes the shadow pipe to the secondary sink, disconnecting it if that fails.
+    fn poll_drain_shadow<S: ZeroCopySink>(
+        &mut self,
+        cx: &mut Context<'_>,
+        secondary: &mut S,
+    ) -> Poll<()> {
+        while self.shadow_len > 0 && !self.stats.disconnected {
+            if let Err(err) = ready!(secondary.poll_write_ready_n(cx)) {
+                debug!("secondary sink failed: {err}");
+                self.disconnect(cx, secondary);
+                break;
+            }
+            let res = secondary.try_write_io_n(|| {
+                self.stats.secondary.splice_calls += 1;
+                try_splice(self.shadow.read_fd(), secondary.sink_fd(), self.shadow_len)
+            });
+            match res {
+                Ok(n) if n > 0 => {
+                    self.shadow_len -= n;
+                    self.stats.secondary.bytes += n;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    self.stats.secondary.would_block += 1;
+                }
+                res => {
+                    debug!("secondary sink failed: {res:?}");
+                    self.disconnect(cx, secondary);
+                }
+            }
+        }
+        Poll::Ready(())
+    }
+
+    /// Tees the current fill into the shadow pipe, or applies the policy if it has no room.
+    fn poll_tee<S: ZeroCopySink>(&mut self, cx: &mut Context<'_>, secondary: &mut S) -> Poll<()> {
+        if self.teed {
+            return Poll::Ready(());
+        }
+        if self.shadow_len > 0 {
+            match self.policy {
+                // poll_drain_shadow has registered the waker
+                ShadowPolicy::Block => return Poll::Pending,
+                ShadowPolicy::Drop => {
+                    self.stats.dropped += self.main_len;
+                    self.teed = true;
+                }
+                ShadowPolicy::Disconnect => self.disconnect(cx, secondary),
+            }
+            return Poll::Ready(());
+        }
+        self.stats.tee_calls += 1;
+        match try_tee(self.main.read_fd(), self.shadow.write_fd(), self.main_len) {
+            Ok(n) => {
+                // only possible if the shadow pipe is smaller than the main pipe
+                self.stats.dropped += self.main_len - n;
+                self.shadow_len = n;
+            }
+            Err(err) => {
+                debug!("tee failed: {err}");
+                self.disconnect(cx, secondary);
+            }
+        }
+        self.teed = true;
+        Poll::Ready(())
+    }
+
+    fn poll_fill<R: ZeroCopySource>(
+        &mut self,
+        cx: &mut Context<'_>,
+        r: &mut R,
+    ) -> Poll<Result<()>> {
+        loop {
+            ready!(r.poll_read_ready_n(cx))?;
+            let res = r.try_read_io_n(|| {
+                self.stats.primary.splice_calls += 1;
+                try_splice(r.source_fd(), self.main.write_fd(), self.main.size())
+            });
+            match res {
+                Ok(0) => self.read_done = true,
+                Ok(n) => {
+                    self.main_len = n;
+                    self.teed = self.stats.disconnected;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    self.stats.primary.would_block += 1;
+                    continue;
+                }
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+            return Poll::Ready(Ok(()));
+        }
+    }
+
+    fn poll_drain_main<P: ZeroCopySink>(
+        &mut self,
+        cx: &mut Context<'_>,
+        primary: &mut P,
+    ) -> Poll<Result<()>> {
+        while self.main_len > 0 {
+            ready!(primary.poll_write_ready_n(cx))?;
+            let res = primary.try_write_io_n(|| {
+                self.stats.primary.splice_calls += 1;
+                try_splice(self.main.read_fd(), primary.sink_fd(), self.main_len)
+            });
+            match res {
+                Ok(0) => {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::WriteZero,
+                        "write zero byte into writer",
+                    )))
+                }
+                Ok(n) => {
+                    self.main_len -= n;
+                    self.stats.primary.bytes += n;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    self.stats.primary.would_block += 1;
+                }
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Copies until the source reaches EOF and everything has been written to the primary sink.
+    fn poll_copy<R, P, S>(
+        &mut self,
+        cx: &mut Context<'_>,
+        r: &mut R,
+        primary: &mut P,
+        secondary: &mut S,
+    ) -> Poll<CopyResult<()>>
+    where
+        R: ZeroCopySource,
+        P: ZeroCopySink,
+        S: ZeroCopySink,
+    {
+        let budget = PollBudget::start(self.budget, &self.stats.primary);
+        loop {
+            let coop = ready!(budget.poll_proceed(cx, &mut self.stats.primary));
+            // the secondary sink makes progress whenever the copy is polled
+            let _ = self.poll_drain_shadow(cx, secondary);
+            let _ = self.poll_close_shadow(cx, secondary);
+            if self.main_len == 0 {
+                if self.read_done {
+                    return Poll::Ready(Ok(()));
+                }
+                ready!(self.poll_fill(cx, r))
+                    .in_phase(CopyPhase::SpliceIn, self.stats.primary.bytes)?;
+                continue;
+            }
+            ready!(self.poll_tee(cx, secondary));
+            ready!(self.poll_drain_main(cx, primary))
+                .in_phase(CopyPhase::SpliceOut, self.stats.primary.bytes)?;
+            coop.made_progress();
+        }
+    }
+}
+
+/// Copy data from a source to a primary and a secondary sink and shut both down afterwards.
+/// This function is only available on linux platforms and uses splice and tee.
+pub async fn copy_tee<'a, R, P, S>(
+    r: &'a mut R,
+    primary: &'a mut P,
+    secondary: &'a mut S,
+    options: &CopyOptions,
+) -> CopyResult<TeeStats>
+where
+    R: ZeroCopySource,
+    P: ZeroCopySink,
+    S: ZeroCopySink,
+{
+    debug!("copying stream to two sinks using splice and tee");
+    let mut tee = Tee::new(options).await?;
+    poll_fn(|cx| tee.poll_copy(cx, r, primary, secondary)).await?;
+    poll_fn(|cx| Pin::new(&mut *primary).poll_shutdown(cx))
+        .await
+        .in_phase(CopyPhase::Shutdown, tee.stats.primary.bytes)?;
+    if tee.policy == ShadowPolicy::Block {
+        poll_fn(|cx| tee.poll_drain_shadow(cx, secondary)).await;
+    } else {
+        // a stalled secondary sink must not hold up the end of the copy
+        poll_fn(|cx| {
+            let _ = tee.poll_drain_shadow(cx, secondary);
+            if tee.shadow_len > 0 && !tee.stats.disconnected {
+                match tee.policy {
+                    ShadowPolicy::Drop => tee.stats.dropped += tee.shadow_len,
+                    _ => tee.disconnect(cx, secondary),
+                }
+            }
+            Poll::Ready(())
+        })
+        .await;
+    }
+    poll_fn(|cx| tee.poll_close_shadow(cx, secondary)).await;
+    if !tee.stats.disconnected
+        && poll_fn(|cx| Pin::new(&mut *secondary).poll_shutdown(cx))
+            .await
+            .is_err()
+    {
+        tee.stats.disconnected = true;
+    }
+    let mut stats = tee.stats;
+    stats.primary.pipe_size = Some(tee.main.size());
+    stats.secondary.pipe_size = Some(tee.shadow.size());
+    stats.primary = stats.primary.finish();
+    stats.secondary = stats.secondary.finish();
+    Ok(stats)
+}
@@ -0,0 +1,191 @@
+#[cfg(target_os = "linux")]
+mod linux;
+
+#[cfg(target_os = "linux")]
+pub use linux::copy_tee;
+
+use crate::copy::stats::CopyStats;
+use serde::Serialize;
+
+/// What a tee copy does when the secondary sink, e.g. a shadow upstream, cannot keep up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShadowPolicy {
+    /// Wait for the secondary sink, which slows the primary sink down to its pace.
+    #[default]
+    Block,
+    /// Skip the data the secondary sink has no room for, so it receives an incomplete stream.
+    /// The skipped bytes are counted in [`TeeStats::dropped`].
+    Drop,
+    /// Shut the secondary sink down the first time it has no room and stop copying to it.
+    Disconnect,
+}
+
+/// Statistics of a finished tee copy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TeeStats {
+    /// The copy from the source to the primary sink.
+    pub primary: CopyStats,
+    /// The copy to the secondary sink, its `bytes` are the bytes the secondary sink received.
+    pub secondary: CopyStats,
+    /// The number of tee syscalls.
+    pub tee_calls: usize,
+    /// The number of bytes the secondary sink missed because of [`ShadowPolicy::Drop`].
+    pub dropped: usize,
+    /// Whether the secondary sink was disconnected, either because of
+    /// [`ShadowPolicy::Disconnect`] or because writing to it failed.
+    pub disconnected: bool,
+}
+
+#[cfg(not(target_os = "linux"))]
+use crate::copy::{
+    endpoint::{ZeroCopySink, ZeroCopySource},
+    error::{CopyPhase, CopyResult, IoResultExt},
+    options::CopyOptions,
+    stats::CopyMechanism,
+};
+
+/// Copy data from a source to a primary and a secondary sink and shut both down afterwards.
+/// This function is only available on non-linux platforms and uses a buffered copy.
+///
+/// Like the shadow pipe on linux, the secondary sink has a buffer for one read,
+/// the policy only applies when a new read arrives while the previous one has not been written yet.
+#[cfg(not(target_os = "linux"))]
+pub async fn copy_tee<'a, R, P, S>(
+    r: &'a mut R,
+    primary: &'a mut P,
+    secondary: &'a mut S,
+    options: &CopyOptions,
+) -> CopyResult<TeeStats>
+where
+    R: ZeroCopySource,
+    P: ZeroCopySink,
+    S: ZeroCopySink,
+{
+    use essentials::debug;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    debug!("copying stream to two sinks using a buffered copy");
+    let policy = options.get_shadow_policy();
+    let mut stats = TeeStats {
+        primary: CopyStats::new(CopyMechanism::Buffered),
+        secondary: CopyStats::new(CopyMechanism::Buffered),
+        tee_calls: 0,
+        dropped: 0,
+        disconnected: false,
+    };
+    let mut buf = vec![0; 64 * 1024];
+    // the data the secondary sink has not taken yet
+    let mut shadow = Vec::with_capacity(buf.len());
+    loop {
+        stats.primary.read_calls += 1;
+        let n = r
+            .read(&mut buf)
+            .await
+            .in_phase(CopyPhase::Read, stats.primary.bytes)?;
+        if n == 0 {
+            break;
+        }
+        stats.primary.write_calls += 1;
+        primary
+            .write_all(&buf[..n])
+            .await
+            .in_phase(CopyPhase::Write, stats.primary.bytes)?;
+        stats.primary.bytes += n;
+        if stats.disconnected {
+            continue;
+        }
+        let block = policy == ShadowPolicy::Block;
+        if write_shadow(secondary, &mut shadow, &mut stats, block)
+            .await
+            .is_err()
+        {
+            disconnect(secondary, &mut stats).await;
+            continue;
+        }
+        if shadow.is_empty() {
+            shadow.extend_from_slice(&buf[..n]);
+            if write_shadow(secondary, &mut shadow, &mut stats, false)
+                .await
+                .is_err()
+            {
+                disconnect(secondary, &mut stats).await;
+            }
+        } else if policy == ShadowPolicy::Drop {
+            stats.dropped += n;
+        } else {
+            disconnect(secondary, &mut stats).await;
+        }
+    }
+    primary
+        .shutdown()
+        .await
+        .in_phase(CopyPhase::Shutdown, stats.primary.bytes)?;
+    if !stats.disconnected {
+        // a stalled secondary sink must not hold up the end of the copy
+        let block = policy == ShadowPolicy::Block;
+        if write_shadow(secondary, &mut shadow, &mut stats, block)
+            .await
+            .is_err()
+        {
+            disconnect(secondary, &mut stats).await;
+        } else if !shadow.is_empty() {
+            match policy {
+                ShadowPolicy::Drop => stats.dropped += shadow.len(),
+                _ => disconnect(secondary, &mut stats).await,
+            }
+        }
+    }
+    if !stats.disconnected && secondary.shutdown().await.is_err() {
+        stats.disconnected = true;
+    }
+    stats.primary = stats.primary.finish();
+    stats.secondary = stats.secondary.finish();
+    Ok(stats)
+}
+
+/// Writes the buffered data to the secondary sink.
+/// Unless `block` is set, only as much as the sink takes without waiting.
+#[cfg(not(target_os = "linux"))]
+async fn write_shadow<S: ZeroCopySink>(
+    secondary: &mut S,
+    shadow: &mut Vec<u8>,
+    stats: &mut TeeStats,
+    block: bool,
+) -> std::io::Result<()> {
+    use std::future::poll_fn;
+    use std::pin::Pin;
+    use std::task::Poll;
+
+    while !shadow.is_empty() {
+        stats.secondary.write_calls += 1;
+        let written = poll_fn(
+            |cx| match Pin::new(&mut *secondary).poll_write(cx, shadow) {
+                Poll::Pending if !block => Poll::Ready(Ok(None)),
+                Poll::Pending => Poll::Pending,
+                Poll::Ready(res) => Poll::Ready(res.map(Some)),
+            },
+        )
+        .await?;
+        match written {
+            None => break,
+            Some(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Some(n) => {
+                shadow.drain(..n);
+                stats.secondary.bytes += n;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Stops copying to the secondary sink and shuts it down, its errors are ignored.
+#[cfg(not(target_os = "linux"))]
+async fn disconnect<S: ZeroCopySink>(secondary: &mut S, stats: &mut TeeStats) {
+    use essentials::debug;
+    use tokio::io::AsyncWriteExt;
+
+    debug!("disconnecting the secondary sink");
+    stats.disconnected = true;
+    let _ = secondary.shutdown().await;
+}
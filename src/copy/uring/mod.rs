@@ -0,0 +1,340 @@
+mod ring;
+
+use crate::copy::budget::PollBudget;
+use crate::copy::endpoint::{ZeroCopySink, ZeroCopySource};
+use crate::copy::error::{CopyError, CopyPhase, CopyResult, IoResultExt};
+use crate::copy::options::CopyOptions;
+use crate::copy::pipe::{AdaptiveSize, SplicePipe};
+use crate::copy::stats::{CopyMechanism, CopyStats};
+use essentials::debug;
+use ring::{Ring, Sqe, IORING_OP_POLL_ADD, IORING_OP_SPLICE};
+use std::future::{poll_fn, Future};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWriteExt;
+
+/// the environment variable the default backend is read from
+const BACKEND_VAR: &str = "IO_COPY_BACKEND";
+
+const UNSET: u8 = 0;
+const READINESS: u8 = 1;
+const IO_URING: u8 = 2;
+
+static DEFAULT_BACKEND: AtomicU8 = AtomicU8::new(UNSET);
+
+/// How splice and sendfile copies drive their syscalls.
+///
+/// This enum is only available on linux platforms with the `io-uring` feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyBackend {
+    /// Non-blocking splice and sendfile calls made when tokio reports the endpoints ready.
+    Readiness,
+    /// Splice operations submitted to an io_uring, file reads never block a runtime worker.
+    /// Every direction of a copy uses its own ring with a single operation in flight,
+    /// rings are reused by later copies once their copy is done.
+    ///
+    /// Data moves through a pipe with `IORING_OP_SPLICE` in both steps, so it never enters userspace,
+    /// which is why `IORING_OP_READ` and `IORING_OP_SEND` are not used. `IORING_OP_POLL_ADD` waits
+    /// for sockets which are not ready. Copies fall back to [`Readiness`](CopyBackend::Readiness)
+    /// when the kernel does not support io_uring splice or when they are captured.
+    /// Cold pages are not probed, the kernel reads them on its own worker threads.
+    IoUring,
+}
+
+impl CopyBackend {
+    /// The backend of copies which do not select one in their [`CopyOptions`].
+    /// Unless changed with [`set_default`](CopyBackend::set_default),
+    /// it is read from the `IO_COPY_BACKEND` environment variable (`readiness` or `io-uring`)
+    /// and is [`Readiness`](CopyBackend::Readiness) if the variable is not set.
+    pub fn get_default() -> Self {
+        match DEFAULT_BACKEND.load(Ordering::Relaxed) {
+            READINESS => CopyBackend::Readiness,
+            IO_URING => CopyBackend::IoUring,
+            _ => {
+                let backend = match std::env::var(BACKEND_VAR).as_deref() {
+                    Ok("io-uring") | Ok("io_uring") => CopyBackend::IoUring,
+                    _ => CopyBackend::Readiness,
+                };
+                // a concurrent set_default wins
+                let _ = DEFAULT_BACKEND.compare_exchange(
+                    UNSET,
+                    backend.id(),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+                Self::get_default()
+            }
+        }
+    }
+
+    /// Change the backend of copies which do not select one in their [`CopyOptions`].
+    pub fn set_default(backend: Self) {
+        DEFAULT_BACKEND.store(backend.id(), Ordering::Relaxed);
+    }
+
+    /// Whether the running kernel supports the backend.
+    pub fn is_supported(self) -> bool {
+        static IO_URING_SUPPORTED: OnceLock<bool> = OnceLock::new();
+        match self {
+            CopyBackend::Readiness => true,
+            CopyBackend::IoUring => *IO_URING_SUPPORTED
+                .get_or_init(|| Ring::supports(&[IORING_OP_SPLICE, IORING_OP_POLL_ADD])),
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            CopyBackend::Readiness => READINESS,
+            CopyBackend::IoUring => IO_URING,
+        }
+    }
+}
+
+/// Takes an idle ring or sets up a new one if the copy should use io_uring.
+/// Returns `None` if it should use the readiness backend instead.
+pub(crate) fn ring(options: &CopyOptions) -> Option<Ring> {
+    if options.get_backend() != CopyBackend::IoUring || !CopyBackend::IoUring.is_supported() {
+        return None;
+    }
+    if options.get_capture().is_some() {
+        debug!("capturing copies with readiness, io_uring splice is not captured");
+        return None;
+    }
+    match Ring::new() {
+        Ok(ring) => Some(ring),
+        Err(err) => {
+            debug!("falling back to readiness, io_uring setup failed: {err}");
+            None
+        }
+    }
+}
+
+/// Copy data from a source to a sink through a pipe with io_uring and shut the sink down afterwards.
+pub(crate) async fn copy<'a, R, W>(
+    ring: Ring,
+    r: &'a mut R,
+    w: &'a mut W,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    let stats = splice(ring, r, w, None, length, options).await?;
+    w.shutdown()
+        .await
+        .in_phase(CopyPhase::Shutdown, stats.bytes)?;
+    Ok(stats)
+}
+
+/// Copy data from a source to a sink through a pipe with io_uring, the sink is flushed but not shut down.
+/// Copies `length` bytes if given, otherwise until EOF.
+/// A file source is read at `offset` if given, otherwise at its file position, which is advanced.
+pub(crate) async fn splice<'a, R, W>(
+    ring: Ring,
+    r: &'a mut R,
+    w: &'a mut W,
+    offset: Option<u64>,
+    length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats>
+where
+    R: ZeroCopySource,
+    W: ZeroCopySink,
+{
+    let pipe = SplicePipe::new(options.get_pipe_pool(), options.get_splice_config())
+        .await
+        .in_phase(CopyPhase::Pipe, 0)?;
+    let stats = transfer(
+        ring,
+        pipe,
+        r.source_fd(),
+        w.sink_fd(),
+        offset,
+        length,
+        options,
+    )
+    .await?;
+    w.flush().await.in_phase(CopyPhase::Flush, stats.bytes)?;
+    Ok(stats)
+}
+
+/// Copy data in both directions between `a` and `b` with a ring per direction.
+/// When one side reaches EOF, the write side of the opposing stream is shut down.
+pub(crate) async fn copy_bidirectional<A, B>(
+    forward: Ring,
+    backward: Ring,
+    a: &mut A,
+    b: &mut B,
+    options: &CopyOptions,
+) -> CopyResult<(CopyStats, CopyStats)>
+where
+    A: ZeroCopySource + ZeroCopySink,
+    B: ZeroCopySource + ZeroCopySink,
+{
+    let (a_to_b, b_to_a) = SplicePipe::pair(options.get_pipe_pool(), options.get_splice_config())
+        .await
+        .in_phase(CopyPhase::Pipe, 0)?;
+    // the transfers only use the fds, so the streams stay available for the shutdowns
+    let mut a_to_b = Direction::Running(Box::pin(transfer(
+        forward,
+        a_to_b,
+        a.source_fd(),
+        b.sink_fd(),
+        None,
+        None,
+        options,
+    )));
+    let mut b_to_a = Direction::Running(Box::pin(transfer(
+        backward,
+        b_to_a,
+        b.source_fd(),
+        a.sink_fd(),
+        None,
+        None,
+        options,
+    )));
+    poll_fn(|cx| {
+        let a_to_b = a_to_b.poll(cx, b)?;
+        let b_to_a = b_to_a.poll(cx, a)?;
+        Poll::Ready(Ok((ready!(a_to_b), ready!(b_to_a))))
+    })
+    .await
+}
+
+/// One direction of a bidirectional copy.
+enum Direction<F> {
+    Running(Pin<Box<F>>),
+    ShuttingDown(CopyStats),
+    Done(CopyStats),
+}
+
+impl<F: Future<Output = CopyResult<CopyStats>>> Direction<F> {
+    /// Drives the transfer and shuts the sink down once it is done.
+    fn poll<W: ZeroCopySink>(
+        &mut self,
+        cx: &mut Context<'_>,
+        w: &mut W,
+    ) -> Poll<CopyResult<CopyStats>> {
+        loop {
+            match self {
+                Direction::Running(transfer) => {
+                    *self = Direction::ShuttingDown(ready!(transfer.as_mut().poll(cx))?);
+                }
+                Direction::ShuttingDown(stats) => {
+                    ready!(Pin::new(&mut *w).poll_shutdown(cx))
+                        .in_phase(CopyPhase::Shutdown, stats.bytes)?;
+                    *self = Direction::Done(stats.clone());
+                }
+                Direction::Done(stats) => return Poll::Ready(Ok(stats.clone())),
+            }
+        }
+    }
+}
+
+/// The ring and the pipe of a transfer.
+/// When the transfer is dropped, the operation still in flight is cancelled before the pipe is released,
+/// a pipe the kernel may still use is closed instead of being reused.
+struct Transfer {
+    ring: Ring,
+    pipe: SplicePipe,
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        if !self.ring.cancel() {
+            self.pipe.poison();
+        }
+    }
+}
+
+/// Moves data from `fd_in` to `fd_out` through the pipe with io_uring splice operations.
+async fn transfer(
+    ring: Ring,
+    pipe: SplicePipe,
+    fd_in: RawFd,
+    fd_out: RawFd,
+    mut offset: Option<u64>,
+    mut length: Option<usize>,
+    options: &CopyOptions,
+) -> CopyResult<CopyStats> {
+    let Transfer { ring, pipe } = &mut Transfer { ring, pipe };
+    let mut adaptive = AdaptiveSize::new(options.get_splice_config());
+    let mut stats = CopyStats::new(CopyMechanism::IoUring);
+    let mut budget = PollBudget::start(*options.get_budget(), &stats);
+    let mut filled = 0;
+    while length != Some(0) {
+        // the pipe has been drained, so it can be resized
+        adaptive.adapt(pipe, filled);
+        let len = length.map_or(pipe.size(), |length| length.min(pipe.size()));
+        let fill = Sqe::splice(fd_in, offset, pipe.write_fd(), len as u32);
+        filled = run(ring, fill, fd_in, libc::POLLIN, &mut stats)
+            .await
+            .in_phase(CopyPhase::SpliceIn, stats.bytes)?;
+        if filled == 0 {
+            break;
+        }
+        if let Some(offset) = offset.as_mut() {
+            *offset += filled as u64;
+        }
+        if let Some(length) = length.as_mut() {
+            *length -= filled;
+        }
+        let mut drained = 0;
+        while drained < filled {
+            let drain = Sqe::splice(pipe.read_fd(), None, fd_out, (filled - drained) as u32);
+            match run(ring, drain, fd_out, libc::POLLOUT, &mut stats)
+                .await
+                .in_phase(CopyPhase::SpliceOut, stats.bytes)?
+            {
+                0 => {
+                    return Err(CopyError::new(
+                        CopyPhase::SpliceOut,
+                        stats.bytes,
+                        Error::new(ErrorKind::WriteZero, "write zero byte into writer"),
+                    ))
+                }
+                size => {
+                    drained += size;
+                    stats.bytes += size;
+                }
+            }
+        }
+        // completions are often ready right away, so yield like the readiness backend does
+        if budget.is_spent(&stats) {
+            stats.yields += 1;
+            tokio::task::yield_now().await;
+            budget = PollBudget::start(*options.get_budget(), &stats);
+        } else {
+            tokio::task::coop::consume_budget().await;
+        }
+    }
+    stats.pipe_size = Some(pipe.size());
+    Ok(stats.finish())
+}
+
+/// Runs a splice operation, waiting until `fd` signals `events` whenever it fails with EAGAIN.
+async fn run(
+    ring: &mut Ring,
+    sqe: Sqe,
+    fd: RawFd,
+    events: libc::c_short,
+    stats: &mut CopyStats,
+) -> Result<usize> {
+    loop {
+        stats.splice_calls += 1;
+        match ring.run(sqe).await {
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                stats.would_block += 1;
+                // a level-triggered poll completes right away if the fd became ready meanwhile
+                ring.run(Sqe::poll(fd, events)).await?;
+            }
+            res => return res,
+        }
+    }
+}
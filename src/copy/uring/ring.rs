@@ -0,0 +1,499 @@
+use std::io::{Error, ErrorKind, Result};
+use std::mem::ManuallyDrop;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use tokio::io::unix::AsyncFd;
+
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+
+const IORING_ENTER_GETEVENTS: libc::c_uint = 1;
+
+const IORING_REGISTER_EVENTFD: libc::c_uint = 4;
+const IORING_REGISTER_PROBE: libc::c_uint = 8;
+const IO_URING_OP_SUPPORTED: u16 = 1;
+
+/// the number of idle rings kept for later copies
+const MAX_IDLE_RINGS: usize = 64;
+
+/// Rings whose copy is done, reused by the next copies instead of setting up new ones.
+static IDLE_RINGS: Mutex<Vec<Instance>> = Mutex::new(Vec::new());
+
+pub const IORING_OP_POLL_ADD: u8 = 6;
+const IORING_OP_ASYNC_CANCEL: u8 = 14;
+pub const IORING_OP_SPLICE: u8 = 30;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+/// A submission queue entry, the union fields are named after their use by splice.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sqe {
+    pub opcode: u8,
+    pub flags: u8,
+    pub ioprio: u16,
+    pub fd: i32,
+    pub off: u64,
+    pub splice_off_in: u64,
+    pub len: u32,
+    pub splice_flags: u32,
+    pub user_data: u64,
+    pub buf_index: u16,
+    pub personality: u16,
+    pub splice_fd_in: i32,
+    pub addr3: u64,
+    pub pad: u64,
+}
+
+impl Sqe {
+    /// Splices `len` bytes from `fd_in` to `fd_out`, one of which must be a pipe.
+    /// `off_in` is where to read a file, `None` uses and advances the file position.
+    pub fn splice(fd_in: RawFd, off_in: Option<u64>, fd_out: RawFd, len: u32) -> Self {
+        Self {
+            opcode: IORING_OP_SPLICE,
+            fd: fd_out,
+            off: u64::MAX,
+            splice_off_in: off_in.unwrap_or(u64::MAX),
+            len,
+            splice_fd_in: fd_in,
+            ..Default::default()
+        }
+    }
+
+    /// Waits until any of the poll `events` is signalled on `fd`.
+    pub fn poll(fd: RawFd, events: libc::c_short) -> Self {
+        let events = events as u16 as u32;
+        Self {
+            opcode: IORING_OP_POLL_ADD,
+            fd,
+            // poll32_events shares the field with the splice flags and is stored half-word swapped on big endian
+            #[cfg(target_endian = "little")]
+            splice_flags: events,
+            #[cfg(target_endian = "big")]
+            splice_flags: events.rotate_left(16),
+            ..Default::default()
+        }
+    }
+
+    /// Cancels the operation with the `user_data`.
+    fn cancel(user_data: u64) -> Self {
+        Self {
+            opcode: IORING_OP_ASYNC_CANCEL,
+            fd: -1,
+            // the target is passed in addr, which shares the field with the splice input offset
+            splice_off_in: user_data,
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+#[repr(C)]
+struct ProbeOp {
+    op: u8,
+    resv: u8,
+    flags: u16,
+    resv2: u32,
+}
+
+#[repr(C)]
+struct Probe {
+    last_op: u8,
+    ops_len: u8,
+    resv: u16,
+    resv2: [u32; 3],
+    ops: [ProbeOp; 256],
+}
+
+/// A memory mapped part of the ring, unmapped on drop.
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: RawFd, offset: libc::off_t, len: usize) -> Result<Self> {
+        // SAFETY: a new shared mapping of the ring fd, the kernel validates the offset and length
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(Self { ptr, len })
+    }
+
+    /// # Safety
+    /// `offset` must be within the mapping and aligned for `T`.
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        // SAFETY: the caller guarantees that the offset is within the mapping
+        unsafe { self.ptr.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `Mmap::new` with this length and is not used afterwards
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// A minimal io_uring instance which runs one operation at a time.
+/// Completions are signalled through an eventfd registered with the ring,
+/// which is polled by the tokio reactor.
+///
+/// Every submission is tagged with its own `user_data` and only its completion is returned.
+/// The operations only reference file descriptors, never userspace memory,
+/// so an operation outliving its future cannot write to freed memory.
+///
+/// Closing a ring does not wait for operations the kernel has already handed to its worker threads,
+/// they keep using their file descriptors, e.g. a pooled pipe, after the ring is gone.
+/// So when a ring is dropped with an operation in flight, it cancels the operation and blocks
+/// until its completion arrives, see [`cancel`](Ring::cancel). Only rings without an operation
+/// in flight are kept for later copies, at most 64 of them.
+pub struct Ring {
+    instance: ManuallyDrop<Instance>,
+    /// A duplicate of the instance's eventfd registered with the reactor of the runtime using the ring,
+    /// idle rings are not registered, so they can be reused from any runtime.
+    event: AsyncFd<OwnedFd>,
+    /// The `user_data` of the operation submitted but not completed yet.
+    in_flight: Option<u64>,
+}
+
+impl Ring {
+    /// Takes an idle ring or sets up a new one. Must be called from a tokio runtime.
+    pub fn new() -> Result<Self> {
+        let idle = IDLE_RINGS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .pop();
+        let instance = match idle {
+            Some(instance) => instance,
+            None => Instance::new(2)?,
+        };
+        Ok(Self {
+            event: AsyncFd::new(instance.event.try_clone()?)?,
+            instance: ManuallyDrop::new(instance),
+            in_flight: None,
+        })
+    }
+
+    /// Whether the kernel supports all the operations.
+    pub fn supports(opcodes: &[u8]) -> bool {
+        Instance::new(1).is_ok_and(|instance| {
+            opcodes
+                .iter()
+                .all(|opcode| instance.supports(*opcode).unwrap_or(false))
+        })
+    }
+
+    /// Submits the operation and waits for its completion, returning its result.
+    pub async fn run(&mut self, sqe: Sqe) -> Result<usize> {
+        let user_data = self.instance.submit(sqe)?;
+        self.in_flight = Some(user_data);
+        loop {
+            if let Some(res) = self.instance.complete(user_data) {
+                self.in_flight = None;
+                return match res {
+                    res if res >= 0 => Ok(res as usize),
+                    res => Err(Error::from_raw_os_error(-res)),
+                };
+            }
+            let mut guard = self.event.readable().await?;
+            let mut count = 0u64;
+            // reset the eventfd, the completion is read from the ring afterwards
+            // SAFETY: count is a valid 8 byte buffer
+            match unsafe {
+                libc::read(
+                    guard.get_inner().as_raw_fd(),
+                    &mut count as *mut u64 as *mut libc::c_void,
+                    8,
+                )
+            } {
+                -1 if Error::last_os_error().kind() == ErrorKind::WouldBlock => guard.clear_ready(),
+                -1 => return Err(Error::last_os_error()),
+                _ => (),
+            }
+        }
+    }
+
+    /// Cancels the operation in flight, if any, and blocks until its completion arrives.
+    /// Operations waiting for readiness are cancelled right away, a splice already running
+    /// on a kernel worker thread is interrupted or, for a file read, finished first.
+    ///
+    /// Returns whether no operation is in flight anymore. If the cancellation could not be submitted
+    /// or waited for, the kernel may still use the file descriptors of the operation.
+    pub fn cancel(&mut self) -> bool {
+        let Some(target) = self.in_flight else {
+            return true;
+        };
+        let Ok(cancel) = self.instance.submit(Sqe::cancel(target)) else {
+            return false;
+        };
+        // wait for the completions of both operations, so none is left for the next copy
+        let mut pending = 2;
+        while pending > 0 {
+            match self.instance.next() {
+                Some((user_data, _)) if user_data == target || user_data == cancel => pending -= 1,
+                Some(_) => (),
+                None => match self.instance.enter(0, 1, IORING_ENTER_GETEVENTS) {
+                    Err(err) if err.kind() != ErrorKind::Interrupted => return false,
+                    _ => (),
+                },
+            }
+        }
+        self.in_flight = None;
+        true
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        let idle = self.cancel();
+        // SAFETY: the instance is not used after it is taken
+        let instance = unsafe { ManuallyDrop::take(&mut self.instance) };
+        if idle {
+            let mut rings = IDLE_RINGS.lock().unwrap_or_else(|err| err.into_inner());
+            if rings.len() < MAX_IDLE_RINGS {
+                rings.push(instance);
+            }
+        }
+    }
+}
+
+/// The io_uring instance of a ring, which is not bound to a runtime.
+struct Instance {
+    // the mappings must be dropped before the ring fd is closed
+    sq: Mmap,
+    cq: Mmap,
+    sqes: Mmap,
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+    event: OwnedFd,
+    fd: OwnedFd,
+    /// The `user_data` of the next submission.
+    next_user_data: u64,
+}
+
+// SAFETY: the raw pointers only point into the mappings, which are owned by the instance and move with it.
+// The instance is only accessed through `&mut self`, so it is not `Sync`.
+unsafe impl Send for Instance {}
+
+impl Instance {
+    fn new(entries: u32) -> Result<Self> {
+        let mut params = Params::default();
+        // SAFETY: params is a valid io_uring_params struct the kernel fills in
+        let fd = match unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        } {
+            -1 => return Err(Error::last_os_error()),
+            // SAFETY: the fd was just created and is owned by nothing else
+            fd => unsafe { OwnedFd::from_raw_fd(fd as RawFd) },
+        };
+        let sq = Mmap::new(
+            fd.as_raw_fd(),
+            IORING_OFF_SQ_RING,
+            params.sq_off.array as usize + params.sq_entries as usize * 4,
+        )?;
+        let cq = Mmap::new(
+            fd.as_raw_fd(),
+            IORING_OFF_CQ_RING,
+            params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>(),
+        )?;
+        let sqes = Mmap::new(
+            fd.as_raw_fd(),
+            IORING_OFF_SQES,
+            params.sq_entries as usize * std::mem::size_of::<Sqe>(),
+        )?;
+        // SAFETY: eventfd takes no pointers
+        let event = match unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) } {
+            -1 => return Err(Error::last_os_error()),
+            // SAFETY: the fd was just created and is owned by nothing else
+            fd => unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+        register(
+            fd.as_raw_fd(),
+            IORING_REGISTER_EVENTFD,
+            &event.as_raw_fd() as *const RawFd as *const libc::c_void,
+            1,
+        )?;
+        Ok(Self {
+            sq,
+            cq,
+            sqes,
+            sq_off: params.sq_off,
+            cq_off: params.cq_off,
+            event,
+            fd,
+            next_user_data: 0,
+        })
+    }
+
+    /// Whether the kernel supports the operation.
+    fn supports(&self, opcode: u8) -> Result<bool> {
+        // SAFETY: the probe is plain integers, all zeroes is a valid value
+        let mut probe: Box<Probe> = Box::new(unsafe { std::mem::zeroed() });
+        register(
+            self.fd.as_raw_fd(),
+            IORING_REGISTER_PROBE,
+            &mut *probe as *mut Probe as *const libc::c_void,
+            256,
+        )?;
+        Ok(
+            opcode <= probe.last_op
+                && probe.ops[opcode as usize].flags & IO_URING_OP_SUPPORTED != 0,
+        )
+    }
+
+    /// Queues the operation and submits it to the kernel, returning its `user_data`.
+    fn submit(&mut self, mut sqe: Sqe) -> Result<u64> {
+        let user_data = self.next_user_data;
+        self.next_user_data = self.next_user_data.wrapping_add(1);
+        sqe.user_data = user_data;
+        // SAFETY: the offsets come from the kernel and lie within the mappings. The ring has no
+        // SQPOLL thread, so the kernel only reads the queue during io_uring_enter, and every entry
+        // is consumed by the io_uring_enter submitting it, so the entry at the tail is free.
+        let (tail, previous) = unsafe {
+            let mask = *self.sq.at::<u32>(self.sq_off.ring_mask);
+            let tail = &*self.sq.at::<AtomicU32>(self.sq_off.tail);
+            let previous = tail.load(Ordering::Relaxed);
+            let index = previous & mask;
+            ptr::write(self.sqes.at::<Sqe>(0).add(index as usize), sqe);
+            *self.sq.at::<u32>(self.sq_off.array).add(index as usize) = index;
+            tail.store(previous.wrapping_add(1), Ordering::Release);
+            (tail, previous)
+        };
+        match self.enter(1, 0, 0) {
+            Ok(1) => Ok(user_data),
+            res => {
+                let err = match res {
+                    Err(err) => err,
+                    Ok(_) => Error::other("io_uring did not consume the submission"),
+                };
+                // the kernel has not consumed the entry, take it back so it never runs later
+                tail.store(previous, Ordering::Release);
+                Err(err)
+            }
+        }
+    }
+
+    /// Submits `to_submit` queued entries and waits for `min_complete` completions if `flags` asks for it.
+    fn enter(&self, to_submit: u32, min_complete: u32, flags: libc::c_uint) -> Result<usize> {
+        // SAFETY: io_uring_enter without a signal mask takes no pointers
+        match unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.fd.as_raw_fd(),
+                to_submit,
+                min_complete,
+                flags,
+                ptr::null::<libc::c_void>(),
+                0,
+            )
+        } {
+            -1 => Err(Error::last_os_error()),
+            res => Ok(res as usize),
+        }
+    }
+
+    /// Consumes the completions in the queue, returning the result of the operation with the `user_data`.
+    fn complete(&mut self, user_data: u64) -> Option<i32> {
+        while let Some((completed, res)) = self.next() {
+            if completed == user_data {
+                return Some(res);
+            }
+        }
+        None
+    }
+
+    /// Consumes the next completion in the queue, returning its `user_data` and result.
+    fn next(&mut self) -> Option<(u64, i32)> {
+        // SAFETY: the offsets come from the kernel and lie within the mappings,
+        // entries between the head and the tail are written by the kernel before it publishes the tail
+        unsafe {
+            let head = &*self.cq.at::<AtomicU32>(self.cq_off.head);
+            let tail = &*self.cq.at::<AtomicU32>(self.cq_off.tail);
+            let mask = *self.cq.at::<u32>(self.cq_off.ring_mask);
+            let current = head.load(Ordering::Relaxed);
+            if current == tail.load(Ordering::Acquire) {
+                return None;
+            }
+            let cqe = &*self
+                .cq
+                .at::<Cqe>(self.cq_off.cqes)
+                .add((current & mask) as usize);
+            let completion = (cqe.user_data, cqe.res);
+            head.store(current.wrapping_add(1), Ordering::Release);
+            Some(completion)
+        }
+    }
+}
+
+fn register(fd: RawFd, opcode: libc::c_uint, arg: *const libc::c_void, nr_args: u32) -> Result<()> {
+    // SAFETY: the callers pass an argument valid for the opcode and `nr_args`
+    match unsafe { libc::syscall(libc::SYS_io_uring_register, fd, opcode, arg, nr_args) } {
+        -1 => Err(Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
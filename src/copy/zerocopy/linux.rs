@@ -0,0 +1,322 @@
+use crate::copy::error::{CopyError, CopyPhase, CopyResult, IoResultExt};
+use crate::copy::options::CopyOptions;
+use crate::copy::stats::{CopyMechanism, CopyStats};
+use essentials::debug;
+use std::io::{Error, ErrorKind, Result};
+use std::net::TcpStream as StdTcpStream;
+use std::os::unix::io::{AsRawFd, RawFd};
+use tokio::io::{unix::AsyncFd, Interest};
+use tokio::net::TcpStream;
+use tokio::runtime::Handle;
+
+// not exported by libc, see `linux/errqueue.h`
+const SO_EE_ORIGIN_ZEROCOPY: u8 = 5;
+const SO_EE_CODE_ZEROCOPY_COPIED: u8 = 1;
+
+/// A tcp stream owned for zero-copy sends with [`send_zerocopy`](crate::send_zerocopy).
+///
+/// The completions of `MSG_ZEROCOPY` sends are read from the error queue of the socket,
+/// and tokio keeps a registration which has seen error events write ready for good.
+/// The sender therefore owns the socket with a registration of its own, which is renewed
+/// once every send is complete, and [`into_inner`](ZeroCopySender::into_inner) registers
+/// the stream afresh, so later copies to it still wait for write readiness.
+///
+/// The kernel reads a buffer until it reports the completion of every send, so the sender holds
+/// the buffer until then, even if the send is cancelled. The next send waits for those sends first,
+/// a sender dropped with sends in flight hands the buffer and the socket to a task which waits
+/// for them before releasing both.
+/// Sockets which do not support `SO_ZEROCOPY` are written with plain writes.
+pub struct ZeroCopySender {
+    /// only taken when the sender is consumed or dropped
+    socket: Option<AsyncFd<StdTcpStream>>,
+    zerocopy: bool,
+    /// the buffer of the last send
+    buf: Option<Box<dyn AsRef<[u8]> + Send>>,
+    /// the number of sends of the buffer the kernel has not completed yet
+    pending: usize,
+    /// whether the registration may have seen error events
+    dirty: bool,
+}
+
+impl ZeroCopySender {
+    /// Take a tcp stream over for zero-copy sends.
+    pub fn new(stream: TcpStream) -> Result<Self> {
+        let stream = stream.into_std()?;
+        let zerocopy = match set_zerocopy(stream.as_raw_fd(), true) {
+            Ok(()) => true,
+            Err(err) => {
+                debug!("sending without MSG_ZEROCOPY: {}", err);
+                false
+            }
+        };
+        Ok(Self {
+            socket: Some(register(stream)?),
+            zerocopy,
+            buf: None,
+            pending: 0,
+            dirty: false,
+        })
+    }
+
+    /// Wait until the kernel has completed every send and return the tcp stream.
+    pub async fn into_inner(mut self) -> Result<TcpStream> {
+        self.complete(&mut CopyStats::new(CopyMechanism::ZeroCopySend))
+            .await?;
+        let stream = self.socket.take().ok_or_else(gone)?.into_inner();
+        if self.zerocopy {
+            set_zerocopy(stream.as_raw_fd(), false)?;
+        }
+        drain(&stream)?;
+        TcpStream::from_std(stream)
+    }
+
+    /// Waits until the kernel has completed every send of the last buffer and releases it.
+    async fn complete(&mut self, stats: &mut CopyStats) -> Result<()> {
+        let socket = self.socket.as_ref().ok_or_else(gone)?;
+        while self.pending > 0 {
+            reap(socket, &mut self.pending, stats).await?;
+        }
+        self.buf = None;
+        Ok(())
+    }
+
+    /// Registers the socket afresh if the registration has seen error events.
+    fn renew(&mut self) -> Result<()> {
+        if self.dirty {
+            let stream = self.socket.take().ok_or_else(gone)?.into_inner();
+            drain(&stream)?;
+            self.socket = Some(register(stream)?);
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn send<B: AsRef<[u8]> + Send + 'static>(
+        &mut self,
+        buf: B,
+        options: &CopyOptions,
+    ) -> CopyResult<CopyStats> {
+        // the sends of a cancelled call are completed first, they are not part of this copy
+        self.complete(&mut CopyStats::new(CopyMechanism::ZeroCopySend))
+            .await
+            .in_phase(CopyPhase::Write, 0)?;
+        self.renew().in_phase(CopyPhase::Write, 0)?;
+        let zerocopy = self.zerocopy && buf.as_ref().len() >= options.get_zerocopy_threshold();
+        let mut stats = if zerocopy {
+            debug!("sending buffer using MSG_ZEROCOPY");
+            CopyStats::new(CopyMechanism::ZeroCopySend)
+        } else {
+            debug!("writing buffer using plain writes");
+            CopyStats::new(CopyMechanism::Buffered)
+        };
+        let socket = self
+            .socket
+            .as_ref()
+            .ok_or_else(gone)
+            .in_phase(CopyPhase::Write, 0)?;
+        let data: &[u8] = (**self.buf.insert(Box::new(buf))).as_ref();
+        let flags = if zerocopy { libc::MSG_ZEROCOPY } else { 0 };
+        while stats.bytes < data.len() {
+            let mut guard = socket
+                .ready(Interest::WRITABLE)
+                .await
+                .in_phase(CopyPhase::Write, stats.bytes)?;
+            stats.write_calls += 1;
+            match send_flags(socket.as_raw_fd(), &data[stats.bytes..], flags) {
+                Ok(0) => {
+                    return Err(CopyError::new(
+                        CopyPhase::Write,
+                        stats.bytes,
+                        Error::new(ErrorKind::WriteZero, "write zero byte into sink"),
+                    ))
+                }
+                Ok(n) => {
+                    stats.bytes += n;
+                    if zerocopy {
+                        self.pending += 1;
+                        self.dirty = true;
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                // The send buffer is full or the sends in flight exceed the socket's option memory
+                // limit. Both are freed when the kernel completes a send, so wait for that
+                // instead of write readiness, which tokio reports for every error queue event.
+                Err(err)
+                    if self.pending > 0
+                        && (err.kind() == ErrorKind::WouldBlock
+                            || err.raw_os_error() == Some(libc::ENOBUFS)) =>
+                {
+                    if err.kind() == ErrorKind::WouldBlock {
+                        stats.would_block += 1;
+                    }
+                    reap(socket, &mut self.pending, &mut stats)
+                        .await
+                        .in_phase(CopyPhase::Write, stats.bytes)?;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    stats.would_block += 1;
+                    guard.clear_ready();
+                }
+                Err(err) => return Err(CopyError::new(CopyPhase::Write, stats.bytes, err)),
+            }
+            try_reap(socket, &mut self.pending, &mut stats)
+                .in_phase(CopyPhase::Write, stats.bytes)?;
+        }
+        self.complete(&mut stats)
+            .await
+            .in_phase(CopyPhase::Write, stats.bytes)?;
+        Ok(stats.finish())
+    }
+}
+
+impl Drop for ZeroCopySender {
+    fn drop(&mut self) {
+        if self.pending == 0 {
+            return;
+        }
+        let (Some(socket), Some(buf)) = (self.socket.take(), self.buf.take()) else {
+            return;
+        };
+        let mut pending = self.pending;
+        let Ok(handle) = Handle::try_current() else {
+            debug!("leaking buffer with {} zero-copy sends in flight", pending);
+            std::mem::forget(buf);
+            return;
+        };
+        debug!("waiting for {} zero-copy sends in the background", pending);
+        handle.spawn(async move {
+            let mut stats = CopyStats::new(CopyMechanism::ZeroCopySend);
+            while pending > 0 {
+                if let Err(err) = reap(&socket, &mut pending, &mut stats).await {
+                    debug!(
+                        "leaking buffer with {} zero-copy sends in flight: {}",
+                        pending, err
+                    );
+                    std::mem::forget(buf);
+                    return;
+                }
+            }
+            drop(buf);
+        });
+    }
+}
+
+fn register(stream: StdTcpStream) -> Result<AsyncFd<StdTcpStream>> {
+    AsyncFd::with_interest(stream, Interest::WRITABLE.add(Interest::ERROR))
+}
+
+fn gone() -> Error {
+    Error::new(ErrorKind::NotConnected, "the socket of the sender is gone")
+}
+
+/// Reads the notifications which are already queued.
+fn try_reap(
+    socket: &AsyncFd<StdTcpStream>,
+    pending: &mut usize,
+    stats: &mut CopyStats,
+) -> Result<()> {
+    loop {
+        match recv_completions(socket.as_raw_fd(), stats) {
+            Ok(n) => *pending = pending.saturating_sub(n),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Waits until the kernel completes at least one more send.
+async fn reap(
+    socket: &AsyncFd<StdTcpStream>,
+    pending: &mut usize,
+    stats: &mut CopyStats,
+) -> Result<()> {
+    let before = *pending;
+    while *pending == before {
+        let mut guard = socket.ready(Interest::ERROR).await?;
+        match recv_completions(socket.as_raw_fd(), stats) {
+            Ok(n) => *pending = pending.saturating_sub(n),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => guard.clear_ready(),
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Empties the error queue of a socket whose sends are complete.
+fn drain(stream: &StdTcpStream) -> Result<()> {
+    let mut stats = CopyStats::new(CopyMechanism::ZeroCopySend);
+    loop {
+        match recv_completions(stream.as_raw_fd(), &mut stats) {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Reads one message from the error queue, returns the number of sends it completes.
+fn recv_completions(fd: RawFd, stats: &mut CopyStats) -> Result<usize> {
+    let mut control = [0u64; 16];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+    if unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    let mut completed = 0;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        if matches!(
+            (header.cmsg_level, header.cmsg_type),
+            (libc::SOL_IP, libc::IP_RECVERR) | (libc::SOL_IPV6, libc::IPV6_RECVERR)
+        ) {
+            let err = unsafe {
+                std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::sock_extended_err>())
+            };
+            if err.ee_errno == 0 && err.ee_origin == SO_EE_ORIGIN_ZEROCOPY {
+                // the notification covers the sends numbered ee_info to ee_data
+                let n = err.ee_data.wrapping_sub(err.ee_info) as usize + 1;
+                completed += n;
+                if err.ee_code & SO_EE_CODE_ZEROCOPY_COPIED != 0 {
+                    stats.zerocopy_copied += n;
+                }
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok(completed)
+}
+
+fn set_zerocopy(fd: RawFd, enabled: bool) -> Result<()> {
+    let value = libc::c_int::from(enabled);
+    match unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ZEROCOPY,
+            (&value as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    } {
+        -1 => Err(Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn send_flags(fd: RawFd, buf: &[u8], flags: libc::c_int) -> Result<usize> {
+    match unsafe {
+        libc::send(
+            fd,
+            buf.as_ptr().cast(),
+            buf.len(),
+            flags | libc::MSG_NOSIGNAL,
+        )
+    } {
+        -1 => Err(Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
@@ -0,0 +1,70 @@
+#[cfg(target_os = "linux")]
+mod linux;
+
+#[cfg(target_os = "linux")]
+pub use linux::ZeroCopySender;
+
+#[cfg(not(target_os = "linux"))]
+use crate::copy::{
+    endpoint::ZeroCopySink,
+    error::{CopyError, CopyPhase, CopyResult, IoResultExt},
+    stats::{CopyMechanism, CopyStats},
+};
+#[cfg(not(target_os = "linux"))]
+use std::io::{Error, ErrorKind};
+#[cfg(not(target_os = "linux"))]
+use tokio::io::AsyncWriteExt;
+
+/// Writes a buffer to a sink with plain writes.
+#[cfg(not(target_os = "linux"))]
+async fn write<W: ZeroCopySink>(w: &mut W, buf: &[u8]) -> CopyResult<CopyStats> {
+    let mut stats = CopyStats::new(CopyMechanism::Buffered);
+    while stats.bytes < buf.len() {
+        stats.write_calls += 1;
+        match w.write(&buf[stats.bytes..]).await {
+            Ok(0) => {
+                return Err(CopyError::new(
+                    CopyPhase::Write,
+                    stats.bytes,
+                    Error::new(ErrorKind::WriteZero, "write zero byte into sink"),
+                ))
+            }
+            Ok(n) => stats.bytes += n,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(CopyError::new(CopyPhase::Write, stats.bytes, err)),
+        }
+    }
+    w.flush().await.in_phase(CopyPhase::Flush, stats.bytes)?;
+    Ok(stats.finish())
+}
+
+/// A tcp stream owned for sends with [`send_zerocopy`](crate::send_zerocopy).
+/// This type is only available on non-linux platforms and uses plain writes.
+#[cfg(not(target_os = "linux"))]
+pub struct ZeroCopySender {
+    stream: tokio::net::TcpStream,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl ZeroCopySender {
+    /// Take a tcp stream over for sends.
+    pub fn new(stream: tokio::net::TcpStream) -> std::io::Result<Self> {
+        Ok(Self { stream })
+    }
+
+    /// Return the tcp stream.
+    pub async fn into_inner(self) -> std::io::Result<tokio::net::TcpStream> {
+        Ok(self.stream)
+    }
+
+    pub(crate) async fn send<B: AsRef<[u8]> + Send + 'static>(
+        &mut self,
+        buf: B,
+        _options: &crate::copy::options::CopyOptions,
+    ) -> CopyResult<CopyStats> {
+        use essentials::debug;
+
+        debug!("writing buffer using plain writes");
+        write(&mut self.stream, buf.as_ref()).await
+    }
+}
@@ -1,6 +1,36 @@
 //! IO utilities for Rust.
 
 mod copy;
+pub mod replay;
 
+pub use copy::copy;
+pub use copy::copy_bidirectional;
 pub use copy::copy_file;
+pub use copy::copy_file_range;
+pub use copy::copy_file_to_file;
 pub use copy::copy_tcp;
+pub use copy::copy_tcp_to_unix;
+pub use copy::copy_unix;
+pub use copy::copy_unix_to_tcp;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use copy::CopyBackend;
+pub use copy::{copy_bidirectional_with, copy_tcp_with, copy_with, CopyOptions};
+pub use copy::{
+    copy_bidirectional_with_stats, copy_file_range_with_stats, copy_file_with_stats,
+    copy_tcp_to_unix_with_stats, copy_tcp_with_stats, copy_unix_to_tcp_with_stats,
+    copy_unix_with_stats, copy_with_stats,
+};
+pub use copy::{copy_file_with, copy_tcp_to_unix_with, copy_unix_to_tcp_with, copy_unix_with};
+pub use copy::{copy_tee, copy_tee_with, ShadowPolicy, TeeStats};
+pub use copy::{copy_with_prefix, copy_with_prefix_with};
+pub use copy::{receive_to_file, receive_to_file_with};
+pub use copy::{send_with_header, send_with_header_with};
+pub use copy::{send_zerocopy, send_zerocopy_with, ZeroCopySender};
+pub use copy::{Capture, CaptureDirection, CaptureFrame, CaptureReader};
+#[cfg(target_os = "linux")]
+pub use copy::{ColdPagePolicy, CopyBudget, PipeCache, PipePool, SpliceConfig};
+pub use copy::{CopyError, CopyPhase, CopyResult};
+pub use copy::{CopyMechanism, CopyProgress, CopyStats, FileRange, SyncPolicy};
+#[cfg(target_os = "linux")]
+pub use copy::{MemFile, MemFileWriter, Spool, SpoolConfig};
+pub use copy::{ZeroCopySink, ZeroCopySource};
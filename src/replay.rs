@@ -0,0 +1,276 @@
+//! Replay of recorded tcp sessions against a server.
+//!
+//! A session is recorded with a [`Capture`](crate::Capture) passed to
+//! [`CopyOptions::capture`](crate::CopyOptions::capture): the data sent by the client is
+//! recorded as [`Forward`](CaptureDirection::Forward) and the responses of the server as
+//! [`Backward`](CaptureDirection::Backward). A proxy copying each direction with
+//! [`copy_tcp_with`](crate::copy_tcp_with) uses [`Capture::backward`](crate::Capture::backward)
+//! for the responses. The client side of the [`Recording`] is then sent to a server
+//! and its responses are compared to the recorded ones.
+
+use crate::{CaptureDirection, CaptureFrame, CaptureReader};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::io::Result;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{self, Instant};
+
+/// the buffer size used to read the responses
+const BUF_SIZE: usize = 64 * 1024;
+
+/// How the client side of a recording is paced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Timing {
+    /// Keep the intervals between the recorded frames.
+    #[default]
+    Original,
+    /// Send the client side as fast as possible.
+    Fast,
+}
+
+/// Options of a replay made with [`Recording::replay`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayOptions {
+    timing: Timing,
+    idle_timeout: Duration,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            timing: Timing::default(),
+            idle_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl ReplayOptions {
+    /// Create the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How the client side is paced, by default with the original timing.
+    pub fn timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// How long to wait for more responses once the client side has been sent,
+    /// unless the server closes the connection earlier. Defaults to 5 seconds.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+}
+
+/// A response of the server which differs from the recorded one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    /// The index of the recorded response, the number of recorded responses
+    /// if the server sent more data than was recorded.
+    pub exchange: usize,
+    /// The offset of the first differing byte in the data sent by the server.
+    pub offset: usize,
+    /// The recorded response.
+    pub expected: Vec<u8>,
+    /// The data the server sent in place of the recorded response.
+    pub actual: Vec<u8>,
+}
+
+/// The result of a replay.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReplayReport {
+    /// The number of bytes sent to the server.
+    pub sent: usize,
+    /// The number of bytes received from the server.
+    pub received: usize,
+    /// The number of bytes the server sent in the recording.
+    pub expected: usize,
+    /// The responses which differ from the recording, in order.
+    pub divergences: Vec<Divergence>,
+    /// When the replay started.
+    pub started_at: DateTime<Utc>,
+    /// When the replay finished.
+    pub finished_at: DateTime<Utc>,
+}
+
+impl ReplayReport {
+    /// Whether the server responded exactly as recorded.
+    pub fn is_match(&self) -> bool {
+        self.divergences.is_empty()
+    }
+
+    /// How long the replay took.
+    pub fn duration(&self) -> chrono::Duration {
+        self.finished_at - self.started_at
+    }
+}
+
+/// A recorded session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    frames: Vec<CaptureFrame>,
+}
+
+impl Recording {
+    /// Create a recording from captured frames.
+    pub fn new(frames: Vec<CaptureFrame>) -> Self {
+        Self { frames }
+    }
+
+    /// Read a recording from a capture file.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::new(CaptureReader::open(path).await?.frames().await?))
+    }
+
+    /// The recorded frames.
+    pub fn frames(&self) -> &[CaptureFrame] {
+        &self.frames
+    }
+
+    /// The data sent by the client.
+    pub fn client_data(&self) -> Vec<u8> {
+        self.data(CaptureDirection::Forward)
+    }
+
+    /// The data sent by the server.
+    pub fn server_data(&self) -> Vec<u8> {
+        self.data(CaptureDirection::Backward)
+    }
+
+    fn data(&self, direction: CaptureDirection) -> Vec<u8> {
+        self.frames
+            .iter()
+            .filter(|frame| frame.direction == direction)
+            .flat_map(|frame| frame.payload.iter().copied())
+            .collect()
+    }
+
+    /// The ranges of the server data of every response,
+    /// a response is made of the server frames recorded between two client frames.
+    fn exchanges(&self) -> Vec<Range<usize>> {
+        let mut exchanges: Vec<Range<usize>> = Vec::new();
+        let mut offset = 0;
+        let mut responding = false;
+        for frame in &self.frames {
+            match frame.direction {
+                CaptureDirection::Forward => responding = false,
+                CaptureDirection::Backward => {
+                    offset += frame.payload.len();
+                    match exchanges.last_mut() {
+                        Some(exchange) if responding => exchange.end = offset,
+                        _ => exchanges.push(offset - frame.payload.len()..offset),
+                    }
+                    responding = true;
+                }
+            }
+        }
+        exchanges
+    }
+
+    /// Connect to a server and replay the session against it.
+    pub async fn replay_to(
+        &self,
+        addr: SocketAddr,
+        options: &ReplayOptions,
+    ) -> Result<ReplayReport> {
+        self.replay(TcpStream::connect(addr).await?, options).await
+    }
+
+    /// Send the client side of the session to a server and compare its responses to the recorded
+    /// ones. The write side of the stream is shut down once the client side has been sent,
+    /// the responses are read until EOF or until the idle timeout elapses.
+    pub async fn replay<S>(&self, stream: S, options: &ReplayOptions) -> Result<ReplayReport>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let started_at = Utc::now();
+        let (mut reader, mut writer) = tokio::io::split(stream);
+        let sent_all = AtomicBool::new(false);
+        let send = async {
+            let start = Instant::now();
+            let first = self.frames.first().map(|frame| frame.timestamp);
+            let mut sent = 0;
+            for frame in &self.frames {
+                if frame.direction != CaptureDirection::Forward {
+                    continue;
+                }
+                if let (Timing::Original, Some(first)) = (options.timing, first) {
+                    let offset = (frame.timestamp - first).to_std().unwrap_or_default();
+                    time::sleep_until(start + offset).await;
+                }
+                writer.write_all(&frame.payload).await?;
+                sent += frame.payload.len();
+            }
+            writer.shutdown().await?;
+            sent_all.store(true, Ordering::Relaxed);
+            Ok(sent)
+        };
+        let receive = async {
+            let mut received = Vec::new();
+            let mut buf = vec![0; BUF_SIZE];
+            loop {
+                match time::timeout(options.idle_timeout, reader.read(&mut buf)).await {
+                    Ok(Ok(0)) => break,
+                    Ok(Ok(n)) => received.extend_from_slice(&buf[..n]),
+                    Ok(Err(err)) => return Err(err),
+                    // the server may be waiting for the rest of the client side
+                    Err(_) if !sent_all.load(Ordering::Relaxed) => continue,
+                    Err(_) => break,
+                }
+            }
+            Ok(received)
+        };
+        let (sent, received) = tokio::try_join!(send, receive)?;
+        let expected = self.server_data();
+        Ok(ReplayReport {
+            sent,
+            received: received.len(),
+            expected: expected.len(),
+            divergences: compare(&expected, &self.exchanges(), &received),
+            started_at,
+            finished_at: Utc::now(),
+        })
+    }
+}
+
+/// Compares every recorded response to the data the server sent at the same offset.
+fn compare(expected: &[u8], exchanges: &[Range<usize>], actual: &[u8]) -> Vec<Divergence> {
+    let mut divergences = Vec::new();
+    for (exchange, range) in exchanges.iter().enumerate() {
+        let recorded = &expected[range.clone()];
+        let replayed = actual
+            .get(range.start..range.end.min(actual.len()))
+            .unwrap_or_default();
+        if recorded != replayed {
+            let same = recorded
+                .iter()
+                .zip(replayed)
+                .take_while(|(recorded, replayed)| recorded == replayed)
+                .count();
+            divergences.push(Divergence {
+                exchange,
+                offset: range.start + same,
+                expected: recorded.to_vec(),
+                actual: replayed.to_vec(),
+            });
+        }
+    }
+    if actual.len() > expected.len() {
+        divergences.push(Divergence {
+            exchange: exchanges.len(),
+            offset: expected.len(),
+            expected: Vec::new(),
+            actual: actual[expected.len()..].to_vec(),
+        });
+    }
+    divergences
+}
@@ -0,0 +1,134 @@
+use std::{env, path::PathBuf};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}_{}.cap", name, std::process::id()))
+}
+
+fn payload(frames: &[::io::CaptureFrame], direction: ::io::CaptureDirection) -> Vec<u8> {
+    frames
+        .iter()
+        .filter(|frame| frame.direction == direction)
+        .flat_map(|frame| frame.payload.iter().copied())
+        .collect()
+}
+
+#[tokio::test]
+async fn capture_bidirectional() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("capture_bidirectional");
+    let response = tokio::fs::read("long_file.txt").await.unwrap()[..1024 * 1024].to_vec();
+    let mock_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mock_addr = mock_listener.local_addr().unwrap();
+    let mock_response = response.clone();
+    tokio::spawn(async move {
+        let (mut server, _) = mock_listener.accept().await.unwrap();
+        let mut buf = Vec::new();
+        server.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello");
+        server.write_all(&mock_response).await.unwrap();
+        server.shutdown().await.unwrap();
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let capture = ::io::Capture::create(&path).await.unwrap();
+    let options = ::io::CopyOptions::new().capture(capture.clone());
+    let proxy = tokio::spawn(async move {
+        let mut left = listener.accept().await.unwrap().0;
+        let mut right = tokio::net::TcpStream::connect(&mock_addr).await.unwrap();
+        ::io::copy_bidirectional_with(&mut left, &mut right, &options).await
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    assert!(buf == response);
+    let (a_to_b, b_to_a) = proxy.await.unwrap().unwrap();
+    assert_eq!(a_to_b.bytes, 5);
+    assert_eq!(b_to_a.bytes, response.len());
+    assert_eq!(
+        capture.len(),
+        std::fs::metadata(&path).unwrap().len(),
+        "the capture length is the file length"
+    );
+    let frames = ::io::CaptureReader::open(&path)
+        .await
+        .unwrap()
+        .frames()
+        .await
+        .unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(payload(&frames, ::io::CaptureDirection::Forward), b"hello");
+    assert!(payload(&frames, ::io::CaptureDirection::Backward) == response);
+    assert!(frames
+        .windows(2)
+        .all(|frames| frames[0].timestamp <= frames[1].timestamp));
+}
+
+#[tokio::test]
+async fn capture_copy_tcp() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("capture_copy_tcp");
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let upload = data.clone();
+    tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        client.write_all(&upload).await.unwrap();
+        client.shutdown().await.unwrap();
+    });
+    let (mut rx, _) = listener.accept().await.unwrap().0.into_split();
+    let sink_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let sink_addr = sink_listener.local_addr().unwrap();
+    let sink = tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&sink_addr).await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let (_, mut tx) = sink_listener.accept().await.unwrap().0.into_split();
+    let options = ::io::CopyOptions::new().capture(::io::Capture::create(&path).await.unwrap());
+    let stats = ::io::copy_tcp_with(&mut rx, &mut tx, None, &options)
+        .await
+        .unwrap();
+    drop(tx);
+    assert_eq!(stats.bytes, data.len());
+    assert!(sink.await.unwrap() == data);
+    let mut reader = ::io::CaptureReader::open(&path).await.unwrap();
+    let mut captured = Vec::new();
+    while let Some(frame) = reader.next_frame().await.unwrap() {
+        assert_eq!(frame.direction, ::io::CaptureDirection::Forward);
+        assert!(!frame.payload.is_empty());
+        captured.extend_from_slice(&frame.payload);
+    }
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert!(captured == data);
+}
+
+#[tokio::test]
+async fn capture_reader_invalid() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let err = ::io::CaptureReader::open("long_file.txt")
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    let mut truncated = ::io::CaptureReader::new(&b"IOCAP001\x00\x00"[..])
+        .await
+        .unwrap();
+    assert_eq!(
+        truncated.next_frame().await.unwrap_err().kind(),
+        std::io::ErrorKind::UnexpectedEof
+    );
+}
@@ -0,0 +1,171 @@
+use std::{env, net::SocketAddr, path::PathBuf};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}_{}.pcapng", name, std::process::id()))
+}
+
+/// A tcp segment parsed from an enhanced packet block.
+struct Segment {
+    src: SocketAddr,
+    dst: SocketAddr,
+    flags: u8,
+    payload: Vec<u8>,
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+/// Parses the blocks of a pcapng file with IPv4 packets.
+fn parse(mut data: &[u8]) -> Vec<Segment> {
+    assert_eq!(u32_le(data), 0x0A0D0D0A);
+    assert_eq!(u32_le(&data[8..]), 0x1A2B3C4D);
+    let mut segments = Vec::new();
+    while !data.is_empty() {
+        let kind = u32_le(data);
+        let len = u32_le(&data[4..]) as usize;
+        assert_eq!(len % 4, 0);
+        assert_eq!(u32_le(&data[len - 4..]), len as u32);
+        if kind == 6 {
+            let captured = u32_le(&data[20..]) as usize;
+            let packet = &data[28..28 + captured];
+            assert_eq!(&packet[12..14], &[0x08, 0x00]);
+            let ip = &packet[14..];
+            let ip_len = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+            let src_ip: [u8; 4] = ip[12..16].try_into().unwrap();
+            let dst_ip: [u8; 4] = ip[16..20].try_into().unwrap();
+            let tcp = &ip[20..ip_len];
+            segments.push(Segment {
+                src: SocketAddr::from((src_ip, u16::from_be_bytes([tcp[0], tcp[1]]))),
+                dst: SocketAddr::from((dst_ip, u16::from_be_bytes([tcp[2], tcp[3]]))),
+                flags: tcp[13],
+                payload: tcp[20..].to_vec(),
+            });
+        }
+        data = &data[len..];
+    }
+    segments
+}
+
+fn payload(segments: &[Segment], src: SocketAddr) -> Vec<u8> {
+    segments
+        .iter()
+        .filter(|segment| segment.src == src)
+        .flat_map(|segment| segment.payload.iter().copied())
+        .collect()
+}
+
+#[tokio::test]
+async fn capture_pcapng_file() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("capture_pcapng_file");
+    let response = tokio::fs::read("long_file.txt").await.unwrap()[..300 * 1024].to_vec();
+    let mock_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mock_addr = mock_listener.local_addr().unwrap();
+    let mock_response = response.clone();
+    tokio::spawn(async move {
+        let (mut server, _) = mock_listener.accept().await.unwrap();
+        let mut buf = Vec::new();
+        server.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello");
+        server.write_all(&mock_response).await.unwrap();
+        server.shutdown().await.unwrap();
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut left = listener.accept().await.unwrap().0;
+    let mut right = tokio::net::TcpStream::connect(&mock_addr).await.unwrap();
+    let (client_addr, server_addr) = (left.peer_addr().unwrap(), right.peer_addr().unwrap());
+    let capture = ::io::Capture::create_pcapng_tcp(&path, &left, &right)
+        .await
+        .unwrap();
+    assert!(capture.is_empty());
+    let options = ::io::CopyOptions::new().capture(capture.clone());
+    let proxy = tokio::spawn(async move {
+        ::io::copy_bidirectional_with(&mut left, &mut right, &options).await
+    });
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    assert!(buf == response);
+    proxy.await.unwrap().unwrap();
+    capture.close().await.unwrap();
+    let data = tokio::fs::read(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(data.len() as u64, capture.len());
+    let segments = parse(&data);
+    assert!(segments.iter().all(|segment| {
+        (segment.src, segment.dst) == (client_addr, server_addr)
+            || (segment.src, segment.dst) == (server_addr, client_addr)
+    }));
+    // the synthesised handshake and the FINs of both sides
+    assert_eq!(segments[0].flags, 0x02);
+    assert_eq!(segments[1].flags, 0x12);
+    assert_eq!(
+        segments
+            .iter()
+            .filter(|segment| segment.flags & 0x01 != 0)
+            .count(),
+        2
+    );
+    assert_eq!(payload(&segments, client_addr), b"hello");
+    assert!(payload(&segments, server_addr) == response);
+    // closing again is a no-op
+    capture.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn capture_pcapng_writer() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap()[..2 * 1024 * 1024].to_vec();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let upload = data.clone();
+    tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        client.write_all(&upload).await.unwrap();
+        client.shutdown().await.unwrap();
+    });
+    let (mut rx, _) = listener.accept().await.unwrap().0.into_split();
+    let (client_addr, proxy_addr) = (rx.peer_addr().unwrap(), rx.local_addr().unwrap());
+    let sink_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let sink_addr = sink_listener.local_addr().unwrap();
+    let sink = tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&sink_addr).await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let (_, mut tx) = sink_listener.accept().await.unwrap().0.into_split();
+    let (writer, mut reader) = tokio::io::duplex(64 * 1024);
+    let pcapng = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let capture = ::io::Capture::pcapng(writer, client_addr, proxy_addr);
+    let options = ::io::CopyOptions::new().capture(capture.clone());
+    let stats = ::io::copy_tcp_with(&mut rx, &mut tx, None, &options)
+        .await
+        .unwrap();
+    drop(tx);
+    assert_eq!(stats.bytes, data.len());
+    assert!(sink.await.unwrap() == data);
+    capture.close().await.unwrap();
+    let segments = parse(&pcapng.await.unwrap());
+    assert!(payload(&segments, client_addr) == data);
+    assert!(payload(&segments, proxy_addr).is_empty());
+    assert!(segments
+        .iter()
+        .all(|segment| segment.payload.len() <= 65_000));
+}
@@ -0,0 +1,13 @@
+//! Helpers shared by the integration tests.
+
+/// The CPU time consumed by the calling thread.
+/// The tests run on a current-thread runtime, so this is the time the runtime spent busy.
+#[cfg(target_os = "linux")]
+pub fn thread_cpu_time() -> std::time::Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+    std::time::Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
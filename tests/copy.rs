@@ -0,0 +1,67 @@
+use std::env;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{unix::pipe, TcpListener, UnixStream},
+};
+
+#[tokio::test]
+async fn copy_pipe_to_tcp() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let (mut tx, mut rx) = pipe::pipe().unwrap();
+        tx.write_all(b"hello").await.unwrap();
+        drop(tx);
+        ::io::copy(&mut rx, &mut stream, None).await
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"hello");
+    assert_eq!(server.await.unwrap().unwrap(), 5);
+}
+
+#[tokio::test]
+async fn copy_file_to_unix() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let (mut left, mut right) = UnixStream::pair().unwrap();
+    let server = tokio::spawn(async move {
+        let mut mock_file = tokio::fs::File::open("long_file.txt").await.unwrap();
+        ::io::copy(&mut mock_file, &mut left, Some(1024 * 1024)).await
+    });
+    let mut buf = Vec::new();
+    right.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(server.await.unwrap().unwrap(), 1024 * 1024);
+    let content = tokio::fs::read("long_file.txt").await.unwrap();
+    assert_eq!(buf, &content[..1024 * 1024]);
+}
+
+#[tokio::test]
+async fn copy_tcp_to_file() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let path = env::temp_dir().join(format!("io-copy-tcp-to-file-{}", std::process::id()));
+    let mut file = tokio::fs::File::create(&path).await.unwrap();
+    tokio::spawn(async move {
+        let (mut server, _) = listener.accept().await.unwrap();
+        let chunk = [b'x'; 1024].as_slice();
+        for _ in 0..1024 {
+            server.write_all(chunk).await.unwrap();
+        }
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let n = ::io::copy(&mut client, &mut file, None).await.unwrap();
+    assert_eq!(n, 1024 * 1024);
+    let content = tokio::fs::read(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(content, [b'x'; 1024 * 1024]);
+}
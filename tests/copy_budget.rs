@@ -0,0 +1,80 @@
+#![cfg(target_os = "linux")]
+
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+#[tokio::test]
+async fn copy_budget_file_to_file() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    // files are always ready, so only the budget makes the copy yield
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let ticker = tokio::spawn({
+        let ticks = ticks.clone();
+        async move {
+            loop {
+                ticks.fetch_add(1, Ordering::Relaxed);
+                tokio::task::yield_now().await;
+            }
+        }
+    });
+    let path = env::temp_dir().join(format!("copy_budget_{}.txt", std::process::id()));
+    let mut src = tokio::fs::File::open("long_file.txt").await.unwrap();
+    let mut dst = tokio::fs::File::create(&path).await.unwrap();
+    let options = ::io::CopyOptions::new().budget(::io::CopyBudget::new(1024 * 1024, 1024));
+    let stats = ::io::copy_with(&mut src, &mut dst, None, &options)
+        .await
+        .unwrap();
+    let ticked = ticks.load(Ordering::Relaxed);
+    ticker.abort();
+    let len = tokio::fs::metadata(&path).await.unwrap().len();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(stats.bytes, 20 * 1024 * 1024);
+    assert_eq!(len, 20 * 1024 * 1024);
+    // the budget is checked between syscalls and sendfile moves up to 2 MiB at once
+    assert!(stats.yields >= 9);
+    assert!(ticked >= 9);
+}
+
+#[tokio::test]
+async fn copy_budget_syscalls() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(async move {
+        let (mut rx, mut tx) = listener.accept().await.unwrap().0.into_split();
+        let options = ::io::CopyOptions::new().budget(::io::CopyBudget::new(usize::MAX, 2));
+        ::io::copy_with(&mut rx, &mut tx, None, &options)
+            .await
+            .unwrap()
+    });
+    let (mut rx, mut tx) = tokio::net::TcpStream::connect(&addr)
+        .await
+        .unwrap()
+        .into_split();
+    let reader = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        rx.read_to_end(&mut buf).await.unwrap();
+        buf.len()
+    });
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    tx.write_all(&data).await.unwrap();
+    tx.shutdown().await.unwrap();
+    let stats = proxy.await.unwrap();
+    assert_eq!(stats.bytes, data.len());
+    // a fill and a write are allowed per poll
+    assert!(stats.yields > 0);
+    assert_eq!(reader.await.unwrap(), data.len());
+}
@@ -0,0 +1,91 @@
+use std::env;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+#[tokio::test]
+async fn copy_file_error() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (_left_rx, mut left_tx) = listener.accept().await.unwrap().0.into_split();
+        let mut mock_file = tokio::fs::File::open("long_file.txt").await.unwrap();
+        let result = ::io::copy_file(&mut mock_file, &mut left_tx, None).await;
+        let position = tokio::io::AsyncSeekExt::stream_position(&mut mock_file)
+            .await
+            .unwrap();
+        (result, position)
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut buf = [0; 64 * 1024];
+    client.read_exact(&mut buf).await.unwrap();
+    drop(client);
+    let (result, position) = server.await.unwrap();
+    let err = result.unwrap_err();
+    if cfg!(target_os = "linux") {
+        // the file position is advanced by the bytes sent before the failure
+        assert_eq!(position, err.transferred() as u64);
+    }
+    let expected = if cfg!(target_os = "linux") {
+        ::io::CopyPhase::SendFile
+    } else {
+        ::io::CopyPhase::Write
+    };
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    let expected = if ::io::CopyBackend::get_default() == ::io::CopyBackend::IoUring {
+        ::io::CopyPhase::SpliceOut
+    } else {
+        expected
+    };
+    assert_eq!(err.phase(), expected);
+    assert!(err.transferred() >= 64 * 1024);
+    assert!(err.transferred() < 20 * 1024 * 1024);
+    let kind = err.kind();
+    let err: std::io::Error = err.into();
+    assert_eq!(err.kind(), kind);
+}
+
+#[tokio::test]
+async fn copy_tcp_error() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let mock_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mock_addr = mock_listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut server, _) = mock_listener.accept().await.unwrap();
+        let chunk = [b'x'; 1024].as_slice();
+        for _ in 0..(20 * 1024) {
+            if server.write_all(chunk).await.is_err() {
+                break;
+            }
+        }
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(async move {
+        let (_left_rx, mut left_tx) = listener.accept().await.unwrap().0.into_split();
+        let (mut right_rx, _right_tx) = tokio::net::TcpStream::connect(&mock_addr)
+            .await
+            .unwrap()
+            .into_split();
+        ::io::copy_tcp(&mut right_rx, &mut left_tx, None).await
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut buf = [0; 64 * 1024];
+    client.read_exact(&mut buf).await.unwrap();
+    drop(client);
+    let err = proxy.await.unwrap().unwrap_err();
+    let expected = if cfg!(target_os = "linux") {
+        ::io::CopyPhase::SpliceOut
+    } else {
+        ::io::CopyPhase::Write
+    };
+    assert_eq!(err.phase(), expected);
+    assert!(err.transferred() >= 64 * 1024);
+    assert!(err.transferred() < 20 * 1024 * 1024);
+}
@@ -1,6 +1,8 @@
-use std::env;
+mod common;
+
+use std::{env, io::SeekFrom};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
     net::TcpListener,
 };
 
@@ -67,3 +69,95 @@ async fn copy_file_exact() {
     // 20 MB file
     assert_eq!(len, 1024 * 1024);
 }
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn copy_file_stalled_reader() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (_left_rx, mut left_tx) = listener.accept().await.unwrap().0.into_split();
+        let mut mock_file = tokio::fs::File::open("long_file.txt").await.unwrap();
+        ::io::copy_file(&mut mock_file, &mut left_tx, None)
+            .await
+            .unwrap()
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    // let the socket buffers fill up while the client is not reading
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let start = common::thread_cpu_time();
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let spent = common::thread_cpu_time() - start;
+    // the runtime runs on this thread, a busy loop would burn the whole 500ms
+    assert!(spent < std::time::Duration::from_millis(50), "{spent:?}");
+    let mut len = 0;
+    let mut buf = [0; 1024];
+    loop {
+        let n = client.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    // 20 MB file
+    assert_eq!(len, 20 * 1024 * 1024);
+    assert_eq!(server.await.unwrap(), 20 * 1024 * 1024);
+}
+
+#[tokio::test]
+async fn copy_file_range() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (_left_rx, mut left_tx) = listener.accept().await.unwrap().0.into_split();
+        let mut mock_file = tokio::fs::File::open("long_file.txt").await.unwrap();
+        let n = ::io::copy_file_range(&mut mock_file, &mut left_tx, 1000, Some(5000))
+            .await
+            .unwrap();
+        assert_eq!(n, 5000);
+        // the file position is left untouched
+        assert_eq!(mock_file.stream_position().await.unwrap(), 0);
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    server.await.unwrap();
+    let content = tokio::fs::read("long_file.txt").await.unwrap();
+    assert_eq!(buf, &content[1000..6000]);
+}
+
+#[tokio::test]
+async fn copy_file_from_position() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (_left_rx, mut left_tx) = listener.accept().await.unwrap().0.into_split();
+        let mut mock_file = tokio::fs::File::open("long_file.txt").await.unwrap();
+        mock_file.seek(SeekFrom::End(-4096)).await.unwrap();
+        let n = ::io::copy_file(&mut mock_file, &mut left_tx, Some(1024))
+            .await
+            .unwrap();
+        assert_eq!(n, 1024);
+        let n = ::io::copy_file(&mut mock_file, &mut left_tx, None)
+            .await
+            .unwrap();
+        assert_eq!(n, 3072);
+        // the file position is advanced to the end
+        assert_eq!(mock_file.stream_position().await.unwrap(), 20 * 1024 * 1024);
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    server.await.unwrap();
+    let content = tokio::fs::read("long_file.txt").await.unwrap();
+    assert_eq!(buf, &content[content.len() - 4096..]);
+}
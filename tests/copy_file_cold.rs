@@ -0,0 +1,141 @@
+#![cfg(target_os = "linux")]
+
+use std::{env, os::fd::AsRawFd, sync::Arc};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::Semaphore,
+};
+
+/// Writes a file and evicts it from the page cache.
+async fn cold_file(path: &str, data: &[u8]) -> tokio::fs::File {
+    let mut file = tokio::fs::File::create(path).await.unwrap();
+    file.write_all(data).await.unwrap();
+    file.sync_all().await.unwrap();
+    drop(file);
+    let file = tokio::fs::File::open(path).await.unwrap();
+    unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    file
+}
+
+async fn send_file(
+    mut file: tokio::fs::File,
+    options: ::io::CopyOptions,
+) -> (::io::CopyStats, Vec<u8>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let (_, mut tx) = listener.accept().await.unwrap().0.into_split();
+    let stats = ::io::copy_with(&mut file, &mut tx, None, &options)
+        .await
+        .unwrap();
+    tx.shutdown().await.unwrap();
+    (stats, client.await.unwrap())
+}
+
+#[tokio::test]
+async fn copy_file_cold_readahead() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap()[..8 * 1024 * 1024].to_vec();
+    let file = cold_file("cold_readahead.txt", &data).await;
+    let options = ::io::CopyOptions::new().cold_page_policy(::io::ColdPagePolicy::Readahead(Some(
+        Arc::new(Semaphore::new(1)),
+    )));
+    let (stats, received) = send_file(file, options).await;
+    tokio::fs::remove_file("cold_readahead.txt").await.unwrap();
+    assert_eq!(stats.bytes, data.len());
+    assert!(received == data);
+    if stats.mechanism == ::io::CopyMechanism::IoUring {
+        // io_uring reads cold pages on kernel workers, nothing is probed
+        assert_eq!(stats.cold_chunks, 0);
+    } else {
+        // the file is probed in 2 MiB windows
+        assert!(stats.cold_chunks > 0);
+        assert!(stats.cold_chunks <= 4);
+    }
+}
+
+#[tokio::test]
+async fn copy_file_cold_block() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap()[..1024 * 1024].to_vec();
+    let file = cold_file("cold_block.txt", &data).await;
+    let options = ::io::CopyOptions::new().cold_page_policy(::io::ColdPagePolicy::Block);
+    let (stats, received) = send_file(file, options).await;
+    tokio::fs::remove_file("cold_block.txt").await.unwrap();
+    assert_eq!(stats.bytes, data.len());
+    assert!(received == data);
+    assert_eq!(stats.cold_chunks, 0);
+}
+
+#[tokio::test]
+async fn copy_file_cold_middle() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap()[..2 * 1024 * 1024].to_vec();
+    let file = cold_file("cold_middle.txt", &data).await;
+    // only the middle of the window is cold, its first and last pages are cached
+    tokio::fs::read("cold_middle.txt").await.unwrap();
+    unsafe {
+        libc::posix_fadvise(
+            file.as_raw_fd(),
+            512 * 1024,
+            1024 * 1024,
+            libc::POSIX_FADV_DONTNEED,
+        )
+    };
+    let options = ::io::CopyOptions::new();
+    let (stats, received) = send_file(file, options).await;
+    tokio::fs::remove_file("cold_middle.txt").await.unwrap();
+    assert_eq!(stats.bytes, data.len());
+    assert!(received == data);
+    if stats.mechanism != ::io::CopyMechanism::IoUring {
+        assert_eq!(stats.cold_chunks, 1);
+    }
+}
+
+#[tokio::test]
+async fn copy_file_with_cold() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap()[..4 * 1024 * 1024].to_vec();
+    let mut file = cold_file("cold_copy_file_with.txt", &data).await;
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let (_, mut tx) = listener.accept().await.unwrap().0.into_split();
+    let options = ::io::CopyOptions::new().cold_page_policy(::io::ColdPagePolicy::Readahead(None));
+    let stats = ::io::copy_file_with(&mut file, &mut tx, Some(3 * 1024 * 1024), &options)
+        .await
+        .unwrap();
+    tx.shutdown().await.unwrap();
+    tokio::fs::remove_file("cold_copy_file_with.txt")
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes, 3 * 1024 * 1024);
+    assert!(client.await.unwrap() == data[..3 * 1024 * 1024]);
+    // the file position is advanced like with copy_file
+    let position = tokio::io::AsyncSeekExt::stream_position(&mut file)
+        .await
+        .unwrap();
+    assert_eq!(position, 3 * 1024 * 1024);
+    if stats.mechanism != ::io::CopyMechanism::IoUring {
+        assert!(stats.cold_chunks > 0);
+    }
+}
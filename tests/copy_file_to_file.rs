@@ -0,0 +1,139 @@
+use std::{env, path::PathBuf};
+use tokio::io::AsyncSeekExt;
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}_{}.txt", name, std::process::id()))
+}
+
+#[tokio::test]
+async fn copy_file_to_file_progress() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("copy_file_to_file_progress");
+    let mut src = tokio::fs::File::open("long_file.txt").await.unwrap();
+    let mut dst = tokio::fs::File::create(&path).await.unwrap();
+    let (progress, mut updates) = tokio::sync::watch::channel(::io::CopyProgress::default());
+    let watcher = tokio::spawn(async move {
+        let mut seen = Vec::new();
+        while updates.changed().await.is_ok() {
+            seen.push(*updates.borrow_and_update());
+        }
+        seen
+    });
+    let options = ::io::CopyOptions::new().progress(progress);
+    let stats = ::io::copy_file_to_file(&mut src, &mut dst, ::io::FileRange::new(), &options)
+        .await
+        .unwrap();
+    drop(options);
+    let seen = watcher.await.unwrap();
+    let copied = tokio::fs::read(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(stats.bytes, 20 * 1024 * 1024);
+    assert!(copied == tokio::fs::read("long_file.txt").await.unwrap());
+    if cfg!(target_os = "linux") {
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::CopyFileRange);
+        assert!(stats.copy_file_range_calls > 0);
+    } else {
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::Buffered);
+    }
+    assert_eq!(
+        seen.last(),
+        Some(&::io::CopyProgress {
+            bytes: 20 * 1024 * 1024,
+            remaining: 0,
+        })
+    );
+    assert!(seen
+        .iter()
+        .all(|progress| progress.bytes + progress.remaining == 20 * 1024 * 1024));
+}
+
+#[tokio::test]
+async fn copy_file_to_file_range() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("copy_file_to_file_range");
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let mut src = tokio::fs::File::open("long_file.txt").await.unwrap();
+    let mut dst = tokio::fs::File::create(&path).await.unwrap();
+    let range = ::io::FileRange::new()
+        .src_offset(1000)
+        .dst_offset(10)
+        .length(3 * 1024 * 1024);
+    let stats = ::io::copy_file_to_file(&mut src, &mut dst, range, &::io::CopyOptions::new())
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes, 3 * 1024 * 1024);
+    assert_eq!(src.stream_position().await.unwrap(), 0);
+    assert_eq!(dst.stream_position().await.unwrap(), 0);
+    let copied = tokio::fs::read(&path).await.unwrap();
+    assert_eq!(copied.len(), 10 + 3 * 1024 * 1024);
+    assert!(copied[10..] == data[1000..1000 + 3 * 1024 * 1024]);
+
+    // a range past the end of the source file is cut short
+    let range = ::io::FileRange::new()
+        .src_offset(data.len() as u64 - 100)
+        .length(1024);
+    let stats = ::io::copy_file_to_file(&mut src, &mut dst, range, &::io::CopyOptions::new())
+        .await
+        .unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(stats.bytes, 100);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn copy_file_to_file_reflink() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("copy_file_to_file_reflink");
+    let mut src = tokio::fs::File::open("long_file.txt").await.unwrap();
+    let mut dst = tokio::fs::File::create(&path).await.unwrap();
+    let options = ::io::CopyOptions::new().reflink(true);
+    let stats = ::io::copy_file_to_file(&mut src, &mut dst, ::io::FileRange::new(), &options)
+        .await
+        .unwrap();
+    let copied = tokio::fs::read(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(stats.bytes, 20 * 1024 * 1024);
+    assert!(copied == tokio::fs::read("long_file.txt").await.unwrap());
+    // the copy falls back when the file systems cannot share extents
+    assert!(matches!(
+        stats.mechanism,
+        ::io::CopyMechanism::Reflink | ::io::CopyMechanism::CopyFileRange
+    ));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn copy_file_to_file_cross_fs() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    // tmpfs and the working directory usually are different file systems
+    let Ok(dir) = std::fs::metadata("/dev/shm") else {
+        return;
+    };
+    assert!(dir.is_dir());
+    let path = PathBuf::from(format!(
+        "/dev/shm/copy_file_to_file_{}.txt",
+        std::process::id()
+    ));
+    let mut src = tokio::fs::File::open("long_file.txt").await.unwrap();
+    let mut dst = tokio::fs::File::create(&path).await.unwrap();
+    let range = ::io::FileRange::new().length(5 * 1024 * 1024 + 3);
+    let stats = ::io::copy_file_to_file(&mut src, &mut dst, range, &::io::CopyOptions::new())
+        .await
+        .unwrap();
+    let copied = tokio::fs::read(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(stats.bytes, 5 * 1024 * 1024 + 3);
+    assert!(copied == tokio::fs::read("long_file.txt").await.unwrap()[..5 * 1024 * 1024 + 3]);
+    assert!(matches!(
+        stats.mechanism,
+        ::io::CopyMechanism::CopyFileRange | ::io::CopyMechanism::Splice
+    ));
+}
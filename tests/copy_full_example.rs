@@ -167,7 +167,7 @@ async fn copy_exact() {
         .await
         .into_iter()
         .map(|x| x.unwrap())
-        .collect::<std::io::Result<Vec<_>>>()
+        .collect::<::io::CopyResult<Vec<_>>>()
         .unwrap();
     });
     let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
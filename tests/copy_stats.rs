@@ -0,0 +1,81 @@
+use std::env;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+#[tokio::test]
+async fn copy_file_stats() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf.len()
+    });
+    let (_, mut left_tx) = listener.accept().await.unwrap().0.into_split();
+    let mut mock_file = tokio::fs::File::open("long_file.txt").await.unwrap();
+    let stats = ::io::copy_file_with_stats(&mut mock_file, &mut left_tx, Some(1024 * 1024))
+        .await
+        .unwrap();
+    drop(left_tx);
+    assert_eq!(client.await.unwrap(), 1024 * 1024);
+    assert_eq!(stats.bytes, 1024 * 1024);
+    assert!(stats.finished_at >= stats.started_at);
+    if stats.mechanism == ::io::CopyMechanism::IoUring {
+        // the io_uring backend is selected with IO_COPY_BACKEND=io-uring
+        assert!(stats.splice_calls > 0);
+        assert_eq!(stats.sendfile_calls, 0);
+    } else if cfg!(target_os = "linux") {
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::SendFile);
+        assert!(stats.sendfile_calls > 0);
+        assert_eq!(stats.splice_calls, 0);
+    } else {
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::Buffered);
+        assert!(stats.read_calls > 0 && stats.write_calls > 0);
+    }
+}
+
+#[tokio::test]
+async fn copy_tcp_stats() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(async move {
+        let (mut rx, mut tx) = listener.accept().await.unwrap().0.into_split();
+        ::io::copy_tcp_with_stats(&mut rx, &mut tx, None)
+            .await
+            .unwrap()
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"hello");
+    let stats = proxy.await.unwrap();
+    assert_eq!(stats.bytes, 5);
+    if stats.mechanism == ::io::CopyMechanism::IoUring {
+        assert!(stats.splice_calls >= 3);
+        assert_eq!(stats.sendfile_calls, 0);
+    } else if cfg!(target_os = "linux") {
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::Splice);
+        // at least one splice into the pipe, one out of it and one reading EOF
+        assert!(stats.splice_calls >= 3);
+        assert_eq!(stats.sendfile_calls, 0);
+    } else {
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::Buffered);
+    }
+
+    let json = serde_json::to_value(&stats).unwrap();
+    assert_eq!(json["bytes"], 5);
+    assert_eq!(json["splice_calls"], stats.splice_calls);
+    assert!(json["started_at"].is_string());
+    assert!(json["mechanism"].is_string());
+}
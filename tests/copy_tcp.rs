@@ -1,3 +1,5 @@
+mod common;
+
 use std::env;
 
 use essentials::debug;
@@ -139,3 +141,92 @@ async fn copy_tcp_exact() {
     let n = client.read(&mut buf).await.unwrap();
     assert_eq!(&buf[..n], b"hihi");
 }
+
+#[tokio::test]
+async fn copy_tcp_bidirectional() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let mock_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mock_addr = mock_listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut server, _) = mock_listener.accept().await.unwrap();
+        let mut buf = [0; 1024];
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        // the client has shut down its write side, so we should see EOF
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(n, 0);
+        server.write_all(b"hi").await.unwrap();
+        server.shutdown().await.unwrap();
+        debug!("server responded!");
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(async move {
+        let mut left = listener.accept().await.unwrap().0;
+        let mut right = tokio::net::TcpStream::connect(&mock_addr).await.unwrap();
+        ::io::copy_bidirectional(&mut left, &mut right).await
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"hi");
+    assert_eq!(proxy.await.unwrap().unwrap(), (5, 2));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn copy_tcp_idle() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let mock_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mock_addr = mock_listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut server, _) = mock_listener.accept().await.unwrap();
+        let mut buf = [0; 1024];
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        server.write_all(b"hi").await.unwrap();
+        server.shutdown().await.unwrap();
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        for _ in 0..16 {
+            let (mut left_rx, mut left_tx) = listener.accept().await.unwrap().0.into_split();
+            let (mut right_rx, mut right_tx) = tokio::net::TcpStream::connect(&mock_addr)
+                .await
+                .unwrap()
+                .into_split();
+            connections.push(tokio::spawn(async move {
+                ::io::copy_tcp(&mut left_rx, &mut right_tx, None).await
+            }));
+            connections.push(tokio::spawn(async move {
+                ::io::copy_tcp(&mut right_rx, &mut left_tx, None).await
+            }));
+        }
+        join_all(connections).await;
+    });
+    let mut clients = Vec::new();
+    for _ in 0..16 {
+        clients.push(tokio::net::TcpStream::connect(&addr).await.unwrap());
+    }
+    // let the proxy settle into waiting on the idle connections
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let start = common::thread_cpu_time();
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let spent = common::thread_cpu_time() - start;
+    // the runtime runs on this thread, a busy loop would burn the whole 500ms
+    assert!(spent < std::time::Duration::from_millis(50), "{spent:?}");
+    let client = &mut clients[0];
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut buf = [0; 1024];
+    let n = client.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hi");
+}
@@ -0,0 +1,185 @@
+use std::{
+    env,
+    io::Result,
+    os::unix::io::RawFd,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{tcp::OwnedReadHalf, tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// Accepts a connection, sends `data` from the client and returns the server's read half.
+async fn upload(data: Vec<u8>) -> OwnedReadHalf {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut client = TcpStream::connect(&addr).await.unwrap();
+        client.write_all(&data).await.unwrap();
+        client.shutdown().await.unwrap();
+    });
+    listener.accept().await.unwrap().0.into_split().0
+}
+
+/// Returns the server's write half and the client, which is not reading yet.
+async fn sink() -> (OwnedWriteHalf, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = TcpStream::connect(&addr).await.unwrap();
+    let tx = listener.accept().await.unwrap().0.into_split().1;
+    (tx, client)
+}
+
+/// A sink whose shutdown is pending the first time it is polled.
+struct SlowShutdown {
+    inner: OwnedWriteHalf,
+    pending: bool,
+}
+
+impl AsyncWrite for SlowShutdown {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if this.pending {
+            this.pending = false;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+impl ::io::ZeroCopySink for SlowShutdown {
+    fn sink_fd(&self) -> RawFd {
+        self.inner.sink_fd()
+    }
+
+    fn poll_write_ready_n(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.inner.poll_write_ready_n(cx)
+    }
+
+    fn try_write_io_n<R>(&self, f: impl FnOnce() -> Result<R>) -> Result<R> {
+        self.inner.try_write_io_n(f)
+    }
+}
+
+fn read_all(mut client: TcpStream) -> JoinHandle<Vec<u8>> {
+    tokio::spawn(async move {
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    })
+}
+
+#[tokio::test]
+async fn copy_tee_block() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let mut rx = upload(data.clone()).await;
+    let (mut primary, primary_client) = sink().await;
+    let (mut secondary, secondary_client) = sink().await;
+    let primary_client = read_all(primary_client);
+    let secondary_client = read_all(secondary_client);
+    let (primary_bytes, secondary_bytes) = ::io::copy_tee(&mut rx, &mut primary, &mut secondary)
+        .await
+        .unwrap();
+    drop(primary);
+    drop(secondary);
+    assert_eq!(primary_bytes, data.len());
+    assert_eq!(secondary_bytes, data.len());
+    assert!(primary_client.await.unwrap() == data);
+    assert!(secondary_client.await.unwrap() == data);
+}
+
+#[tokio::test]
+async fn copy_tee_drop() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let mut rx = upload(data.clone()).await;
+    let (mut primary, primary_client) = sink().await;
+    // the secondary client does not read, so its socket buffer fills up
+    let (mut secondary, secondary_client) = sink().await;
+    let primary_client = read_all(primary_client);
+    let options = ::io::CopyOptions::new().shadow_policy(::io::ShadowPolicy::Drop);
+    let stats = ::io::copy_tee_with(&mut rx, &mut primary, &mut secondary, &options)
+        .await
+        .unwrap();
+    drop(primary);
+    assert_eq!(stats.primary.bytes, data.len());
+    assert!(primary_client.await.unwrap() == data);
+    assert!(stats.dropped > 0);
+    assert!(!stats.disconnected);
+    drop(secondary);
+    let received = read_all(secondary_client).await.unwrap();
+    assert_eq!(received.len(), stats.secondary.bytes);
+    assert!(stats.secondary.bytes + stats.dropped <= data.len());
+    if cfg!(target_os = "linux") {
+        assert!(stats.tee_calls > 0);
+    }
+}
+
+#[tokio::test]
+async fn copy_tee_disconnect() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let mut rx = upload(data.clone()).await;
+    let (mut primary, primary_client) = sink().await;
+    let (mut secondary, secondary_client) = sink().await;
+    let primary_client = read_all(primary_client);
+    let options = ::io::CopyOptions::new().shadow_policy(::io::ShadowPolicy::Disconnect);
+    let stats = ::io::copy_tee_with(&mut rx, &mut primary, &mut secondary, &options)
+        .await
+        .unwrap();
+    drop(primary);
+    assert_eq!(stats.primary.bytes, data.len());
+    assert!(primary_client.await.unwrap() == data);
+    assert!(stats.disconnected);
+    assert_eq!(stats.dropped, 0);
+    drop(secondary);
+    let received = read_all(secondary_client).await.unwrap();
+    assert!(received.len() < data.len());
+    assert!(received == data[..received.len()]);
+}
+
+#[tokio::test]
+async fn copy_tee_disconnect_pending_shutdown() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let mut rx = upload(data.clone()).await;
+    let (mut primary, primary_client) = sink().await;
+    let (secondary, secondary_client) = sink().await;
+    let mut secondary = SlowShutdown {
+        inner: secondary,
+        pending: true,
+    };
+    let primary_client = read_all(primary_client);
+    let options = ::io::CopyOptions::new().shadow_policy(::io::ShadowPolicy::Disconnect);
+    let stats = ::io::copy_tee_with(&mut rx, &mut primary, &mut secondary, &options)
+        .await
+        .unwrap();
+    drop(primary);
+    assert!(primary_client.await.unwrap() == data);
+    assert!(stats.disconnected);
+    assert!(!secondary.pending);
+    // the secondary sink is still open, so EOF means its shutdown was finished
+    let received = read_all(secondary_client).await.unwrap();
+    assert!(received == data[..received.len()]);
+    drop(secondary);
+}
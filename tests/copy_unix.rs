@@ -0,0 +1,293 @@
+use std::env;
+
+use essentials::debug;
+use futures_util::future::join_all;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, UnixStream},
+    task::JoinError,
+};
+
+#[tokio::test]
+async fn copy_unix() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let (mut server, upstream) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        server.write_all(b"hi").await.unwrap();
+        server.shutdown().await.unwrap();
+        debug!("server responded!");
+    });
+    let (mut client, downstream) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        let (mut left_rx, mut left_tx) = downstream.into_split();
+        let (mut right_rx, mut right_tx) = upstream.into_split();
+        join_all([
+            tokio::spawn(async move { ::io::copy_unix(&mut left_rx, &mut right_tx, None).await }),
+            tokio::spawn(async move { ::io::copy_unix(&mut right_rx, &mut left_tx, None).await }),
+        ])
+        .await;
+    });
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut buf = [0; 1024];
+    let n = client.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hi");
+}
+
+#[tokio::test]
+async fn copy_unix_long() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let (mut server, upstream) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        let chunk = [b'x'; 1024].as_slice();
+        for _ in 0..(20 * 1024) {
+            server.write_all(chunk).await.unwrap();
+        }
+    });
+    let (mut client, downstream) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        let (mut left_rx, mut left_tx) = downstream.into_split();
+        let (mut right_rx, mut right_tx) = upstream.into_split();
+        join_all([
+            tokio::spawn(async move {
+                ::io::copy_unix(&mut left_rx, &mut right_tx, None)
+                    .await
+                    .unwrap()
+            }),
+            tokio::spawn(async move {
+                ::io::copy_unix(&mut right_rx, &mut left_tx, None)
+                    .await
+                    .unwrap()
+            }),
+        ])
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, JoinError>>()
+        .unwrap();
+        debug!("finished copying");
+    });
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut len = 0;
+    let mut buf = [0; 1024];
+    loop {
+        let n = client.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    // 20 MB
+    assert_eq!(len, 20 * 1024 * 1024);
+}
+
+#[tokio::test]
+async fn copy_unix_exact() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let (mut server, upstream) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hell");
+        server.write_all(b"hihihihihihi").await.unwrap();
+    });
+    let (mut client, downstream) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        let (mut left_rx, mut left_tx) = downstream.into_split();
+        let (mut right_rx, mut right_tx) = upstream.into_split();
+        join_all([
+            tokio::spawn(
+                async move { ::io::copy_unix(&mut left_rx, &mut right_tx, Some(4)).await },
+            ),
+            tokio::spawn(
+                async move { ::io::copy_unix(&mut right_rx, &mut left_tx, Some(4)).await },
+            ),
+        ])
+        .await;
+    });
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 1024];
+    let n = client.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hihi");
+}
+
+#[tokio::test]
+async fn copy_tcp_to_unix() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let (mut server, upstream) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        server.write_all(b"hi").await.unwrap();
+        server.shutdown().await.unwrap();
+        debug!("server responded!");
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut left_rx, mut left_tx) = listener.accept().await.unwrap().0.into_split();
+        let (mut right_rx, mut right_tx) = upstream.into_split();
+        join_all([
+            tokio::spawn(
+                async move { ::io::copy_tcp_to_unix(&mut left_rx, &mut right_tx, None).await },
+            ),
+            tokio::spawn(
+                async move { ::io::copy_unix_to_tcp(&mut right_rx, &mut left_tx, None).await },
+            ),
+        ])
+        .await;
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut buf = [0; 1024];
+    let n = client.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hi");
+}
+
+#[tokio::test]
+async fn copy_unix_to_tcp() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let mock_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mock_addr = mock_listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut server, _) = mock_listener.accept().await.unwrap();
+        let mut buf = [0; 1024];
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        server.write_all(b"hi").await.unwrap();
+        server.shutdown().await.unwrap();
+        debug!("server responded!");
+    });
+    let (mut client, downstream) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        let (mut left_rx, mut left_tx) = downstream.into_split();
+        let (mut right_rx, mut right_tx) = tokio::net::TcpStream::connect(&mock_addr)
+            .await
+            .unwrap()
+            .into_split();
+        join_all([
+            tokio::spawn(
+                async move { ::io::copy_unix_to_tcp(&mut left_rx, &mut right_tx, None).await },
+            ),
+            tokio::spawn(
+                async move { ::io::copy_tcp_to_unix(&mut right_rx, &mut left_tx, None).await },
+            ),
+        ])
+        .await;
+    });
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut buf = [0; 1024];
+    let n = client.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hi");
+}
+
+#[tokio::test]
+async fn copy_file_unix() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let (mut client, server) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        let (mut left_rx, mut left_tx) = server.into_split();
+        let mut buf = [0; 1024];
+        let n = left_rx.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        let mut mock_file = tokio::fs::File::open("long_file.txt").await.unwrap();
+        ::io::copy_file(&mut mock_file, &mut left_tx, None)
+            .await
+            .unwrap();
+    });
+    client.write_all(b"hello").await.unwrap();
+    let mut len = 0;
+    let mut buf = [0; 1024];
+    loop {
+        let n = client.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    // 20 MB file
+    assert_eq!(len, 20 * 1024 * 1024);
+}
+
+#[tokio::test]
+async fn copy_unix_bidirectional() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let (mut server, mut upstream) = UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0; 1024];
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        server.write_all(b"hi").await.unwrap();
+        server.shutdown().await.unwrap();
+    });
+    let (mut client, mut downstream) = UnixStream::pair().unwrap();
+    let proxy =
+        tokio::spawn(async move { ::io::copy_bidirectional(&mut downstream, &mut upstream).await });
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"hi");
+    assert_eq!(proxy.await.unwrap().unwrap(), (5, 2));
+}
+
+#[tokio::test]
+async fn copy_unix_with() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let options = ::io::CopyOptions::new();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let tcp = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (accepted, _) = listener.accept().await.unwrap();
+    let (_tcp_rx, mut tcp_tx) = tcp.into_split();
+    let (mut accepted_rx, _accepted_tx) = accepted.into_split();
+    let (mut client, unix) = UnixStream::pair().unwrap();
+    let (mut unix_rx, mut unix_tx) = unix.into_split();
+    let (mut other, sink) = UnixStream::pair().unwrap();
+    let (_, mut sink_tx) = sink.into_split();
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+    // unix -> tcp -> unix -> unix
+    let stats = ::io::copy_unix_to_tcp_with(&mut unix_rx, &mut tcp_tx, Some(5), &options)
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes, 5);
+    let stats = ::io::copy_tcp_to_unix_with(&mut accepted_rx, &mut unix_tx, Some(5), &options)
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes, 5);
+    let (mut client_rx, _client_tx) = client.into_split();
+    let stats = ::io::copy_unix_with(&mut client_rx, &mut sink_tx, None, &options)
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes, 5);
+    let mut buf = Vec::new();
+    other.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"hello");
+}
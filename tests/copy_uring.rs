@@ -0,0 +1,190 @@
+#![cfg(all(target_os = "linux", feature = "io-uring"))]
+
+use std::env;
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+async fn send_range(
+    offset: u64,
+    length: usize,
+    options: ::io::CopyOptions,
+) -> (::io::CopyStats, Vec<u8>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let (_, mut tx) = listener.accept().await.unwrap().0.into_split();
+    let mut file = tokio::fs::File::open("long_file.txt").await.unwrap();
+    file.seek(std::io::SeekFrom::Start(offset)).await.unwrap();
+    let stats = ::io::copy_with(&mut file, &mut tx, Some(length), &options)
+        .await
+        .unwrap();
+    tx.shutdown().await.unwrap();
+    (stats, client.await.unwrap())
+}
+
+#[tokio::test]
+async fn copy_uring_file() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let options = ::io::CopyOptions::new().backend(::io::CopyBackend::IoUring);
+    let (stats, received) = send_range(1000, 4 * 1024 * 1024, options).await;
+    assert_eq!(stats.bytes, 4 * 1024 * 1024);
+    assert!(received == data[1000..1000 + 4 * 1024 * 1024]);
+    if ::io::CopyBackend::IoUring.is_supported() {
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::IoUring);
+        assert!(stats.splice_calls >= 2 * 64);
+        assert_eq!(stats.pipe_size, Some(64 * 1024));
+    } else {
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::SendFile);
+    }
+}
+
+#[tokio::test]
+async fn copy_uring_readiness() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let options = ::io::CopyOptions::new().backend(::io::CopyBackend::Readiness);
+    let (stats, received) = send_range(0, 1024 * 1024, options).await;
+    assert_eq!(stats.bytes, 1024 * 1024);
+    assert_eq!(received.len(), 1024 * 1024);
+    assert_eq!(stats.mechanism, ::io::CopyMechanism::SendFile);
+}
+
+#[tokio::test]
+async fn copy_uring_tcp() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(async move {
+        let (mut rx, mut tx) = listener.accept().await.unwrap().0.into_split();
+        let options = ::io::CopyOptions::new().backend(::io::CopyBackend::IoUring);
+        let stats = ::io::copy_with(&mut rx, &mut tx, None, &options)
+            .await
+            .unwrap();
+        tx.shutdown().await.unwrap();
+        stats
+    });
+    let (mut rx, mut tx) = tokio::net::TcpStream::connect(&addr)
+        .await
+        .unwrap()
+        .into_split();
+    let reader = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        rx.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    // the proxy waits for the slow writer with a poll operation instead of failing
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    tx.write_all(&data).await.unwrap();
+    tx.shutdown().await.unwrap();
+    let stats = proxy.await.unwrap();
+    assert_eq!(stats.bytes, data.len());
+    assert!(reader.await.unwrap() == data);
+    if ::io::CopyBackend::IoUring.is_supported() {
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::IoUring);
+        assert!(stats.would_block > 0);
+    }
+}
+
+#[tokio::test]
+async fn copy_uring_bidirectional() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap()[..4 * 1024 * 1024].to_vec();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream_listener.local_addr().unwrap();
+    // the upstream echoes everything back
+    tokio::spawn(async move {
+        let (mut rx, mut tx) = upstream_listener.accept().await.unwrap().0.into_split();
+        tokio::io::copy(&mut rx, &mut tx).await.unwrap();
+        tx.shutdown().await.unwrap();
+    });
+    let proxy = tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+        let mut upstream = tokio::net::TcpStream::connect(upstream_addr).await.unwrap();
+        let options = ::io::CopyOptions::new().backend(::io::CopyBackend::IoUring);
+        ::io::copy_bidirectional_with(&mut client, &mut upstream, &options)
+            .await
+            .unwrap()
+    });
+    let (mut rx, mut tx) = tokio::net::TcpStream::connect(&addr)
+        .await
+        .unwrap()
+        .into_split();
+    let reader = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        rx.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    tx.write_all(&data).await.unwrap();
+    // the half-close travels to the upstream and its EOF back to the client
+    tx.shutdown().await.unwrap();
+    assert!(reader.await.unwrap() == data);
+    let (sent, received) = proxy.await.unwrap();
+    assert_eq!(sent.bytes, data.len());
+    assert_eq!(received.bytes, data.len());
+    if ::io::CopyBackend::IoUring.is_supported() {
+        assert_eq!(sent.mechanism, ::io::CopyMechanism::IoUring);
+        assert_eq!(received.mechanism, ::io::CopyMechanism::IoUring);
+    }
+}
+
+#[tokio::test]
+async fn copy_uring_cancelled() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let pool = ::io::PipePool::new(1, 1024 * 1024);
+    let options = ::io::CopyOptions::new()
+        .backend(::io::CopyBackend::IoUring)
+        .pipe_pool(pool.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    // the first client stays silent, so its copy is dropped while waiting for data
+    let silent = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let (mut rx, mut tx) = listener.accept().await.unwrap().0.into_split();
+    let copy = ::io::copy_with(&mut rx, &mut tx, None, &options);
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(100), copy)
+            .await
+            .is_err()
+    );
+    // the cancelled operation released the pipe, so the next copy reuses it
+    assert_eq!(pool.in_use(), 0);
+    let client = tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let (mut rx, mut tx) = listener.accept().await.unwrap().0.into_split();
+    let stats = ::io::copy_with(&mut rx, &mut tx, None, &options)
+        .await
+        .unwrap();
+    tx.shutdown().await.unwrap();
+    assert_eq!(stats.bytes, 5);
+    assert_eq!(client.await.unwrap(), b"hello");
+    if ::io::CopyBackend::IoUring.is_supported() {
+        assert_eq!(pool.misses(), 1);
+        assert_eq!(pool.hits(), 1);
+    }
+    drop(silent);
+}
@@ -0,0 +1,98 @@
+use std::env;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+#[tokio::test]
+async fn copy_with_prefix() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let body = tokio::fs::read("long_file.txt").await.unwrap()[..1024 * 1024].to_vec();
+    let upstream_listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream_listener.local_addr().unwrap();
+    let upstream_body = body.clone();
+    tokio::spawn(async move {
+        let (mut upstream, _) = upstream_listener.accept().await.unwrap();
+        upstream.write_all(&upstream_body).await.unwrap();
+        // more than the proxy copies
+        upstream.write_all(b"trailing").await.unwrap();
+    });
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(async move {
+        let (_, mut client_tx) = listener.accept().await.unwrap().0.into_split();
+        let (mut upstream_rx, _upstream_tx) = tokio::net::TcpStream::connect(upstream_addr)
+            .await
+            .unwrap()
+            .into_split();
+        let prefix = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", 1024 * 1024);
+        ::io::copy_with_prefix_with(
+            &mut upstream_rx,
+            &mut client_tx,
+            prefix.as_bytes(),
+            Some(1024 * 1024),
+            &::io::CopyOptions::new(),
+        )
+        .await
+        .unwrap()
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    let stats = proxy.await.unwrap();
+    let head = b"HTTP/1.1 200 OK\r\nContent-Length: 1048576\r\n\r\n";
+    assert_eq!(stats.bytes, head.len() + body.len());
+    assert_eq!(buf.len(), stats.bytes);
+    assert_eq!(&buf[..head.len()], head);
+    assert!(buf[head.len()..] == body);
+}
+
+#[tokio::test]
+async fn copy_with_prefix_only() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    // a prefix bigger than the pipe is written in several fills
+    let prefix = tokio::fs::read("long_file.txt").await.unwrap()[..300 * 1024].to_vec();
+    let (mut source, _writer) = tokio::net::UnixStream::pair().unwrap();
+    let (mut left, mut right) = tokio::net::UnixStream::pair().unwrap();
+    let reader = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        right.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let n = ::io::copy_with_prefix(&mut source, &mut left, &prefix, Some(0))
+        .await
+        .unwrap();
+    drop(left);
+    assert_eq!(n, prefix.len());
+    assert!(reader.await.unwrap() == prefix);
+}
+
+#[tokio::test]
+async fn copy_with_prefix_capture() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = env::temp_dir().join(format!("copy_with_prefix_{}.cap", std::process::id()));
+    let capture = ::io::Capture::create(&path).await.unwrap();
+    let (mut source, mut writer) = tokio::net::UnixStream::pair().unwrap();
+    let (mut left, mut right) = tokio::net::UnixStream::pair().unwrap();
+    tokio::spawn(async move {
+        writer.write_all(b"body").await.unwrap();
+    });
+    let options = ::io::CopyOptions::new().capture(capture);
+    let stats = ::io::copy_with_prefix_with(&mut source, &mut left, b"prefix ", None, &options)
+        .await
+        .unwrap();
+    drop(left);
+    assert_eq!(stats.bytes, 11);
+    let mut buf = Vec::new();
+    right.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"prefix body");
+    let recording = ::io::replay::Recording::open(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(recording.client_data(), b"prefix body");
+}
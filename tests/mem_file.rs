@@ -0,0 +1,131 @@
+#![cfg(target_os = "linux")]
+
+use std::{env, os::unix::io::AsRawFd};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+async fn build(content: &[u8]) -> ::io::MemFile {
+    let mut writer = ::io::MemFile::create("export.csv").unwrap();
+    for chunk in content.chunks(100_000) {
+        writer.write_all(chunk).await.unwrap();
+    }
+    writer.flush().await.unwrap();
+    assert_eq!(writer.len(), content.len() as u64);
+    writer.seal().unwrap()
+}
+
+#[tokio::test]
+async fn mem_file() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let content = tokio::fs::read("long_file.txt").await.unwrap()[..4 * 1024 * 1024].to_vec();
+    let file = build(&content).await;
+    assert_eq!(file.len(), content.len() as u64);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut clients = Vec::new();
+    for _ in 0..3 {
+        clients.push(tokio::spawn(async move {
+            let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+            let mut buf = Vec::new();
+            client.read_to_end(&mut buf).await.unwrap();
+            buf
+        }));
+    }
+    let mut servers = Vec::new();
+    for _ in 0..3 {
+        let (_, mut tx) = listener.accept().await.unwrap().0.into_split();
+        let file = file.clone();
+        servers.push(tokio::spawn(async move {
+            let mut r = file.open().await.unwrap();
+            ::io::copy_file(&mut r, &mut tx, None).await.unwrap()
+        }));
+    }
+    for server in servers {
+        assert_eq!(server.await.unwrap(), content.len());
+    }
+    for client in clients {
+        assert!(client.await.unwrap() == content);
+    }
+}
+
+#[tokio::test]
+async fn mem_file_send() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let content = tokio::fs::read("long_file.txt").await.unwrap()[..4 * 1024 * 1024].to_vec();
+    let file = build(&content).await;
+    let mut sends = Vec::new();
+    for _ in 0..3 {
+        let (mut left, mut right) = tokio::net::UnixStream::pair().unwrap();
+        let file = file.clone();
+        sends.push((
+            tokio::spawn(async move { file.send(&mut left).await.unwrap() }),
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                right.read_to_end(&mut buf).await.unwrap();
+                buf
+            }),
+        ));
+    }
+    for (send, receive) in sends {
+        assert_eq!(send.await.unwrap(), content.len());
+        assert!(receive.await.unwrap() == content);
+    }
+}
+
+#[tokio::test]
+async fn mem_file_range() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let content = tokio::fs::read("long_file.txt").await.unwrap()[..1024 * 1024].to_vec();
+    let file = build(&content).await;
+    for (offset, length) in [(0, 10), (1000, 64 * 1024), (512 * 1024, 512 * 1024)] {
+        let (mut left, mut right) = tokio::net::UnixStream::pair().unwrap();
+        let reader = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            right.read_to_end(&mut buf).await.unwrap();
+            buf
+        });
+        let mut r = file.open().await.unwrap();
+        let n = ::io::copy_file_range(&mut r, &mut left, offset as u64, Some(length))
+            .await
+            .unwrap();
+        drop(left);
+        assert_eq!(n, length);
+        assert!(reader.await.unwrap() == content[offset..offset + length]);
+    }
+}
+
+#[tokio::test]
+async fn mem_file_sealed() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let file = build(b"id,name\n1,alice\n").await;
+    let mut r = file.open().await.unwrap();
+    let seals = unsafe { libc::fcntl(r.as_raw_fd(), libc::F_GET_SEALS) };
+    assert_ne!(seals & libc::F_SEAL_WRITE, 0);
+    assert_ne!(seals & libc::F_SEAL_SEAL, 0);
+    // the content can be read but not changed, even through a writable file
+    let mut w = std::fs::OpenOptions::new()
+        .write(true)
+        .open(format!("/proc/self/fd/{}", r.as_raw_fd()))
+        .unwrap();
+    let err = std::io::Write::write_all(&mut w, b"2,bob\n").unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+    let mut buf = String::new();
+    r.read_to_string(&mut buf).await.unwrap();
+    assert_eq!(buf, "id,name\n1,alice\n");
+    assert!(::io::MemFile::create("empty")
+        .unwrap()
+        .seal()
+        .unwrap()
+        .is_empty());
+}
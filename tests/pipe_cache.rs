@@ -0,0 +1,183 @@
+#![cfg(target_os = "linux")]
+
+use std::env;
+use tokio::{io::AsyncReadExt, net::TcpListener};
+
+/// Sends the entry for the key to a new client and returns what the client received.
+async fn fetch(cache: &::io::PipeCache, key: &str, listener: &TcpListener) -> Vec<u8> {
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let (_, mut tx) = listener.accept().await.unwrap().0.into_split();
+    let stats = cache.send(key, &mut tx).await.unwrap().unwrap();
+    assert_eq!(stats.mechanism, ::io::CopyMechanism::Splice);
+    drop(tx);
+    let buf = client.await.unwrap();
+    assert_eq!(stats.bytes, buf.len());
+    buf
+}
+
+#[tokio::test]
+async fn pipe_cache() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let cache = ::io::PipeCache::new(8, 1024 * 1024);
+    assert_eq!(cache.capacity(), 64 * 1024);
+    assert!(cache.insert("/health", b"ok").unwrap());
+    let page = tokio::fs::read("long_file.txt").await.unwrap()[..64 * 1024].to_vec();
+    assert!(cache.insert("/index.html", &page).unwrap());
+    assert_eq!(cache.len(), 2);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    for _ in 0..3 {
+        assert_eq!(fetch(&cache, "/health", &listener).await, b"ok");
+        assert!(fetch(&cache, "/index.html", &listener).await == page);
+    }
+    assert_eq!(cache.hits(), 6);
+
+    // replacing and invalidating entries
+    assert!(cache.insert("/health", b"degraded").unwrap());
+    assert_eq!(fetch(&cache, "/health", &listener).await, b"degraded");
+    assert!(cache.invalidate("/health"));
+    assert!(!cache.invalidate("/health"));
+    assert!(!cache.contains("/health"));
+    let (_, mut tx) = tokio::net::UnixStream::pair().unwrap();
+    assert!(cache.send("/health", &mut tx).await.unwrap().is_none());
+    assert_eq!(cache.misses(), 1);
+    cache.clear();
+    assert!(cache.is_empty());
+}
+
+#[tokio::test]
+async fn pipe_cache_limits() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    // the memory limit only allows two 16 KiB pipes
+    let config = ::io::SpliceConfig::new().pipe_size(16 * 1024);
+    let cache = ::io::PipeCache::with_config(8, 32 * 1024, config);
+    assert_eq!(cache.max_entries(), 2);
+    assert!(!cache.insert("big", &[0; 16 * 1024 + 1]).unwrap());
+    assert!(cache.insert("a", &[1; 16 * 1024]).unwrap());
+    assert!(cache.insert("b", b"b").unwrap());
+    assert!(!cache.insert("c", b"c").unwrap());
+    // replacing an entry does not need another one
+    assert!(cache.insert("b", b"bb").unwrap());
+    assert_eq!(cache.len(), 2);
+}
+
+#[tokio::test]
+async fn pipe_cache_send_file() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = env::temp_dir().join(format!("pipe_cache_{}.txt", std::process::id()));
+    let content = tokio::fs::read("long_file.txt").await.unwrap()[..40 * 1024].to_vec();
+    tokio::fs::write(&path, &content).await.unwrap();
+    let cache = ::io::PipeCache::new(8, 1024 * 1024);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    for (key, file, len) in [
+        ("/small", path.clone(), content.len()),
+        ("/small", path.clone(), content.len()),
+        ("/large", "long_file.txt".into(), 20 * 1024 * 1024),
+    ] {
+        let client = tokio::spawn(async move {
+            let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+            let mut buf = Vec::new();
+            client.read_to_end(&mut buf).await.unwrap();
+            buf
+        });
+        let (_, mut tx) = listener.accept().await.unwrap().0.into_split();
+        let mut file = tokio::fs::File::open(file).await.unwrap();
+        let stats = cache.send_file(key, &mut file, &mut tx).await.unwrap();
+        drop(tx);
+        assert_eq!(stats.bytes, len);
+        let buf = client.await.unwrap();
+        assert_eq!(buf.len(), len);
+        if key == "/small" {
+            assert_eq!(stats.mechanism, ::io::CopyMechanism::Splice);
+            assert!(buf == content);
+        }
+    }
+    tokio::fs::remove_file(&path).await.unwrap();
+    // the large file does not fit and is not cached
+    assert!(cache.contains("/small"));
+    assert!(!cache.contains("/large"));
+    assert_eq!(cache.hits(), 1);
+    assert_eq!(cache.misses(), 2);
+}
+
+#[tokio::test]
+async fn pipe_cache_capture() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = env::temp_dir().join(format!("pipe_cache_{}.cap", std::process::id()));
+    let capture = ::io::Capture::create(&path).await.unwrap();
+    let options = ::io::CopyOptions::new().capture(capture.clone());
+    let cache = ::io::PipeCache::new(8, 1024 * 1024);
+    assert!(cache.insert("/health", b"ok").unwrap());
+    let (mut tx, mut rx) = tokio::net::UnixStream::pair().unwrap();
+    let stats = cache
+        .send_with("/health", &mut tx, &options)
+        .await
+        .unwrap()
+        .unwrap();
+    drop(tx);
+    assert_eq!(stats.bytes, 2);
+    let mut buf = Vec::new();
+    rx.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"ok");
+    let mut reader = ::io::CaptureReader::open(&path).await.unwrap();
+    let frame = reader.next_frame().await.unwrap().unwrap();
+    assert_eq!(frame.direction, ::io::CaptureDirection::Forward);
+    assert_eq!(frame.payload, b"ok");
+    assert!(reader.next_frame().await.unwrap().is_none());
+    tokio::fs::remove_file(&path).await.unwrap();
+}
+
+#[tokio::test]
+async fn pipe_cache_rewrite() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = env::temp_dir().join(format!("pipe_cache_rewrite_{}.txt", std::process::id()));
+    tokio::fs::write(&path, [b'a'; 8 * 1024]).await.unwrap();
+    let cache = ::io::PipeCache::new(8, 1024 * 1024);
+    let mut file = tokio::fs::File::open(&path).await.unwrap();
+    assert!(cache.insert_file("/page", &mut file).await.unwrap());
+    // rewriting the file in place does not change the cached content
+    {
+        use std::os::unix::fs::FileExt;
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.write_all_at(&[b'b'; 8 * 1024], 0).unwrap();
+    }
+    tokio::fs::remove_file(&path).await.unwrap();
+    // the tee targets are leased from the pool
+    let pool = ::io::PipePool::new(1, 1024 * 1024);
+    let options = ::io::CopyOptions::new().pipe_pool(pool.clone());
+    for _ in 0..2 {
+        let (mut tx, mut rx) = tokio::net::UnixStream::pair().unwrap();
+        let reader = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            rx.read_to_end(&mut buf).await.unwrap();
+            buf
+        });
+        let stats = cache
+            .send_with("/page", &mut tx, &options)
+            .await
+            .unwrap()
+            .unwrap();
+        drop(tx);
+        assert_eq!(stats.bytes, 8 * 1024);
+        assert!(reader.await.unwrap() == [b'a'; 8 * 1024]);
+    }
+    assert_eq!(pool.misses(), 1);
+    assert_eq!(pool.hits(), 1);
+}
@@ -0,0 +1,157 @@
+#![cfg(target_os = "linux")]
+
+use std::env;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+async fn proxy_once(listener: &TcpListener, options: &::io::CopyOptions) {
+    let addr = listener.local_addr().unwrap();
+    let client = tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        client.write_all(b"hello").await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let (mut rx, mut tx) = listener.accept().await.unwrap().0.into_split();
+    let stats = ::io::copy_with(&mut rx, &mut tx, None, options)
+        .await
+        .unwrap();
+    tx.shutdown().await.unwrap();
+    assert_eq!(stats.bytes, 5);
+    assert_eq!(client.await.unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn pipe_pool_reuse() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let pool = ::io::PipePool::new(4, 1024 * 1024);
+    let options = ::io::CopyOptions::new().pipe_pool(pool.clone());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    for _ in 0..10 {
+        proxy_once(&listener, &options).await;
+    }
+    assert_eq!(pool.misses(), 1);
+    assert_eq!(pool.hits(), 9);
+    assert_eq!(pool.idle(), 1);
+    assert_eq!(pool.in_use(), 0);
+}
+
+#[tokio::test]
+async fn pipe_pool_limits() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    // the memory limit only allows two 64 KiB pipes
+    let pool = ::io::PipePool::new(8, 128 * 1024);
+    assert_eq!(pool.max_pipes(), 2);
+    pool.prefill(8).unwrap();
+    assert_eq!(pool.idle(), 2);
+    // a memory limit below one pipe still allows one
+    assert_eq!(::io::PipePool::new(8, 1024).max_pipes(), 1);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let options = ::io::CopyOptions::new().pipe_pool(pool.clone());
+    let server = tokio::spawn(async move {
+        let mut copies = Vec::new();
+        for _ in 0..4 {
+            let (mut rx, mut tx) = listener.accept().await.unwrap().0.into_split();
+            let options = options.clone();
+            copies.push(tokio::spawn(async move {
+                ::io::copy_with(&mut rx, &mut tx, None, &options)
+                    .await
+                    .unwrap()
+                    .bytes
+            }));
+        }
+        copies
+    });
+    let mut clients = Vec::new();
+    for _ in 0..4 {
+        clients.push(tokio::net::TcpStream::connect(&addr).await.unwrap());
+    }
+    let copies = server.await.unwrap();
+    // all copies are waiting for data, two use the pooled pipes and two wait for one of them
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(pool.in_use(), 2);
+    assert_eq!(pool.idle(), 0);
+    assert_eq!(pool.hits(), 2);
+    assert_eq!(pool.misses(), 0);
+
+    for client in clients.iter_mut() {
+        client.write_all(b"hello").await.unwrap();
+        client.shutdown().await.unwrap();
+    }
+    for copy in copies {
+        assert_eq!(copy.await.unwrap(), 5);
+    }
+    // the waiting copies reused the returned pipes
+    assert_eq!(pool.hits(), 4);
+    assert_eq!(pool.misses(), 0);
+    assert_eq!(pool.in_use(), 0);
+    assert_eq!(pool.idle(), 2);
+}
+
+#[tokio::test]
+async fn pipe_pool_overflow() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let pool = ::io::PipePool::new(1, 1024 * 1024).overflow(1);
+    assert_eq!(pool.max_overflow(), 1);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let options = ::io::CopyOptions::new().pipe_pool(pool.clone());
+    let copy_options = options.clone();
+    let server = tokio::spawn(async move {
+        let mut copies = Vec::new();
+        for _ in 0..3 {
+            let (mut rx, mut tx) = listener.accept().await.unwrap().0.into_split();
+            let options = copy_options.clone();
+            copies.push(tokio::spawn(async move {
+                ::io::copy_with(&mut rx, &mut tx, None, &options)
+                    .await
+                    .unwrap()
+                    .bytes
+            }));
+        }
+        copies
+    });
+    let mut clients = Vec::new();
+    for _ in 0..3 {
+        clients.push(tokio::net::TcpStream::connect(&addr).await.unwrap());
+    }
+    let copies = server.await.unwrap();
+    // one copy uses the pooled pipe, one a temporary pipe and the third waits
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert_eq!(pool.in_use(), 1);
+    assert_eq!(pool.misses(), 2);
+    assert_eq!(pool.hits(), 0);
+
+    for client in clients.iter_mut() {
+        client.write_all(b"hello").await.unwrap();
+        client.shutdown().await.unwrap();
+    }
+    for copy in copies {
+        assert_eq!(copy.await.unwrap(), 5);
+    }
+    assert_eq!(pool.hits() + pool.misses(), 3);
+    assert_eq!(pool.in_use(), 0);
+    assert_eq!(pool.idle(), 1);
+
+    // a bidirectional copy needs two pipes, which a pool of one pipe can never hand out
+    let pool = ::io::PipePool::new(1, 1024 * 1024);
+    let options = ::io::CopyOptions::new().pipe_pool(pool);
+    let (mut a, _) = tokio::net::UnixStream::pair().unwrap();
+    let (mut b, _) = tokio::net::UnixStream::pair().unwrap();
+    let err = ::io::copy_bidirectional_with(&mut a, &mut b, &options)
+        .await
+        .unwrap_err();
+    assert_eq!(err.phase(), ::io::CopyPhase::Pipe);
+}
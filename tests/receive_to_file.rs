@@ -0,0 +1,88 @@
+use std::{env, path::PathBuf};
+use tokio::{
+    io::{AsyncSeekExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}_{}.txt", name, std::process::id()))
+}
+
+/// Accepts a connection, sends `data` from the client and returns the server's read half.
+async fn upload(data: Vec<u8>) -> tokio::net::tcp::OwnedReadHalf {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+        client.write_all(&data).await.unwrap();
+        client.shutdown().await.unwrap();
+    });
+    listener.accept().await.unwrap().0.into_split().0
+}
+
+#[tokio::test]
+async fn receive_to_file_exact() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("receive_to_file_exact");
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let mut rx = upload(data.clone()).await;
+    let mut file = tokio::fs::File::create(&path).await.unwrap();
+    let options = ::io::CopyOptions::new().sync_policy(::io::SyncPolicy::Data);
+    #[cfg(target_os = "linux")]
+    let options = options.preallocate(true);
+    let stats = ::io::receive_to_file_with(&mut rx, &mut file, 100, Some(data.len()), &options)
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes, data.len());
+    assert_eq!(
+        file.stream_position().await.unwrap(),
+        100 + data.len() as u64
+    );
+    let received = tokio::fs::read(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(received.len(), 100 + data.len());
+    assert!(received[..100].iter().all(|byte| *byte == 0));
+    assert!(received[100..] == data);
+}
+
+#[tokio::test]
+async fn receive_to_file_until_eof() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("receive_to_file_until_eof");
+    let data = tokio::fs::read("long_file.txt").await.unwrap()[..3 * 1024 * 1024 + 7].to_vec();
+    let mut rx = upload(data.clone()).await;
+    let mut file = tokio::fs::File::create(&path).await.unwrap();
+    let n = ::io::receive_to_file(&mut rx, &mut file, 0, None)
+        .await
+        .unwrap();
+    let received = tokio::fs::read(&path).await.unwrap();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(n, data.len());
+    assert!(received == data);
+}
+
+#[tokio::test]
+async fn receive_to_file_short() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("receive_to_file_short");
+    let mut rx = upload(b"hello".to_vec()).await;
+    let mut file = tokio::fs::File::create(&path).await.unwrap();
+    let options = ::io::CopyOptions::new();
+    #[cfg(target_os = "linux")]
+    let options = options.preallocate(true);
+    let err = ::io::receive_to_file_with(&mut rx, &mut file, 0, Some(1024), &options)
+        .await
+        .unwrap_err();
+    let len = tokio::fs::metadata(&path).await.unwrap().len();
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert_eq!(err.transferred(), 5);
+    // the preallocated blocks do not change the file size
+    assert_eq!(len, 5);
+}
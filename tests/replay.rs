@@ -0,0 +1,171 @@
+use std::{env, net::SocketAddr, path::PathBuf, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}_{}.cap", name, std::process::id()))
+}
+
+/// Starts a line based server answering every line with `respond(n, line)`, n counting from 1.
+async fn stand_in(respond: fn(usize, &str) -> String) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let (rx, mut tx) = stream.into_split();
+                let mut lines = BufReader::new(rx).lines();
+                let mut n = 0;
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    n += 1;
+                    tx.write_all(respond(n, &line).as_bytes()).await.unwrap();
+                }
+                tx.shutdown().await.unwrap();
+            });
+        }
+    });
+    addr
+}
+
+fn pong(_: usize, line: &str) -> String {
+    format!("pong {}\n", line.trim_start_matches("ping "))
+}
+
+/// Proxies a client sending three pings to the server and records the session.
+async fn record(server: SocketAddr, path: &PathBuf) -> ::io::replay::Recording {
+    let capture = ::io::Capture::create(path).await.unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(async move {
+        let (mut client_rx, mut client_tx) = listener.accept().await.unwrap().0.into_split();
+        let (mut server_rx, mut server_tx) = tokio::net::TcpStream::connect(server)
+            .await
+            .unwrap()
+            .into_split();
+        let requests = ::io::CopyOptions::new().capture(capture.clone());
+        let responses = ::io::CopyOptions::new().capture(capture.backward());
+        tokio::try_join!(
+            ::io::copy_tcp_with(&mut client_rx, &mut server_tx, None, &requests),
+            ::io::copy_tcp_with(&mut server_rx, &mut client_tx, None, &responses),
+        )
+        .unwrap();
+    });
+    let (rx, mut tx) = tokio::net::TcpStream::connect(addr)
+        .await
+        .unwrap()
+        .into_split();
+    let mut lines = BufReader::new(rx).lines();
+    for n in 1..=3 {
+        tx.write_all(format!("ping {n}\n").as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(
+            lines.next_line().await.unwrap().unwrap(),
+            format!("pong {n}")
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    tx.shutdown().await.unwrap();
+    assert!(lines.next_line().await.unwrap().is_none());
+    proxy.await.unwrap();
+    ::io::replay::Recording::open(path).await.unwrap()
+}
+
+#[tokio::test]
+async fn replay_match() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("replay_match");
+    let server = stand_in(pong).await;
+    let recording = record(server, &path).await;
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert_eq!(recording.client_data(), b"ping 1\nping 2\nping 3\n");
+    assert_eq!(recording.server_data(), b"pong 1\npong 2\npong 3\n");
+
+    let options = ::io::replay::ReplayOptions::new().timing(::io::replay::Timing::Fast);
+    let report = recording.replay_to(server, &options).await.unwrap();
+    assert!(report.is_match(), "{:?}", report.divergences);
+    assert_eq!(report.sent, 21);
+    assert_eq!(report.received, 21);
+    assert_eq!(report.expected, 21);
+
+    // the original timing keeps the pauses of the client between the pings
+    let report = recording
+        .replay_to(server, &::io::replay::ReplayOptions::new())
+        .await
+        .unwrap();
+    assert!(report.is_match());
+    assert!(report.duration() >= chrono::Duration::milliseconds(100));
+}
+
+#[tokio::test]
+async fn replay_divergence() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let path = temp_path("replay_divergence");
+    let recording = record(stand_in(pong).await, &path).await;
+    tokio::fs::remove_file(&path).await.unwrap();
+    let changed = stand_in(|n, line| match n {
+        2 => "PONG 2\n".to_string(),
+        3 => format!("{}extra\n", pong(n, line)),
+        _ => pong(n, line),
+    })
+    .await;
+    let options = ::io::replay::ReplayOptions::new()
+        .timing(::io::replay::Timing::Fast)
+        .idle_timeout(Duration::from_millis(500));
+    let report = recording.replay_to(changed, &options).await.unwrap();
+    assert!(!report.is_match());
+    assert_eq!(report.received, 27);
+    assert_eq!(report.divergences.len(), 2);
+    let divergence = &report.divergences[0];
+    assert_eq!(divergence.exchange, 1);
+    assert_eq!(divergence.offset, 7);
+    assert_eq!(divergence.expected, b"pong 2\n");
+    assert_eq!(divergence.actual, b"PONG 2\n");
+    let divergence = &report.divergences[1];
+    assert_eq!(divergence.exchange, 3);
+    assert_eq!(divergence.offset, 21);
+    assert_eq!(divergence.expected, b"");
+    assert_eq!(divergence.actual, b"extra\n");
+}
+
+#[tokio::test]
+async fn replay_timing() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let start = chrono::Utc::now();
+    let frame = |millis, direction, payload: &[u8]| ::io::CaptureFrame {
+        direction,
+        timestamp: start + chrono::Duration::milliseconds(millis),
+        payload: payload.to_vec(),
+    };
+    let recording = ::io::replay::Recording::new(vec![
+        frame(0, ::io::CaptureDirection::Forward, b"ping 1\n"),
+        frame(10, ::io::CaptureDirection::Backward, b"pong 1\n"),
+        frame(300, ::io::CaptureDirection::Forward, b"ping 2\n"),
+        frame(310, ::io::CaptureDirection::Backward, b"pong 2\n"),
+    ]);
+    let server = stand_in(pong).await;
+    let report = recording
+        .replay_to(server, &::io::replay::ReplayOptions::new())
+        .await
+        .unwrap();
+    assert!(report.is_match());
+    assert!(report.duration() >= chrono::Duration::milliseconds(300));
+    let report = recording
+        .replay_to(
+            server,
+            &::io::replay::ReplayOptions::new().timing(::io::replay::Timing::Fast),
+        )
+        .await
+        .unwrap();
+    assert!(report.is_match());
+    assert!(report.duration() < chrono::Duration::milliseconds(300));
+}
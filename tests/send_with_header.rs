@@ -0,0 +1,101 @@
+use std::{env, io::IoSlice};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    net::TcpListener,
+};
+
+#[tokio::test]
+async fn send_with_header_range() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let body = &data[100..100 + 1024 * 1024];
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (_, mut tx) = listener.accept().await.unwrap().0.into_split();
+        let mut file = tokio::fs::File::open("long_file.txt").await.unwrap();
+        let header = [
+            IoSlice::new(b"HTTP/1.1 200 OK\r\n"),
+            IoSlice::new(b""),
+            IoSlice::new(b"Content-Length: 1048576\r\n\r\n"),
+        ];
+        let stats = ::io::send_with_header_with(
+            &mut tx,
+            &header,
+            &mut file,
+            100..100 + 1024 * 1024,
+            Some(b"\r\n"),
+            &::io::CopyOptions::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(file.stream_position().await.unwrap(), 0);
+        stats
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    let stats = server.await.unwrap();
+    let head = b"HTTP/1.1 200 OK\r\nContent-Length: 1048576\r\n\r\n";
+    assert_eq!(stats.bytes, buf.len());
+    assert_eq!(buf.len(), head.len() + body.len() + 2);
+    assert_eq!(&buf[..head.len()], head);
+    assert!(&buf[head.len()..head.len() + body.len()] == body);
+    assert_eq!(&buf[head.len() + body.len()..], b"\r\n");
+    assert!(stats.write_calls >= 2);
+}
+
+#[tokio::test]
+async fn send_with_header_whole_file() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (_, mut tx) = listener.accept().await.unwrap().0.into_split();
+        let mut file = tokio::fs::File::open("long_file.txt").await.unwrap();
+        let n = ::io::send_with_header(&mut tx, &[IoSlice::new(b"head")], &mut file, .., None)
+            .await
+            .unwrap();
+        assert_eq!(n, 4 + 20 * 1024 * 1024);
+    });
+    let mut client = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf.len(), 4 + 20 * 1024 * 1024);
+    assert_eq!(&buf[..4], b"head");
+}
+
+#[tokio::test]
+async fn send_with_header_unix() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let (mut left, mut right) = tokio::net::UnixStream::pair().unwrap();
+    let reader = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        right.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let mut file = tokio::fs::File::open("long_file.txt").await.unwrap();
+    // unix sockets cannot be corked, the range is clamped to the end of the file
+    let n = ::io::send_with_header(
+        &mut left,
+        &[IoSlice::new(b"head")],
+        &mut file,
+        20 * 1024 * 1024 - 10..=30 * 1024 * 1024,
+        Some(b"tail"),
+    )
+    .await
+    .unwrap();
+    drop(left);
+    assert_eq!(n, 18);
+    let buf = reader.await.unwrap();
+    assert_eq!(&buf[..4], b"head");
+    assert_eq!(&buf[4..14], &data[data.len() - 10..]);
+    assert_eq!(&buf[14..], b"tail");
+}
@@ -0,0 +1,142 @@
+use std::{env, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Returns a sender owning the server side of a connection and the client.
+async fn sender() -> (::io::ZeroCopySender, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = TcpStream::connect(&addr).await.unwrap();
+    let stream = listener.accept().await.unwrap().0;
+    (::io::ZeroCopySender::new(stream).unwrap(), client)
+}
+
+#[tokio::test]
+async fn send_zerocopy() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let (mut tx, mut client) = sender().await;
+    let reader = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let stats = ::io::send_zerocopy_with(&mut tx, data.clone(), &::io::CopyOptions::new())
+        .await
+        .unwrap();
+    drop(tx);
+    assert_eq!(stats.bytes, data.len());
+    #[cfg(target_os = "linux")]
+    {
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::ZeroCopySend);
+        // the kernel copies the data of loopback sends when it delivers them
+        assert!(stats.zerocopy_copied <= stats.write_calls);
+    }
+    assert!(reader.await.unwrap() == data);
+}
+
+#[tokio::test]
+async fn send_zerocopy_small() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let (mut tx, mut client) = sender().await;
+    let reader = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let stats = ::io::send_zerocopy_with(&mut tx, b"hello", &::io::CopyOptions::new())
+        .await
+        .unwrap();
+    assert_eq!(stats.mechanism, ::io::CopyMechanism::Buffered);
+    #[cfg(target_os = "linux")]
+    {
+        let options = ::io::CopyOptions::new().zerocopy_threshold(0);
+        let stats = ::io::send_zerocopy_with(&mut tx, b" world".to_vec(), &options)
+            .await
+            .unwrap();
+        assert_eq!(stats.mechanism, ::io::CopyMechanism::ZeroCopySend);
+        assert_eq!(stats.bytes, 6);
+    }
+    drop(tx);
+    let buf = reader.await.unwrap();
+    assert_eq!(&buf[..5], b"hello");
+}
+
+#[tokio::test]
+async fn send_zerocopy_then_copy() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let len = data.len();
+    let (mut sender, mut client) = sender().await;
+    let reader = tokio::spawn(async move {
+        let mut head = vec![0; len];
+        client.read_exact(&mut head).await.unwrap();
+        // the copy after the zero-copy send fills the socket buffers meanwhile
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let mut tail = Vec::new();
+        client.read_to_end(&mut tail).await.unwrap();
+        (head, tail)
+    });
+    ::io::send_zerocopy(&mut sender, data.clone())
+        .await
+        .unwrap();
+    let (_, mut tx) = sender.into_inner().await.unwrap().into_split();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let upload = data.clone();
+    tokio::spawn(async move {
+        let mut client = TcpStream::connect(&addr).await.unwrap();
+        client.write_all(&upload).await.unwrap();
+        client.shutdown().await.unwrap();
+    });
+    let (mut rx, _) = listener.accept().await.unwrap().0.into_split();
+    let stats = ::io::copy_tcp_with(&mut rx, &mut tx, None, &::io::CopyOptions::new())
+        .await
+        .unwrap();
+    drop(tx);
+    assert_eq!(stats.bytes, len);
+    // the stream waits for write readiness again instead of retrying in a loop
+    assert!(stats.would_block < 1000, "{}", stats.would_block);
+    let (head, tail) = reader.await.unwrap();
+    assert!(head == data);
+    assert!(tail == data);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn send_zerocopy_cancelled() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let data = tokio::fs::read("long_file.txt").await.unwrap();
+    let len = data.len();
+    let (mut sender, mut client) = sender().await;
+    // the client does not read yet, so the send cannot finish
+    let send = ::io::send_zerocopy(&mut sender, data.clone());
+    assert!(tokio::time::timeout(Duration::from_millis(100), send)
+        .await
+        .is_err());
+    let reader = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    // the next send waits for the sends of the cancelled one
+    let stats = ::io::send_zerocopy_with(&mut sender, b"hello".to_vec(), &::io::CopyOptions::new())
+        .await
+        .unwrap();
+    assert_eq!(stats.bytes, 5);
+    drop(sender);
+    let buf = reader.await.unwrap();
+    assert!(buf.len() < len + 5);
+    assert_eq!(&buf[buf.len() - 5..], b"hello");
+    assert!(buf[..buf.len() - 5] == data[..buf.len() - 5]);
+}
@@ -0,0 +1,121 @@
+#![cfg(target_os = "linux")]
+
+use std::{env, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Proxies everything the client writes back to it through `copy_with`
+/// and returns the copy's stats together with the client task's result.
+async fn proxy(
+    options: ::io::CopyOptions,
+    client: impl FnOnce(tokio::net::TcpStream) -> tokio::task::JoinHandle<usize>,
+) -> (::io::CopyStats, usize) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let proxy = tokio::spawn(async move {
+        let (mut rx, mut tx) = listener.accept().await.unwrap().0.into_split();
+        let stats = ::io::copy_with(&mut rx, &mut tx, None, &options)
+            .await
+            .unwrap();
+        tx.shutdown().await.unwrap();
+        stats
+    });
+    let client = client(tokio::net::TcpStream::connect(&addr).await.unwrap());
+    let stats = proxy.await.unwrap();
+    (stats, client.await.unwrap())
+}
+
+#[tokio::test]
+async fn splice_config_pipe_size() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let max = ::io::SpliceConfig::max_pipe_size();
+    let size = (256 * 1024).min(max);
+    let config = ::io::SpliceConfig::new().pipe_size(size);
+    let (stats, _) = proxy(
+        ::io::CopyOptions::new().splice_config(config),
+        |mut client| {
+            tokio::spawn(async move {
+                client.write_all(b"hello").await.unwrap();
+                client.shutdown().await.unwrap();
+                0
+            })
+        },
+    )
+    .await;
+    assert_eq!(stats.bytes, 5);
+    assert_eq!(stats.pipe_size, Some(size));
+
+    // sizes above the limit are clamped
+    let config = ::io::SpliceConfig::new().pipe_size(usize::MAX);
+    let (stats, _) = proxy(
+        ::io::CopyOptions::new().splice_config(config),
+        |mut client| {
+            tokio::spawn(async move {
+                client.shutdown().await.unwrap();
+                0
+            })
+        },
+    )
+    .await;
+    assert_eq!(stats.pipe_size, Some(max));
+}
+
+#[tokio::test]
+async fn splice_config_adaptive_grow() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let config = ::io::SpliceConfig::new()
+        .pipe_size(16 * 1024)
+        .adaptive(16 * 1024, 1024 * 1024);
+    let (stats, _) = proxy(::io::CopyOptions::new().splice_config(config), |stream| {
+        let (mut rx, mut tx) = stream.into_split();
+        tokio::spawn(async move {
+            let reader = tokio::spawn(async move {
+                let mut buf = Vec::new();
+                rx.read_to_end(&mut buf).await.unwrap();
+                buf.len()
+            });
+            let data = tokio::fs::read("long_file.txt").await.unwrap();
+            tx.write_all(&data).await.unwrap();
+            tx.shutdown().await.unwrap();
+            reader.await.unwrap()
+        })
+    })
+    .await;
+    assert_eq!(stats.bytes, 20 * 1024 * 1024);
+    assert!(stats.pipe_size.unwrap() > 16 * 1024);
+}
+
+#[tokio::test]
+async fn splice_config_adaptive_shrink() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let config = ::io::SpliceConfig::new()
+        .pipe_size(256 * 1024)
+        .adaptive(16 * 1024, 256 * 1024);
+    let (stats, received) = proxy(::io::CopyOptions::new().splice_config(config), |stream| {
+        let (mut rx, mut tx) = stream.into_split();
+        tokio::spawn(async move {
+            let reader = tokio::spawn(async move {
+                let mut buf = Vec::new();
+                rx.read_to_end(&mut buf).await.unwrap();
+                buf.len()
+            });
+            for _ in 0..12 {
+                tx.write_all(b"hello").await.unwrap();
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            tx.shutdown().await.unwrap();
+            reader.await.unwrap()
+        })
+    })
+    .await;
+    assert_eq!(received, 12 * 5);
+    assert!(stats.pipe_size.unwrap() < 256 * 1024);
+}
@@ -0,0 +1,124 @@
+#![cfg(target_os = "linux")]
+
+use std::env;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// Sends the spooled body to a new unix stream and returns what was received.
+async fn replay(spool: &::io::Spool) -> Vec<u8> {
+    let (mut left, mut right) = tokio::net::UnixStream::pair().unwrap();
+    let reader = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        right.read_to_end(&mut buf).await.unwrap();
+        buf
+    });
+    let n = spool.send(&mut left).await.unwrap();
+    drop(left);
+    assert_eq!(n, spool.len());
+    reader.await.unwrap()
+}
+
+#[tokio::test]
+async fn spool_memory() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let body = tokio::fs::read("long_file.txt").await.unwrap()[..64 * 1024].to_vec();
+    let (mut source, mut writer) = tokio::net::UnixStream::pair().unwrap();
+    let upstream_body = body.clone();
+    tokio::spawn(async move {
+        writer.write_all(&upstream_body).await.unwrap();
+        // more than the body
+        writer.write_all(b"next request").await.unwrap();
+    });
+    let spool = ::io::Spool::receive(&mut source, Some(body.len()), &::io::SpoolConfig::new())
+        .await
+        .unwrap();
+    assert!(spool.is_in_memory());
+    assert_eq!(spool.len(), body.len());
+    for _ in 0..3 {
+        assert!(replay(&spool).await == body);
+    }
+    let mut rest = [0; 12];
+    source.read_exact(&mut rest).await.unwrap();
+    assert_eq!(&rest, b"next request");
+}
+
+#[tokio::test]
+async fn spool_disk() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let body = tokio::fs::read("long_file.txt").await.unwrap()[..4 * 1024 * 1024].to_vec();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let upstream_body = body.clone();
+    tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+        client.write_all(&upstream_body).await.unwrap();
+    });
+    let (mut rx, _tx) = tokio::net::TcpStream::connect(&addr)
+        .await
+        .unwrap()
+        .into_split();
+    // the body is bigger than the memory limit and spills to disk while it is received
+    let config = ::io::SpoolConfig::new().memory_limit(256 * 1024);
+    let (spool, stats) =
+        ::io::Spool::receive_with(&mut rx, None, &config, &::io::CopyOptions::new())
+            .await
+            .unwrap();
+    assert!(!spool.is_in_memory());
+    assert_eq!(stats.bytes, body.len());
+    assert_eq!(spool.len(), body.len());
+    let replays = futures_util::future::join_all([replay(&spool), replay(&spool.clone())]).await;
+    for buf in replays {
+        assert!(buf == body);
+    }
+
+    // an empty body
+    let (mut source, writer) = tokio::net::UnixStream::pair().unwrap();
+    drop(writer);
+    let spool = ::io::Spool::receive(&mut source, None, &config)
+        .await
+        .unwrap();
+    assert!(spool.is_empty());
+    assert!(spool.is_in_memory());
+    assert!(replay(&spool).await.is_empty());
+}
+
+#[tokio::test]
+async fn spool_limits() {
+    env::set_var("RUST_LOG", "debug");
+    env::set_var("APP_ENV", "d");
+    essentials::install();
+    let config = ::io::SpoolConfig::new()
+        .memory_limit(1024)
+        .disk_limit(64 * 1024);
+    let (mut source, mut writer) = tokio::net::UnixStream::pair().unwrap();
+    let err = ::io::Spool::receive(&mut source, Some(64 * 1024 + 1), &config)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::FileTooLarge);
+    assert_eq!(err.transferred(), 0);
+
+    writer.write_all(&[1; 64 * 1024]).await.unwrap();
+    writer.write_all(b"too large").await.unwrap();
+    drop(writer);
+    let err = ::io::Spool::receive(&mut source, None, &config)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::FileTooLarge);
+    assert_eq!(err.phase(), ::io::CopyPhase::SpliceIn);
+
+    // the source ends before the whole length was received
+    let (mut source, mut writer) = tokio::net::UnixStream::pair().unwrap();
+    writer.write_all(b"short").await.unwrap();
+    drop(writer);
+    let err = ::io::Spool::receive(&mut source, Some(10), &config)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert_eq!(err.transferred(), 5);
+}